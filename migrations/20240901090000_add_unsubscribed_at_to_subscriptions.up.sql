@@ -0,0 +1,3 @@
+-- Add up migration script here
+ALTER TABLE subscriptions
+    ADD COLUMN unsubscribed_at timestamptz NULL;
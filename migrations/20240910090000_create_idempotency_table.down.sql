@@ -0,0 +1,3 @@
+-- Add down migration script here
+DROP TABLE IF EXISTS idempotency;
+DROP TYPE IF EXISTS header_pair;
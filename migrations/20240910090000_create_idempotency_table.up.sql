@@ -0,0 +1,18 @@
+-- Add up migration script here
+CREATE TYPE header_pair AS
+(
+    name  TEXT,
+    value BYTEA
+);
+
+CREATE TABLE idempotency
+(
+    user_id              uuid          NOT NULL REFERENCES users (user_id),
+    idempotency_key      TEXT          NOT NULL,
+    -- The response columns stay NULL while the first request is still being processed.
+    response_status_code SMALLINT      NULL,
+    response_headers     header_pair[] NULL,
+    response_body        BYTEA         NULL,
+    created_at           timestamptz   NOT NULL,
+    PRIMARY KEY (user_id, idempotency_key)
+);
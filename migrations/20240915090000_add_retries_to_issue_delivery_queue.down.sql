@@ -0,0 +1,4 @@
+-- Add down migration script here
+ALTER TABLE issue_delivery_queue
+    DROP COLUMN execute_after,
+    DROP COLUMN n_retries;
@@ -0,0 +1,4 @@
+-- Add up migration script here
+ALTER TABLE issue_delivery_queue
+    ADD COLUMN n_retries     SMALLINT    NOT NULL DEFAULT 0,
+    ADD COLUMN execute_after timestamptz NOT NULL DEFAULT now();
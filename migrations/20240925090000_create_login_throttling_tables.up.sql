@@ -0,0 +1,24 @@
+-- Add up migration script here
+CREATE TABLE failed_login_attempts
+(
+    username     TEXT        NOT NULL,
+    client_ip    TEXT        NOT NULL,
+    attempted_at timestamptz NOT NULL
+);
+CREATE INDEX failed_login_attempts_username_idx ON failed_login_attempts (username, attempted_at);
+CREATE INDEX failed_login_attempts_client_ip_idx ON failed_login_attempts (client_ip, attempted_at);
+
+-- Rows are never deleted: this is also the audit log of lockouts.
+CREATE TABLE login_lockouts
+(
+    lockout_id   uuid        NOT NULL PRIMARY KEY,
+    -- Either 'username' or 'client_ip'
+    subject_kind TEXT        NOT NULL,
+    subject      TEXT        NOT NULL,
+    n_failures   BIGINT      NOT NULL,
+    locked_at    timestamptz NOT NULL,
+    locked_until timestamptz NOT NULL,
+    cleared_at   timestamptz NULL,
+    cleared_by   uuid        NULL REFERENCES users (user_id)
+);
+CREATE INDEX login_lockouts_subject_idx ON login_lockouts (subject_kind, subject, locked_until);
@@ -0,0 +1,4 @@
+-- Add down migration script here
+ALTER TABLE subscription_tokens
+    DROP COLUMN consumed_at,
+    DROP COLUMN created_at;
@@ -0,0 +1,5 @@
+-- Add down migration script here
+DROP TABLE IF EXISTS email_change_requests;
+
+ALTER TABLE subscriptions
+    DROP COLUMN paused_until;
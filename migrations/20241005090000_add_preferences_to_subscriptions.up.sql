@@ -0,0 +1,13 @@
+-- Add up migration script here
+ALTER TABLE subscriptions
+    ADD COLUMN paused_until timestamptz NULL;
+
+CREATE TABLE email_change_requests
+(
+    email_change_token TEXT        NOT NULL,
+    subscriber_id      UUID        NOT NULL REFERENCES subscriptions (id),
+    new_email          TEXT        NOT NULL,
+    created_at         timestamptz NOT NULL DEFAULT now(),
+    consumed_at        timestamptz NULL,
+    PRIMARY KEY (email_change_token)
+);
@@ -0,0 +1,7 @@
+-- Add down migration script here
+ALTER TABLE newsletter_issues
+    DROP COLUMN list_id;
+ALTER TABLE subscription_tokens
+    DROP COLUMN list_id;
+DROP TABLE IF EXISTS list_memberships;
+DROP TABLE IF EXISTS lists;
@@ -0,0 +1,42 @@
+-- Add up migration script here
+CREATE TABLE lists
+(
+    list_id    uuid        NOT NULL,
+    slug       TEXT        NOT NULL UNIQUE,
+    name       TEXT        NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY (list_id)
+);
+
+-- The one list everybody implicitly subscribed to so far.
+-- Subscriptions and issues which don't name a list keep going there.
+INSERT INTO lists (list_id, slug, name)
+VALUES (gen_random_uuid(), 'newsletter', 'Newsletter');
+
+CREATE TABLE list_memberships
+(
+    list_id       uuid        NOT NULL REFERENCES lists (list_id),
+    subscriber_id uuid        NOT NULL REFERENCES subscriptions (id),
+    status        TEXT        NOT NULL,
+    created_at    timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY (list_id, subscriber_id)
+);
+
+INSERT INTO list_memberships (list_id, subscriber_id, status)
+SELECT lists.list_id, subscriptions.id, subscriptions.status
+FROM subscriptions, lists
+WHERE lists.slug = 'newsletter';
+
+ALTER TABLE subscription_tokens
+    ADD COLUMN list_id uuid NULL REFERENCES lists (list_id);
+UPDATE subscription_tokens
+SET list_id = (SELECT list_id FROM lists WHERE slug = 'newsletter');
+ALTER TABLE subscription_tokens
+    ALTER COLUMN list_id SET NOT NULL;
+
+ALTER TABLE newsletter_issues
+    ADD COLUMN list_id uuid NULL REFERENCES lists (list_id);
+UPDATE newsletter_issues
+SET list_id = (SELECT list_id FROM lists WHERE slug = 'newsletter');
+ALTER TABLE newsletter_issues
+    ALTER COLUMN list_id SET NOT NULL;
@@ -0,0 +1,5 @@
+-- Add down migration script here
+ALTER TABLE newsletter_issues
+    DROP COLUMN segment;
+DROP TABLE IF EXISTS subscriber_tags;
+DROP TABLE IF EXISTS tags;
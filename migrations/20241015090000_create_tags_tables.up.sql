@@ -0,0 +1,22 @@
+-- Add up migration script here
+CREATE TABLE tags
+(
+    tag_id     uuid        NOT NULL,
+    name       TEXT        NOT NULL UNIQUE,
+    created_at timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY (tag_id)
+);
+
+CREATE TABLE subscriber_tags
+(
+    subscriber_id uuid        NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+    tag_id        uuid        NOT NULL REFERENCES tags (tag_id) ON DELETE CASCADE,
+    tagged_at     timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY (subscriber_id, tag_id)
+);
+-- Segment filters look subscribers up by tag.
+CREATE INDEX subscriber_tags_tag_id_idx ON subscriber_tags (tag_id);
+
+-- The segment expression an issue was targeted at, if any.
+ALTER TABLE newsletter_issues
+    ADD COLUMN segment TEXT NULL;
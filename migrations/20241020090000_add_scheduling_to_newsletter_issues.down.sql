@@ -0,0 +1,11 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS newsletter_issues_scheduled_idx;
+UPDATE newsletter_issues
+SET published_at = coalesce(send_at, now())
+WHERE published_at IS NULL;
+ALTER TABLE newsletter_issues
+    ALTER COLUMN published_at SET NOT NULL;
+ALTER TABLE newsletter_issues
+    DROP COLUMN send_at;
+ALTER TABLE newsletter_issues
+    DROP COLUMN status;
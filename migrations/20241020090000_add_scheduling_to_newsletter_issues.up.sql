@@ -0,0 +1,15 @@
+-- Add up migration script here
+-- 'scheduled', 'published' or 'cancelled'.
+ALTER TABLE newsletter_issues
+    ADD COLUMN status TEXT NOT NULL DEFAULT 'published';
+ALTER TABLE newsletter_issues
+    ALTER COLUMN status DROP DEFAULT;
+ALTER TABLE newsletter_issues
+    ADD COLUMN send_at timestamptz NULL;
+-- Scheduled issues are only published once they are due.
+ALTER TABLE newsletter_issues
+    ALTER COLUMN published_at DROP NOT NULL;
+
+CREATE INDEX newsletter_issues_scheduled_idx
+    ON newsletter_issues (send_at)
+    WHERE status = 'scheduled';
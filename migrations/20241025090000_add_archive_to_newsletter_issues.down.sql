@@ -0,0 +1,6 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS newsletter_issues_archive_idx;
+ALTER TABLE newsletter_issues
+    DROP COLUMN slug;
+ALTER TABLE newsletter_issues
+    DROP COLUMN private;
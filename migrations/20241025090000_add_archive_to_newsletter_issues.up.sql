@@ -0,0 +1,30 @@
+-- Add up migration script here
+-- Private issues are left out of the public archive.
+-- Issues sent before the archive existed weren't written for it: they stay private.
+ALTER TABLE newsletter_issues
+    ADD COLUMN private BOOLEAN NOT NULL DEFAULT true;
+ALTER TABLE newsletter_issues
+    ALTER COLUMN private SET DEFAULT false;
+-- The title, made URL-friendly, followed by the start of the issue id to keep it unique.
+ALTER TABLE newsletter_issues
+    ADD COLUMN slug TEXT NULL;
+UPDATE newsletter_issues
+SET slug = concat_ws(
+    '-',
+    nullif(
+        trim(BOTH '-' FROM left(
+            trim(BOTH '-' FROM regexp_replace(lower(title), '[^a-z0-9]+', '-', 'g')),
+            64
+        )),
+        ''
+    ),
+    left(newsletter_issue_id::text, 8)
+);
+ALTER TABLE newsletter_issues
+    ALTER COLUMN slug SET NOT NULL;
+ALTER TABLE newsletter_issues
+    ADD CONSTRAINT newsletter_issues_slug_key UNIQUE (slug);
+
+CREATE INDEX newsletter_issues_archive_idx
+    ON newsletter_issues (published_at DESC)
+    WHERE status = 'published' AND NOT private;
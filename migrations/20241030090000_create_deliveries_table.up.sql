@@ -0,0 +1,19 @@
+-- Add up migration script here
+-- One row per recipient of an issue, outliving the delivery queue.
+CREATE TABLE deliveries
+(
+    newsletter_issue_id uuid        NOT NULL REFERENCES newsletter_issues (newsletter_issue_id),
+    subscriber_id       uuid        NOT NULL REFERENCES subscriptions (id),
+    -- 'queued', 'sent', 'failed', 'bounced' or 'skipped'.
+    status              TEXT        NOT NULL,
+    n_attempts          SMALLINT    NOT NULL DEFAULT 0,
+    last_error          TEXT        NULL,
+    -- The id the email provider assigned to the message, once sent.
+    message_id          TEXT        NULL,
+    updated_at          timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY (newsletter_issue_id, subscriber_id)
+);
+
+INSERT INTO deliveries (newsletter_issue_id, subscriber_id, status, n_attempts)
+SELECT newsletter_issue_id, subscriber_id, 'queued', n_retries
+FROM issue_delivery_queue;
@@ -0,0 +1,12 @@
+-- Add up migration script here
+-- Rows are never deleted: this is the log the confirmation email rate limit counts.
+CREATE TABLE confirmation_emails
+(
+    subscriber_id uuid        NOT NULL REFERENCES subscriptions (id),
+    sent_at       timestamptz NOT NULL DEFAULT now()
+);
+CREATE INDEX confirmation_emails_subscriber_id_idx ON confirmation_emails (subscriber_id, sent_at);
+
+INSERT INTO confirmation_emails (subscriber_id, sent_at)
+SELECT subscriber_id, created_at
+FROM subscription_tokens;
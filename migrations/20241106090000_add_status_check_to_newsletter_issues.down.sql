@@ -0,0 +1,3 @@
+-- Add down migration script here
+ALTER TABLE newsletter_issues
+    DROP CONSTRAINT newsletter_issues_status_check;
@@ -0,0 +1,5 @@
+-- Add up migration script here
+-- The statuses an issue goes through, drafts included.
+ALTER TABLE newsletter_issues
+    ADD CONSTRAINT newsletter_issues_status_check
+    CHECK (status IN ('draft', 'scheduled', 'published', 'cancelled'));
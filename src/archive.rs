@@ -0,0 +1,69 @@
+//! Published issues are public, unless marked as private.
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+
+use crate::template::{parse_template, TemplateContext};
+
+pub struct ArchivedIssue {
+    pub slug: String,
+    pub title: String,
+    pub html_content: String,
+    pub published_at: DateTime<Utc>,
+}
+
+impl ArchivedIssue {
+    /// Render the HTML body for the web, where there is no recipient to personalise it for.
+    ///
+    /// Bodies sent as raw HTML are trusted in an inbox, not on our own pages:
+    /// scripts and the like are stripped before they reach the archive and the feeds.
+    pub fn render_html(&self) -> String {
+        let context = TemplateContext {
+            subscriber_name: "reader",
+            subscriber_email: "",
+            issue_title: &self.title,
+            unsubscribe_url: "#",
+            preferences_url: "#",
+        };
+        ammonia::clean(&parse_template(&self.html_content).render_html(&context))
+    }
+}
+
+/// Return archived issues, the most recently published first.
+pub async fn get_archived_issues(
+    pool: &PgPool,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<ArchivedIssue>, sqlx::Error> {
+    sqlx::query_as!(
+        ArchivedIssue,
+        r#"
+        SELECT slug, title, html_content, published_at as "published_at!"
+        FROM newsletter_issues
+        WHERE status = 'published' AND NOT private
+        ORDER BY published_at DESC, newsletter_issue_id
+        LIMIT $1
+        OFFSET $2
+        "#,
+        limit,
+        offset
+    )
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn get_archived_issue(
+    pool: &PgPool,
+    slug: &str,
+) -> Result<Option<ArchivedIssue>, sqlx::Error> {
+    sqlx::query_as!(
+        ArchivedIssue,
+        r#"
+        SELECT slug, title, html_content, published_at as "published_at!"
+        FROM newsletter_issues
+        WHERE slug = $1 AND status = 'published' AND NOT private
+        "#,
+        slug
+    )
+    .fetch_optional(pool)
+    .await
+}
@@ -0,0 +1,13 @@
+pub use middleware::{reject_anonymous_users, UserId};
+pub use password::{
+    change_password, validate_credentials, AuthError, Credentials, PasswordHashing,
+};
+pub use password_policy::{PasswordPolicy, PasswordPolicyViolation};
+pub use throttling::{
+    clear_lockout, client_ip, get_active_lockouts, validate_credentials_with_throttling, Lockout,
+};
+
+mod middleware;
+mod password;
+mod password_policy;
+mod throttling;
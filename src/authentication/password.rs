@@ -0,0 +1,241 @@
+use anyhow::Context;
+use argon2::password_hash::SaltString;
+use argon2::{Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version};
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use tracing::instrument;
+use uuid::Uuid;
+
+use crate::telemetry::spawn_blocking_with_tracing;
+
+#[derive(thiserror::Error, Debug)]
+pub enum AuthError {
+    #[error("Invalid credentials.")]
+    InvalidCredentials(#[source] anyhow::Error),
+    #[error("Too many failed attempts.")]
+    TooManyAttempts { retry_after: std::time::Duration },
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+pub struct Credentials {
+    pub username: String,
+    pub password: Secret<String>,
+}
+
+/// The Argon2 parameters new hashes are computed with,
+/// along with a hash of a random password in the same format.
+///
+/// Credentials with an unknown username are verified against the latter,
+/// so that they take as long to reject as a wrong password for a known username.
+/// Otherwise, response times would tell which usernames exist.
+pub struct PasswordHashing {
+    params: Params,
+    dummy_hash: Secret<String>,
+}
+
+impl PasswordHashing {
+    pub fn new(params: Params) -> Result<Self, anyhow::Error> {
+        let random_password = Secret::new(Uuid::new_v4().to_string());
+        let dummy_hash = compute_password_hash(random_password, params.clone())
+            .context("Failed to compute the dummy password hash")?;
+        Ok(Self { params, dummy_hash })
+    }
+}
+
+/// Check `credentials` against the stored password hash.
+///
+/// Hashes computed with anything but the currently configured parameters
+/// are replaced with a fresh hash on success.
+#[instrument(
+    name = "Validate credentials",
+    skip(credentials, password_hashing, pool)
+)]
+pub async fn validate_credentials(
+    credentials: Credentials,
+    password_hashing: &PasswordHashing,
+    pool: &PgPool,
+) -> Result<Uuid, AuthError> {
+    let mut user_id = None;
+    let mut expected_password_hash = password_hashing.dummy_hash.clone();
+    if let Some((stored_user_id, stored_password_hash)) =
+        get_stored_credentials(&credentials.username, pool).await?
+    {
+        user_id = Some(stored_user_id);
+        expected_password_hash = stored_password_hash;
+    }
+
+    let password = credentials.password.clone();
+    let current_params = password_hashing.params.clone();
+    let is_outdated = spawn_blocking_with_tracing(move || {
+        verify_password_hash(expected_password_hash, password, &current_params)
+    })
+    .await
+    .context("Failed to spawn blocking task.")??;
+
+    // This is only set to `Some` if we found credentials in the store.
+    // So, even if the random password matched the dummy hash,
+    // we would never authenticate a non-existing user.
+    let user_id = user_id
+        .ok_or_else(|| AuthError::InvalidCredentials(anyhow::anyhow!("Unknown username.")))?;
+
+    if is_outdated {
+        // The user is in already: failing to upgrade the hash is not a reason to lock them out.
+        if let Err(e) = change_password(user_id, credentials.password, password_hashing, pool).await
+        {
+            tracing::warn!(
+                error.cause_chain = ?e,
+                "Failed to upgrade an outdated password hash."
+            );
+        }
+    }
+
+    Ok(user_id)
+}
+
+/// Return whether the hash was computed with parameters other than `params`.
+#[instrument(
+    name = "Verify password hash",
+    skip(expected_password_hash, password_candidate, params)
+)]
+fn verify_password_hash(
+    expected_password_hash: Secret<String>,
+    password_candidate: Secret<String>,
+    params: &Params,
+) -> Result<bool, AuthError> {
+    let expected_password_hash = PasswordHash::new(expected_password_hash.expose_secret())
+        .map_err(|e| anyhow::anyhow!(e))
+        .context("Failed to parse the stored password hash.")?;
+
+    // The algorithm, version and parameters are read from the PHC string,
+    // so any Argon2 instance can verify any stored hash.
+    Argon2::default()
+        .verify_password(
+            password_candidate.expose_secret().as_bytes(),
+            &expected_password_hash,
+        )
+        .map_err(|e| anyhow::anyhow!(e))
+        .context("Invalid password.")
+        .map_err(AuthError::InvalidCredentials)?;
+
+    Ok(!uses_params(&expected_password_hash, params))
+}
+
+fn uses_params(password_hash: &PasswordHash, params: &Params) -> bool {
+    let Ok(stored_params) = Params::try_from(password_hash) else {
+        return false;
+    };
+    password_hash.algorithm == Algorithm::Argon2id.ident()
+        && password_hash.version == Some(Version::V0x13.into())
+        && stored_params.m_cost() == params.m_cost()
+        && stored_params.t_cost() == params.t_cost()
+        && stored_params.p_cost() == params.p_cost()
+}
+
+/// Replace the user's password hash with a freshly salted hash of `password`.
+#[instrument(name = "Change password", skip(password, password_hashing, pool))]
+pub async fn change_password(
+    user_id: Uuid,
+    password: Secret<String>,
+    password_hashing: &PasswordHashing,
+    pool: &PgPool,
+) -> Result<(), anyhow::Error> {
+    let params = password_hashing.params.clone();
+    let password_hash =
+        spawn_blocking_with_tracing(move || compute_password_hash(password, params))
+            .await?
+            .context("Failed to hash password")?;
+    sqlx::query!(
+        r#"
+        UPDATE users
+        SET password_hash = $1
+        WHERE user_id = $2
+        "#,
+        password_hash.expose_secret(),
+        user_id
+    )
+    .execute(pool)
+    .await
+    .context("Failed to change user's password in the database.")?;
+    Ok(())
+}
+
+fn compute_password_hash(
+    password: Secret<String>,
+    params: Params,
+) -> Result<Secret<String>, anyhow::Error> {
+    let salt = SaltString::generate(&mut rand::thread_rng());
+    let password_hash = Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password(password.expose_secret().as_bytes(), &salt)
+        .map_err(|e| anyhow::anyhow!(e))?
+        .to_string();
+    Ok(Secret::new(password_hash))
+}
+
+async fn get_stored_credentials(
+    username: &str,
+    pool: &PgPool,
+) -> Result<Option<(Uuid, Secret<String>)>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"SELECT user_id, password_hash FROM users WHERE username = $1"#,
+        username
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to perform a query to retrieve stored credentials.")?
+    .map(|row| (row.user_id, Secret::new(row.password_hash)));
+
+    Ok(row)
+}
+
+#[cfg(test)]
+mod tests {
+    use argon2::password_hash::SaltString;
+    use argon2::{Algorithm, Argon2, Params, PasswordHash, PasswordHasher, Version};
+    use claims::{assert_err, assert_ok};
+    use secrecy::Secret;
+
+    use super::{uses_params, verify_password_hash};
+
+    fn hash(password: &str, params: Params) -> String {
+        let salt = SaltString::generate(&mut rand::thread_rng());
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password(password.as_bytes(), &salt)
+            .unwrap()
+            .to_string()
+    }
+
+    fn params(m_cost: u32) -> Params {
+        Params::new(m_cost, 2, 1, None).unwrap()
+    }
+
+    #[test]
+    fn a_hash_computed_with_the_current_params_is_up_to_date() {
+        let password_hash = hash("password", params(8192));
+        assert!(uses_params(
+            &PasswordHash::new(&password_hash).unwrap(),
+            &params(8192)
+        ));
+    }
+
+    #[test]
+    fn a_hash_computed_with_other_params_is_outdated() {
+        let password_hash = hash("password", params(4096));
+        let is_outdated = verify_password_hash(
+            Secret::new(password_hash),
+            Secret::new("password".into()),
+            &params(8192),
+        );
+        assert!(assert_ok!(is_outdated));
+    }
+
+    #[test]
+    fn a_wrong_password_is_rejected() {
+        let password_hash = hash("password", params(8192));
+        assert_err!(verify_password_hash(
+            Secret::new(password_hash),
+            Secret::new("wrong".into()),
+            &params(8192),
+        ));
+    }
+}
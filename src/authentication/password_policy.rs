@@ -0,0 +1,117 @@
+use std::collections::HashSet;
+
+use secrecy::{ExposeSecret, Secret};
+
+use crate::configuration::PasswordPolicySettings;
+
+#[derive(thiserror::Error, Debug)]
+pub enum PasswordPolicyViolation {
+    #[error("The new password must be at least {0} characters long.")]
+    TooShort(usize),
+    #[error("The new password must be at most {0} characters long.")]
+    TooLong(usize),
+    #[error("The new password has appeared in a data breach, please pick a different one.")]
+    Breached,
+}
+
+/// The rules a new password has to follow.
+pub struct PasswordPolicy {
+    min_length: usize,
+    max_length: usize,
+    /// Lowercased, so that the check is case-insensitive.
+    breached_passwords: HashSet<String>,
+}
+
+impl PasswordPolicy {
+    pub fn new(
+        min_length: usize,
+        max_length: usize,
+        breached_passwords: impl IntoIterator<Item = String>,
+    ) -> Self {
+        Self {
+            min_length,
+            max_length,
+            breached_passwords: breached_passwords
+                .into_iter()
+                .map(|p| p.to_lowercase())
+                .collect(),
+        }
+    }
+
+    /// Load the list of breached passwords from the file referenced in `settings`.
+    pub fn from_settings(settings: &PasswordPolicySettings) -> Result<Self, std::io::Error> {
+        let breached_passwords = std::fs::read_to_string(&settings.breached_passwords_path)?;
+        let breached_passwords = breached_passwords
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(str::to_owned);
+        Ok(Self::new(
+            settings.min_length,
+            settings.max_length,
+            breached_passwords,
+        ))
+    }
+
+    pub fn check(&self, password: &Secret<String>) -> Result<(), PasswordPolicyViolation> {
+        let password = password.expose_secret();
+        let length = password.chars().count();
+        if length < self.min_length {
+            return Err(PasswordPolicyViolation::TooShort(self.min_length));
+        }
+        if length > self.max_length {
+            return Err(PasswordPolicyViolation::TooLong(self.max_length));
+        }
+        if self.breached_passwords.contains(&password.to_lowercase()) {
+            return Err(PasswordPolicyViolation::Breached);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_matches, assert_ok};
+    use secrecy::Secret;
+
+    use super::{PasswordPolicy, PasswordPolicyViolation};
+
+    fn policy() -> PasswordPolicy {
+        PasswordPolicy::new(12, 20, ["password1234".to_string()])
+    }
+
+    #[test]
+    fn a_password_shorter_than_the_minimum_is_rejected() {
+        let outcome = policy().check(&Secret::new("a".repeat(11)));
+        assert_matches!(outcome, Err(PasswordPolicyViolation::TooShort(12)));
+    }
+
+    #[test]
+    fn a_password_longer_than_the_maximum_is_rejected() {
+        let outcome = policy().check(&Secret::new("a".repeat(21)));
+        assert_matches!(outcome, Err(PasswordPolicyViolation::TooLong(20)));
+    }
+
+    #[test]
+    fn length_is_measured_in_characters_not_bytes() {
+        assert_ok!(policy().check(&Secret::new("ü".repeat(12))));
+    }
+
+    #[test]
+    fn breached_passwords_are_rejected_regardless_of_case() {
+        let outcome = policy().check(&Secret::new("PassWord1234".into()));
+        assert_matches!(outcome, Err(PasswordPolicyViolation::Breached));
+    }
+
+    #[test]
+    fn the_shipped_wordlist_can_be_loaded() {
+        let settings = crate::configuration::PasswordPolicySettings {
+            min_length: 12,
+            max_length: 128,
+            breached_passwords_path: "configuration/breached_passwords.txt".into(),
+        };
+        let policy = PasswordPolicy::from_settings(&settings).unwrap();
+        let outcome = policy.check(&Secret::new("correcthorsebatterystaple".into()));
+        assert_matches!(outcome, Err(PasswordPolicyViolation::Breached));
+    }
+}
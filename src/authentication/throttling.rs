@@ -0,0 +1,363 @@
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use actix_web::HttpRequest;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{Executor, PgPool};
+use tracing::instrument;
+use uuid::Uuid;
+
+use super::{validate_credentials, AuthError, Credentials, PasswordHashing};
+use crate::configuration::LoginThrottlingSettings;
+
+/// What failed attempts are counted against.
+#[derive(Debug, Clone, Copy)]
+enum Subject {
+    Username,
+    ClientIp,
+}
+
+impl Subject {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Subject::Username => "username",
+            Subject::ClientIp => "client_ip",
+        }
+    }
+}
+
+/// The address failed attempts are attributed to.
+///
+/// Only `trusted_proxies` get to tell us who they are forwarding for, through `Forwarded`
+/// or `X-Forwarded-For`: any other client could send a new address with each attempt.
+pub fn client_ip(request: &HttpRequest, trusted_proxies: &[IpAddr]) -> String {
+    let Some(peer_ip) = request.peer_addr().map(|addr| addr.ip()) else {
+        return "unknown".into();
+    };
+    if !trusted_proxies.contains(&peer_ip) {
+        return peer_ip.to_string();
+    }
+    match request.connection_info().realip_remote_addr() {
+        // The forwarded address may come with a port, which changes from one connection to the next.
+        Some(address) => address
+            .parse::<SocketAddr>()
+            .map(|addr| addr.ip().to_string())
+            .unwrap_or_else(|_| address.to_owned()),
+        None => peer_ip.to_string(),
+    }
+}
+
+/// `validate_credentials`, refusing to even look at the password
+/// while the username or the client is locked out.
+///
+/// Each failure is recorded: once a username or a client IP has piled up too many
+/// of them within the window, it is locked out and an audit record is written.
+#[instrument(
+    name = "Validate credentials with throttling",
+    skip(credentials, password_hashing, settings, pool)
+)]
+pub async fn validate_credentials_with_throttling(
+    credentials: Credentials,
+    client_ip: &str,
+    password_hashing: &PasswordHashing,
+    settings: &LoginThrottlingSettings,
+    pool: &PgPool,
+) -> Result<Uuid, AuthError> {
+    if let Some(retry_after) = get_active_lockout(pool, &credentials.username, client_ip).await? {
+        return Err(AuthError::TooManyAttempts { retry_after });
+    }
+
+    let username = credentials.username.clone();
+    match validate_credentials(credentials, password_hashing, pool).await {
+        Ok(user_id) => {
+            forget_failures(pool, Subject::Username, &username)
+                .await
+                .context("Failed to reset failed login attempts.")?;
+            Ok(user_id)
+        }
+        Err(AuthError::InvalidCredentials(e)) => {
+            record_failure(pool, &username, client_ip, settings)
+                .await
+                .context("Failed to record a failed login attempt.")?;
+            Err(AuthError::InvalidCredentials(e))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// How long until the longest active lockout affecting this username or IP is over.
+async fn get_active_lockout(
+    pool: &PgPool,
+    username: &str,
+    client_ip: &str,
+) -> Result<Option<Duration>, anyhow::Error> {
+    let locked_until = sqlx::query_scalar!(
+        r#"
+        SELECT max(locked_until)
+        FROM login_lockouts
+        WHERE
+            cleared_at IS NULL AND
+            locked_until > now() AND
+            (
+                (subject_kind = 'username' AND subject = $1) OR
+                (subject_kind = 'client_ip' AND subject = $2)
+            )
+        "#,
+        username,
+        client_ip
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to look up active lockouts.")?;
+
+    Ok(locked_until.map(|locked_until| {
+        (locked_until - Utc::now())
+            .to_std()
+            .unwrap_or(Duration::ZERO)
+    }))
+}
+
+#[instrument(skip(pool, settings))]
+async fn record_failure(
+    pool: &PgPool,
+    username: &str,
+    client_ip: &str,
+    settings: &LoginThrottlingSettings,
+) -> Result<(), anyhow::Error> {
+    let window_start = Utc::now() - settings.window();
+    let mut transaction = pool.begin().await?;
+    // Attempts outside of the window don't count anymore.
+    transaction
+        .execute(sqlx::query!(
+            "DELETE FROM failed_login_attempts WHERE attempted_at < $1",
+            window_start
+        ))
+        .await?;
+    transaction
+        .execute(sqlx::query!(
+            r#"
+            INSERT INTO failed_login_attempts (username, client_ip, attempted_at)
+            VALUES ($1, $2, now())
+            "#,
+            username,
+            client_ip
+        ))
+        .await?;
+    let n_username_failures = sqlx::query_scalar!(
+        r#"SELECT count(*) as "count!" FROM failed_login_attempts WHERE username = $1"#,
+        username
+    )
+    .fetch_one(&mut *transaction)
+    .await?;
+    let n_client_ip_failures = sqlx::query_scalar!(
+        r#"SELECT count(*) as "count!" FROM failed_login_attempts WHERE client_ip = $1"#,
+        client_ip
+    )
+    .fetch_one(&mut *transaction)
+    .await?;
+    transaction.commit().await?;
+
+    if n_username_failures >= settings.max_failures_per_username {
+        lock_out(
+            pool,
+            Subject::Username,
+            username,
+            n_username_failures,
+            settings,
+        )
+        .await?;
+    }
+    if n_client_ip_failures >= settings.max_failures_per_ip {
+        lock_out(
+            pool,
+            Subject::ClientIp,
+            client_ip,
+            n_client_ip_failures,
+            settings,
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// Write the lockout (and its audit record), unless the subject is locked out already.
+async fn lock_out(
+    pool: &PgPool,
+    subject_kind: Subject,
+    subject: &str,
+    n_failures: i64,
+    settings: &LoginThrottlingSettings,
+) -> Result<(), anyhow::Error> {
+    let locked_until = Utc::now() + settings.lockout();
+    let n_inserted_rows = sqlx::query!(
+        r#"
+        INSERT INTO login_lockouts (
+            lockout_id,
+            subject_kind,
+            subject,
+            n_failures,
+            locked_at,
+            locked_until
+        )
+        SELECT $1, $2, $3, $4, now(), $5
+        WHERE NOT EXISTS (
+            SELECT 1
+            FROM login_lockouts
+            WHERE
+                subject_kind = $2 AND
+                subject = $3 AND
+                cleared_at IS NULL AND
+                locked_until > now()
+        )
+        "#,
+        Uuid::new_v4(),
+        subject_kind.as_str(),
+        subject,
+        n_failures,
+        locked_until
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+
+    if n_inserted_rows > 0 {
+        tracing::warn!(
+            subject_kind = subject_kind.as_str(),
+            subject,
+            n_failures,
+            %locked_until,
+            "Too many failed login attempts. Locking out."
+        );
+    }
+    Ok(())
+}
+
+async fn forget_failures(
+    pool: &PgPool,
+    subject_kind: Subject,
+    subject: &str,
+) -> Result<(), sqlx::Error> {
+    match subject_kind {
+        Subject::Username => {
+            sqlx::query!(
+                "DELETE FROM failed_login_attempts WHERE username = $1",
+                subject
+            )
+            .execute(pool)
+            .await?
+        }
+        Subject::ClientIp => {
+            sqlx::query!(
+                "DELETE FROM failed_login_attempts WHERE client_ip = $1",
+                subject
+            )
+            .execute(pool)
+            .await?
+        }
+    };
+    Ok(())
+}
+
+pub struct Lockout {
+    pub lockout_id: Uuid,
+    pub subject_kind: String,
+    pub subject: String,
+    pub n_failures: i64,
+    pub locked_at: DateTime<Utc>,
+    pub locked_until: DateTime<Utc>,
+}
+
+#[instrument(skip(pool))]
+pub async fn get_active_lockouts(pool: &PgPool) -> Result<Vec<Lockout>, anyhow::Error> {
+    let lockouts = sqlx::query_as!(
+        Lockout,
+        r#"
+        SELECT lockout_id, subject_kind, subject, n_failures, locked_at, locked_until
+        FROM login_lockouts
+        WHERE cleared_at IS NULL AND locked_until > now()
+        ORDER BY locked_at DESC
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve active lockouts.")?;
+    Ok(lockouts)
+}
+
+/// Lift a lockout ahead of time, recording which admin did it.
+///
+/// The failures that led to it are forgotten too,
+/// otherwise the next failed attempt would lock the subject out again.
+/// Return `false` if there is no such lockout, or if it was cleared already.
+#[instrument(skip(pool))]
+pub async fn clear_lockout(
+    pool: &PgPool,
+    lockout_id: Uuid,
+    cleared_by: Uuid,
+) -> Result<bool, anyhow::Error> {
+    let cleared = sqlx::query!(
+        r#"
+        UPDATE login_lockouts
+        SET cleared_at = now(), cleared_by = $2
+        WHERE lockout_id = $1 AND cleared_at IS NULL
+        RETURNING subject_kind, subject
+        "#,
+        lockout_id,
+        cleared_by
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to clear the lockout.")?;
+
+    let Some(cleared) = cleared else {
+        return Ok(false);
+    };
+    let subject_kind = match cleared.subject_kind.as_str() {
+        "username" => Subject::Username,
+        "client_ip" => Subject::ClientIp,
+        other => anyhow::bail!("Unknown lockout subject kind: {}", other),
+    };
+    forget_failures(pool, subject_kind, &cleared.subject)
+        .await
+        .context("Failed to forget failed login attempts.")?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, SocketAddr};
+
+    use actix_web::test::TestRequest;
+
+    use super::client_ip;
+
+    fn request_from(peer: &str) -> TestRequest {
+        TestRequest::default()
+            .peer_addr(peer.parse::<SocketAddr>().unwrap())
+            .insert_header(("X-Forwarded-For", "203.0.113.7"))
+    }
+
+    #[test]
+    fn forwarded_addresses_are_ignored_unless_sent_by_a_trusted_proxy() {
+        let request = request_from("198.51.100.1:4321").to_http_request();
+        assert_eq!(client_ip(&request, &[]), "198.51.100.1");
+    }
+
+    #[test]
+    fn trusted_proxies_tell_us_the_client_address() {
+        let proxy: IpAddr = "10.0.0.1".parse().unwrap();
+        let request = request_from("10.0.0.1:4321").to_http_request();
+        assert_eq!(client_ip(&request, &[proxy]), "203.0.113.7");
+    }
+
+    #[test]
+    fn ports_are_left_out_of_forwarded_addresses() {
+        let proxy: IpAddr = "10.0.0.1".parse().unwrap();
+        let request = TestRequest::default()
+            .peer_addr("10.0.0.1:4321".parse().unwrap())
+            .insert_header(("Forwarded", r#"for="203.0.113.7:5678""#))
+            .to_http_request();
+        assert_eq!(client_ip(&request, &[proxy]), "203.0.113.7");
+    }
+}
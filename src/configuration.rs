@@ -1,3 +1,6 @@
+use std::net::IpAddr;
+use std::sync::Arc;
+
 use secrecy::{ExposeSecret, Secret};
 use serde::Deserialize;
 use sqlx::postgres::{PgConnectOptions, PgSslMode};
@@ -5,6 +8,9 @@ use sqlx::ConnectOptions;
 use tracing::log::LevelFilter;
 
 use crate::domain::SubscriberEmail;
+use crate::email_client::{
+    EmailSender, FileEmailClient, PostmarkEmailClient, RetryPolicy, SmtpEmailClient,
+};
 
 pub fn get_configuration() -> Result<Settings, config::ConfigError> {
     let base_path = std::env::current_dir().expect("Failed to determine the current directory");
@@ -42,6 +48,12 @@ pub struct Settings {
     pub database: DatabaseSettings,
     pub application: ApplicationSettings,
     pub email_client: EmailClientSettings,
+    pub idempotency: IdempotencySettings,
+    pub session: SessionSettings,
+    pub password_policy: PasswordPolicySettings,
+    pub password_hashing: PasswordHashingSettings,
+    pub login_throttling: LoginThrottlingSettings,
+    pub subscriptions: SubscriptionSettings,
 }
 
 #[derive(Deserialize, Clone)]
@@ -81,17 +93,91 @@ pub struct ApplicationSettings {
     pub port: u16,
     pub host: String,
     pub base_url: String,
+    pub hmac_secret: Secret<String>,
+    /// How many background workers deliver newsletter issues.
+    /// `0` disables background delivery altogether, which is what the test suite relies on.
+    pub delivery_workers: usize,
+    /// Whether to release scheduled issues into delivery once they are due.
+    /// The test suite releases them explicitly instead.
+    pub issue_scheduler: bool,
 }
 
 #[derive(Deserialize, Clone)]
 pub struct EmailClientSettings {
-    pub base_url: String,
+    pub provider: EmailProvider,
     pub sender_email: String,
-    pub authorization_token: Secret<String>,
     pub timeout_milliseconds: u64,
+    // Postmark
+    pub base_url: String,
+    pub authorization_token: Secret<String>,
+    pub retry: EmailRetrySettings,
+    // Only required when the matching provider is selected
+    pub smtp: Option<SmtpSettings>,
+    pub file: Option<FileSettings>,
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum EmailProvider {
+    Postmark,
+    Smtp,
+    File,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct SmtpSettings {
+    pub host: String,
+    pub port: u16,
+    pub username: String,
+    pub password: Secret<String>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct FileSettings {
+    /// Where `.eml` files are written to
+    pub directory: String,
 }
 
 impl EmailClientSettings {
+    pub fn client(self) -> Arc<dyn EmailSender> {
+        let sender_email = self.sender().expect("Invalid sender email address.");
+        let timeout = self.timeout();
+        match self.provider {
+            EmailProvider::Postmark => Arc::new(PostmarkEmailClient::new(
+                self.base_url,
+                sender_email,
+                self.authorization_token,
+                timeout,
+                self.retry.policy(),
+            )),
+            EmailProvider::Smtp => {
+                let smtp = self
+                    .smtp
+                    .expect("Missing `email_client.smtp` settings for the SMTP provider.");
+                Arc::new(
+                    SmtpEmailClient::new(
+                        &smtp.host,
+                        smtp.port,
+                        smtp.username,
+                        smtp.password,
+                        sender_email,
+                        timeout,
+                    )
+                    .expect("Failed to build the SMTP email client."),
+                )
+            }
+            EmailProvider::File => {
+                let file = self
+                    .file
+                    .expect("Missing `email_client.file` settings for the file provider.");
+                Arc::new(
+                    FileEmailClient::new(file.directory.into(), sender_email)
+                        .expect("Failed to create the directory for the file email client."),
+                )
+            }
+        }
+    }
+
     pub fn sender(&self) -> Result<SubscriberEmail, String> {
         SubscriberEmail::parse(self.sender_email.clone())
     }
@@ -101,6 +187,135 @@ impl EmailClientSettings {
     }
 }
 
+#[derive(Deserialize, Clone)]
+pub struct EmailRetrySettings {
+    /// Total number of attempts, including the first one.
+    pub max_attempts: u32,
+    pub base_delay_milliseconds: u64,
+    pub max_delay_milliseconds: u64,
+    pub jitter: bool,
+}
+
+impl EmailRetrySettings {
+    pub fn policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: self.max_attempts,
+            base_delay: std::time::Duration::from_millis(self.base_delay_milliseconds),
+            max_delay: std::time::Duration::from_millis(self.max_delay_milliseconds),
+            jitter: self.jitter,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct IdempotencySettings {
+    /// How long a saved response is replayed for a repeated idempotency key.
+    pub expiration_seconds: u64,
+    /// How often expired idempotency keys are deleted from the database.
+    pub cleanup_interval_seconds: u64,
+}
+
+impl IdempotencySettings {
+    pub fn expiration(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.expiration_seconds)
+    }
+
+    pub fn cleanup_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.cleanup_interval_seconds)
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct SubscriptionSettings {
+    /// How long a confirmation link stays valid after subscribing.
+    pub confirmation_token_expiration_seconds: u64,
+    /// How many confirmation emails an address can be sent within `confirmation_email_window_seconds`.
+    pub max_confirmation_emails_per_window: i64,
+    pub confirmation_email_window_seconds: u64,
+}
+
+impl SubscriptionSettings {
+    pub fn confirmation_token_expiration(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.confirmation_token_expiration_seconds)
+    }
+
+    pub fn confirmation_email_window(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.confirmation_email_window_seconds)
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct SessionSettings {
+    pub store: SessionStoreKind,
+    /// Only send the session cookie over HTTPS.
+    /// Turned off locally, where the application is served over plain HTTP.
+    pub cookie_secure: bool,
+    /// Signs the session cookie. At least 64 bytes long.
+    pub secret_key: Secret<String>,
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionStoreKind {
+    Postgres,
+    /// Sessions are lost on restart and are not shared across instances: meant for tests.
+    Memory,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct PasswordPolicySettings {
+    pub min_length: usize,
+    pub max_length: usize,
+    /// A file with one known-breached password per line.
+    pub breached_passwords_path: String,
+}
+
+/// Argon2id cost parameters for new password hashes.
+///
+/// Stored hashes computed with different parameters are upgraded on the next successful login.
+#[derive(Deserialize, Clone)]
+pub struct PasswordHashingSettings {
+    pub memory_size_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl PasswordHashingSettings {
+    pub fn params(&self) -> argon2::Params {
+        argon2::Params::new(
+            self.memory_size_kib,
+            self.iterations,
+            self.parallelism,
+            None,
+        )
+        .expect("Invalid Argon2 parameters.")
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct LoginThrottlingSettings {
+    /// How far back failed attempts are counted.
+    pub window_seconds: u64,
+    pub max_failures_per_username: i64,
+    /// Higher than the per-username limit: many users can share an IP address.
+    pub max_failures_per_ip: i64,
+    pub lockout_seconds: u64,
+    /// The load balancers in front of us, whose `Forwarded` and `X-Forwarded-For` headers
+    /// tell us the client's address. Nobody else's are trusted.
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpAddr>,
+}
+
+impl LoginThrottlingSettings {
+    pub fn window(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.window_seconds)
+    }
+
+    pub fn lockout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.lockout_seconds)
+    }
+}
+
 pub enum Environment {
     Local,
     Production,
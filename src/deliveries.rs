@@ -0,0 +1,113 @@
+//! Every recipient of an issue gets a row in `deliveries`, logging what became of their email.
+//!
+//! Rows are created `queued` alongside the delivery tasks, and updated by the delivery worker
+//! after each attempt: `sent`, `failed`, or `bounced` if the provider deactivated the recipient.
+//! Recipients who are no longer confirmed by the time their turn comes are `skipped`.
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+pub struct IssueSummary {
+    pub newsletter_issue_id: Uuid,
+    pub title: String,
+    pub published_at: DateTime<Utc>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct DeliveryTotals {
+    pub queued: i64,
+    pub sent: i64,
+    pub failed: i64,
+    pub bounced: i64,
+    pub skipped: i64,
+}
+
+impl DeliveryTotals {
+    pub fn total(&self) -> i64 {
+        self.queued + self.sent + self.failed + self.bounced + self.skipped
+    }
+}
+
+/// A delivery which did not make it, as listed in the failure report.
+pub struct FailedDelivery {
+    pub email: String,
+    pub status: String,
+    pub n_attempts: i16,
+    pub last_error: Option<String>,
+    pub message_id: Option<String>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Return the published issues, the most recent first.
+pub async fn get_published_issues(pool: &PgPool) -> Result<Vec<IssueSummary>, sqlx::Error> {
+    sqlx::query_as!(
+        IssueSummary,
+        r#"
+        SELECT newsletter_issue_id, title, published_at as "published_at!"
+        FROM newsletter_issues
+        WHERE status = 'published'
+        ORDER BY published_at DESC
+        "#
+    )
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn get_published_issue(
+    pool: &PgPool,
+    issue_id: Uuid,
+) -> Result<Option<IssueSummary>, sqlx::Error> {
+    sqlx::query_as!(
+        IssueSummary,
+        r#"
+        SELECT newsletter_issue_id, title, published_at as "published_at!"
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1 AND status = 'published'
+        "#,
+        issue_id
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+pub async fn get_delivery_totals(
+    pool: &PgPool,
+    issue_id: Uuid,
+) -> Result<DeliveryTotals, sqlx::Error> {
+    sqlx::query_as!(
+        DeliveryTotals,
+        r#"
+        SELECT
+            count(*) FILTER (WHERE status = 'queued') as "queued!",
+            count(*) FILTER (WHERE status = 'sent') as "sent!",
+            count(*) FILTER (WHERE status = 'failed') as "failed!",
+            count(*) FILTER (WHERE status = 'bounced') as "bounced!",
+            count(*) FILTER (WHERE status = 'skipped') as "skipped!"
+        FROM deliveries
+        WHERE newsletter_issue_id = $1
+        "#,
+        issue_id
+    )
+    .fetch_one(pool)
+    .await
+}
+
+/// Return the failed and bounced deliveries of an issue, in the order they happened.
+pub async fn get_failed_deliveries(
+    pool: &PgPool,
+    issue_id: Uuid,
+) -> Result<Vec<FailedDelivery>, sqlx::Error> {
+    sqlx::query_as!(
+        FailedDelivery,
+        r#"
+        SELECT s.email, d.status, d.n_attempts, d.last_error, d.message_id, d.updated_at
+        FROM deliveries d
+        JOIN subscriptions s ON s.id = d.subscriber_id
+        WHERE d.newsletter_issue_id = $1 AND d.status IN ('failed', 'bounced')
+        ORDER BY d.updated_at, s.email
+        "#,
+        issue_id
+    )
+    .fetch_all(pool)
+    .await
+}
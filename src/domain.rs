@@ -1,7 +1,13 @@
+pub use issue_slug::IssueSlug;
+pub use list_slug::ListSlug;
 pub use new_subscriber::NewSubscriber;
 pub use subscriber_email::SubscriberEmail;
 pub use subscriber_name::SubscriberName;
+pub use tag_name::TagName;
 
+mod issue_slug;
+mod list_slug;
 mod new_subscriber;
 mod subscriber_email;
 mod subscriber_name;
+mod tag_name;
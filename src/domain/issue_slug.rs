@@ -0,0 +1,66 @@
+use uuid::Uuid;
+
+/// The URL-friendly name of an issue in the archive, e.g. `our-spring-update-1b4e28ba`.
+///
+/// The title gives the gist of the issue, the start of its id keeps the slug unique
+/// even if titles are reused.
+#[derive(Debug)]
+pub struct IssueSlug(String);
+
+impl IssueSlug {
+    pub fn new(title: &str, issue_id: Uuid) -> Self {
+        const MAX_TITLE_LENGTH: usize = 64;
+        let mut slug = String::new();
+        for c in title.chars() {
+            if c.is_ascii_alphanumeric() {
+                slug.push(c.to_ascii_lowercase());
+            } else if !slug.is_empty() && !slug.ends_with('-') {
+                slug.push('-');
+            }
+        }
+        slug.truncate(MAX_TITLE_LENGTH);
+        let mut slug = slug.trim_end_matches('-').to_owned();
+        if !slug.is_empty() {
+            slug.push('-');
+        }
+        slug.push_str(&issue_id.simple().to_string()[..8]);
+        Self(slug)
+    }
+}
+
+impl AsRef<str> for IssueSlug {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use crate::domain::IssueSlug;
+
+    fn issue_id() -> Uuid {
+        "1b4e28ba-2fa1-11d2-883f-0016d3cca427".parse().unwrap()
+    }
+
+    #[test]
+    fn the_title_is_lowercased_and_punctuation_becomes_dashes() {
+        let slug = IssueSlug::new("  Tom & Jerry: the 2nd season! ", issue_id());
+        assert_eq!(slug.as_ref(), "tom-jerry-the-2nd-season-1b4e28ba");
+    }
+
+    #[test]
+    fn long_titles_are_truncated() {
+        let slug = IssueSlug::new(&format!("{} b", "a".repeat(63)), issue_id());
+        assert_eq!(slug.as_ref(), format!("{}-1b4e28ba", "a".repeat(63)));
+    }
+
+    #[test]
+    fn characters_other_than_ascii_letters_and_digits_are_dropped() {
+        let slug = IssueSlug::new("¡Olé!", issue_id());
+        assert_eq!(slug.as_ref(), "ol-1b4e28ba");
+        let slug = IssueSlug::new("日本語", issue_id());
+        assert_eq!(slug.as_ref(), "1b4e28ba");
+    }
+}
@@ -0,0 +1,73 @@
+/// The short, URL-friendly name subscribers refer to a list by, e.g. `weekly-digest`.
+#[derive(Debug)]
+pub struct ListSlug(String);
+
+impl ListSlug {
+    pub fn parse<T: AsRef<str>>(s: T) -> Result<Self, String> {
+        let slug = s.as_ref();
+        const MAX_SLUG_LENGTH: usize = 64;
+        let is_well_formed = slug.split('-').all(|word| {
+            !word.is_empty()
+                && word
+                    .bytes()
+                    .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit())
+        });
+
+        if slug.len() <= MAX_SLUG_LENGTH && is_well_formed {
+            Ok(Self(slug.to_string()))
+        } else {
+            Err(format!("{} is not a valid list slug.", slug))
+        }
+    }
+}
+
+impl AsRef<str> for ListSlug {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_err, assert_ok};
+
+    use crate::domain::ListSlug;
+
+    #[test]
+    fn lowercase_words_separated_by_dashes_are_valid() {
+        assert_ok!(ListSlug::parse("weekly-digest-2024"));
+    }
+    #[test]
+    fn a_64_characters_long_slug_is_valid() {
+        assert_ok!(ListSlug::parse("a".repeat(64)));
+    }
+    #[test]
+    fn a_slug_longer_than_64_characters_is_rejected() {
+        assert_err!(ListSlug::parse("a".repeat(65)));
+    }
+    #[test]
+    fn empty_string_is_rejected() {
+        assert_err!(ListSlug::parse(""));
+    }
+    #[test]
+    fn uppercase_letters_are_rejected() {
+        assert_err!(ListSlug::parse("Weekly"));
+    }
+    #[test]
+    fn leading_trailing_or_repeated_dashes_are_rejected() {
+        for slug in ["-weekly", "weekly-", "weekly--digest"] {
+            assert_err!(ListSlug::parse(slug));
+        }
+    }
+    #[test]
+    fn slugs_containing_an_invalid_character_are_rejected() {
+        for slug in [
+            "weekly digest",
+            "weekly_digest",
+            "weekly/digest",
+            "wöchentlich",
+        ] {
+            assert_err!(ListSlug::parse(slug));
+        }
+    }
+}
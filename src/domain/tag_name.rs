@@ -0,0 +1,78 @@
+/// A label attached to subscribers, e.g. `beta` or `early-adopter`.
+///
+/// Tags are referred to by name in segment expressions,
+/// so the operators of that language are not valid names.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TagName(String);
+
+impl TagName {
+    pub fn parse<T: AsRef<str>>(s: T) -> Result<Self, String> {
+        let name = s.as_ref();
+        const MAX_NAME_LENGTH: usize = 64;
+        const RESERVED_NAMES: [&str; 3] = ["and", "or", "not"];
+        let is_well_formed = name.split(['-', '_']).all(|word| {
+            !word.is_empty()
+                && word
+                    .bytes()
+                    .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit())
+        });
+
+        if name.len() <= MAX_NAME_LENGTH && is_well_formed && !RESERVED_NAMES.contains(&name) {
+            Ok(Self(name.to_string()))
+        } else {
+            Err(format!("{} is not a valid tag name.", name))
+        }
+    }
+}
+
+impl AsRef<str> for TagName {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for TagName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_err, assert_ok};
+
+    use crate::domain::TagName;
+
+    #[test]
+    fn lowercase_words_separated_by_dashes_or_underscores_are_valid() {
+        assert_ok!(TagName::parse("early-adopter_2024"));
+    }
+    #[test]
+    fn a_tag_name_longer_than_64_characters_is_rejected() {
+        assert_ok!(TagName::parse("a".repeat(64)));
+        assert_err!(TagName::parse("a".repeat(65)));
+    }
+    #[test]
+    fn empty_string_is_rejected() {
+        assert_err!(TagName::parse(""));
+    }
+    #[test]
+    fn segment_operators_are_rejected() {
+        for name in ["and", "or", "not"] {
+            assert_err!(TagName::parse(name));
+        }
+    }
+    #[test]
+    fn names_containing_an_invalid_character_are_rejected() {
+        for name in [
+            "Beta",
+            "beta tester",
+            "-beta",
+            "beta__tester",
+            "béta",
+            "(beta)",
+        ] {
+            assert_err!(TagName::parse(name));
+        }
+    }
+}
@@ -1,217 +1,166 @@
-use reqwest::Client;
-use secrecy::{ExposeSecret, Secret};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use lettre::message::header::{HeaderName, HeaderValue};
+use lettre::message::{Mailbox, MultiPart};
+use lettre::Message;
 use serde::Serialize;
-use tracing::{info, instrument};
 
 use crate::domain::SubscriberEmail;
 
-#[derive(Debug)]
-pub struct EmailClient {
-    http_client: Client,
-    base_url: String,
-    sender: SubscriberEmail,
-    authorization_token: Secret<String>,
-}
+pub use file::FileEmailClient;
+pub use postmark::{PostmarkEmailClient, RetryPolicy};
+pub use smtp::SmtpEmailClient;
 
-impl EmailClient {
-    pub fn new(
-        base_url: String,
-        sender: SubscriberEmail,
-        authorization_token: Secret<String>,
-        timeout: std::time::Duration,
-    ) -> Self {
-        let http_client = Client::builder().timeout(timeout).build().unwrap();
-
-        Self {
-            http_client,
-            base_url,
-            sender,
-            authorization_token,
-        }
-    }
+mod file;
+mod postmark;
+mod smtp;
+
+/// The interface the rest of the application uses to send emails,
+/// regardless of the provider configured in `EmailClientSettings`.
+#[async_trait]
+pub trait EmailSender: Send + Sync {
+    /// The address emails are sent from.
+    fn sender(&self) -> &SubscriberEmail;
 
-    #[instrument(skip(self))]
-    pub async fn send_email(
+    async fn send_email(
         &self,
         recipient: &SubscriberEmail,
         subject: &str,
         html_content: &str,
         text_content: &str,
-    ) -> Result<(), reqwest::Error> {
-        let url = format!("{}/email", self.base_url);
-        let request_body = SendEmailRequest {
-            from: self.sender.as_ref(),
-            to: recipient.as_ref(),
-            subject,
-            html_body: html_content,
-            text_body: text_content,
-        };
-
-        // todo: check response
-        let response = self
-            .http_client
-            .post(url)
-            .header(
-                "X-Postmark-Server-Token",
-                self.authorization_token.expose_secret(),
-            )
-            .json(&request_body)
-            .send()
-            .await?
-            .error_for_status()?;
-
-        info!(response=?response, "Sent email");
-        Ok(())
-    }
+        headers: &[EmailHeader],
+    ) -> Result<SentEmail, EmailError>;
 }
 
-#[derive(Serialize)]
-#[serde(rename_all = "PascalCase")]
-struct SendEmailRequest<'a> {
-    from: &'a str,
-    to: &'a str,
-    subject: &'a str,
-    html_body: &'a str,
-    text_body: &'a str,
+/// An email the provider accepted for delivery.
+#[derive(Debug)]
+pub struct SentEmail {
+    /// The identifier the provider assigned to the message,
+    /// which shows up in its logs, bounce and delivery webhooks.
+    pub message_id: String,
 }
 
-#[cfg(test)]
-mod tests {
-    use claims::{assert_err, assert_ok};
-    use fake::faker::internet::en::SafeEmail;
-    use fake::faker::lorem::en::{Paragraph, Sentence};
-    use fake::{Fake, Faker};
-    use secrecy::Secret;
-    use wiremock::matchers::{any, header, header_exists, method, path};
-    use wiremock::{Mock, MockServer, Request, ResponseTemplate};
-
-    use crate::domain::SubscriberEmail;
-    use crate::email_client::EmailClient;
+#[derive(thiserror::Error, Debug)]
+pub enum EmailError {
+    #[error("The recipient is inactive: {0}")]
+    InactiveRecipient(String),
+    #[error("The recipient's address is invalid: {0}")]
+    InvalidAddress(String),
+    #[error("The email provider is rate limiting us")]
+    RateLimited { retry_after: Option<Duration> },
+    #[error("The email provider is unavailable")]
+    ServerError {
+        retry_after: Option<Duration>,
+        #[source]
+        source: anyhow::Error,
+    },
+    #[error("The email provider rejected the email: {0}")]
+    Rejected(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
 
-    // Generate a random email subject
-    fn subject() -> String {
-        Sentence(1..2).fake()
+impl EmailError {
+    /// Whether sending the same email again later has a chance of succeeding.
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            EmailError::RateLimited { .. } | EmailError::ServerError { .. }
+        )
     }
 
-    // Generate a random email content
-    fn content() -> String {
-        Paragraph(1..10).fake()
+    /// How long the provider asked us to wait before trying again, if it did.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            EmailError::RateLimited { retry_after }
+            | EmailError::ServerError { retry_after, .. } => *retry_after,
+            _ => None,
+        }
     }
+}
 
-    // Generate a random subscriber email
-    fn email() -> SubscriberEmail {
-        SubscriberEmail::parse(SafeEmail().fake::<String>()).unwrap()
-    }
+/// A custom header to be set on an outgoing email, e.g. `List-Unsubscribe`.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct EmailHeader {
+    pub name: String,
+    pub value: String,
+}
 
-    // Get a test instance of `EmailClient`
-    fn email_client(base_url: &str) -> EmailClient {
-        EmailClient::new(
-            base_url.into(),
-            email(),
-            Secret::new(Faker.fake()),
-            std::time::Duration::from_millis(200),
+/// Build a `multipart/alternative` MIME message, for the backends which speak SMTP's language.
+///
+/// The message gets a freshly generated `Message-ID`, which doubles as the id we report back.
+fn build_message(
+    sender: &SubscriberEmail,
+    recipient: &SubscriberEmail,
+    subject: &str,
+    html_content: &str,
+    text_content: &str,
+    headers: &[EmailHeader],
+) -> Result<(Message, SentEmail), EmailError> {
+    let to = recipient
+        .as_ref()
+        .parse::<Mailbox>()
+        .map_err(|e| EmailError::InvalidAddress(e.to_string()))?;
+    let mut builder = Message::builder()
+        .from(
+            sender
+                .as_ref()
+                .parse::<Mailbox>()
+                .map_err(anyhow::Error::from)?,
         )
+        .to(to)
+        .subject(subject)
+        .message_id(None);
+    for header in headers {
+        let name = HeaderName::new_from_ascii(header.name.clone()).map_err(anyhow::Error::from)?;
+        builder = builder.raw_header(HeaderValue::new(name, header.value.clone()));
     }
+    let message = builder
+        .multipart(MultiPart::alternative_plain_html(
+            text_content.to_owned(),
+            html_content.to_owned(),
+        ))
+        .map_err(anyhow::Error::from)?;
+    let message_id = message
+        .headers()
+        .get_raw("Message-ID")
+        .expect("We just set the Message-ID header.")
+        .to_owned();
+    Ok((message, SentEmail { message_id }))
+}
 
-    #[tokio::test]
-    async fn send_email_sends_the_expected_request() {
-        // Arrange
-        let mock_server = MockServer::start().await;
-        let email_client = email_client(mock_server.uri().as_str());
-
-        struct SendEmailBodyMatcher;
-
-        impl wiremock::Match for SendEmailBodyMatcher {
-            fn matches(&self, request: &Request) -> bool {
-                let result: Result<serde_json::Value, _> = serde_json::from_slice(&request.body);
-
-                if let Ok(body) = result {
-                    body.get("From").is_some()
-                        && body.get("To").is_some()
-                        && body.get("Subject").is_some()
-                        && body.get("HtmlBody").is_some()
-                        && body.get("TextBody").is_some()
-                } else {
-                    false
-                }
-            }
-        }
-        Mock::given(header_exists("X-Postmark-Server-Token"))
-            .and(header("Content-Type", "application/json"))
-            .and(path("/email"))
-            .and(method("POST"))
-            .and(SendEmailBodyMatcher)
-            .respond_with(ResponseTemplate::new(200))
-            .expect(1)
-            .mount(&mock_server)
-            .await;
-
-        let subscriber_email = email();
-        let subject: String = subject();
-        let content: String = content();
-
-        // Act
-        let outcome = email_client
-            .send_email(&subscriber_email, &subject, &content, &content)
-            .await;
-
-        // Assert
-        // Mock expectations are checked on drop
-        assert_ok!(outcome);
-    }
-
-    #[tokio::test]
-    async fn send_email_fails_if_the_server_returns_500() {
-        // Arrange
-        let mock_server = MockServer::start().await;
-        let email_client = email_client(mock_server.uri().as_str());
-
-        let subscriber_email = email();
-        let subject: String = subject();
-        let content: String = Paragraph(1..10).fake();
-
-        Mock::given(any())
-            // Not a 200 anymore!
-            .respond_with(ResponseTemplate::new(500))
-            .expect(1)
-            .mount(&mock_server)
-            .await;
-
-        // Act
-        let outcome = email_client
-            .send_email(&subscriber_email, &subject, &content, &content)
-            .await;
-
-        // Assert
-        assert_err!(outcome);
-    }
+#[cfg(test)]
+mod tests {
+    use claims::assert_ok;
 
-    #[tokio::test]
-    async fn send_email_times_out_if_the_server_takes_too_long() {
-        // Arrange
-        let mock_server = MockServer::start().await;
-        let email_client = email_client(mock_server.uri().as_str());
-
-        let subscriber_email = SubscriberEmail::parse(SafeEmail().fake::<String>()).unwrap();
-        let subject: String = subject();
-        let content: String = content();
-
-        let response = ResponseTemplate::new(200)
-            // 3 minutes!
-            .set_delay(std::time::Duration::from_secs(180));
-
-        Mock::given(any())
-            .respond_with(response)
-            .expect(1)
-            .mount(&mock_server)
-            .await;
-
-        // Act
-        let outcome = email_client
-            .send_email(&subscriber_email, &subject, &content, &content)
-            .await;
-
-        // Assert
-        assert_err!(outcome);
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::{build_message, EmailHeader};
+
+    #[test]
+    fn messages_carry_both_bodies_and_custom_headers() {
+        let sender = SubscriberEmail::parse("sender@example.com").unwrap();
+        let recipient = SubscriberEmail::parse("recipient@example.com").unwrap();
+        let headers = [EmailHeader {
+            name: "List-Unsubscribe-Post".into(),
+            value: "List-Unsubscribe=One-Click".into(),
+        }];
+
+        let message = build_message(
+            &sender,
+            &recipient,
+            "Subject",
+            "<p>HTML body</p>",
+            "Text body",
+            &headers,
+        );
+
+        let (message, sent_email) = assert_ok!(message);
+        let formatted = String::from_utf8(message.formatted()).unwrap();
+        assert!(formatted.contains("List-Unsubscribe-Post: List-Unsubscribe=One-Click"));
+        assert!(formatted.contains("Content-Type: text/plain"));
+        assert!(formatted.contains("Content-Type: text/html"));
+        assert!(formatted.contains(&format!("Message-ID: {}", sent_email.message_id)));
     }
 }
@@ -0,0 +1,98 @@
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+use lettre::{AsyncFileTransport, AsyncTransport, Tokio1Executor};
+use tracing::{info, instrument};
+
+use crate::domain::SubscriberEmail;
+use crate::email_client::{build_message, EmailError, EmailHeader, EmailSender, SentEmail};
+
+/// Writes every email as an `.eml` file in a directory instead of sending it.
+///
+/// Meant for local development: the files can be opened with any mail client.
+pub struct FileEmailClient {
+    transport: AsyncFileTransport<Tokio1Executor>,
+    sender: SubscriberEmail,
+}
+
+impl FileEmailClient {
+    pub fn new(directory: PathBuf, sender: SubscriberEmail) -> Result<Self, std::io::Error> {
+        std::fs::create_dir_all(&directory)?;
+        let transport = AsyncFileTransport::<Tokio1Executor>::new(directory);
+
+        Ok(Self { transport, sender })
+    }
+}
+
+#[async_trait]
+impl EmailSender for FileEmailClient {
+    fn sender(&self) -> &SubscriberEmail {
+        &self.sender
+    }
+
+    #[instrument(skip(self))]
+    async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[EmailHeader],
+    ) -> Result<SentEmail, EmailError> {
+        let (message, sent_email) = build_message(
+            &self.sender,
+            recipient,
+            subject,
+            html_content,
+            text_content,
+            headers,
+        )?;
+        let id = self
+            .transport
+            .send(message)
+            .await
+            .map_err(anyhow::Error::from)?;
+
+        info!(id, message_id = %sent_email.message_id, "Wrote email to disk");
+        Ok(sent_email)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::assert_ok;
+
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::{EmailSender, FileEmailClient};
+
+    #[tokio::test]
+    async fn send_email_writes_an_eml_file() {
+        // Arrange
+        let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let email_client = FileEmailClient::new(
+            directory.clone(),
+            SubscriberEmail::parse("sender@example.com").unwrap(),
+        )
+        .unwrap();
+        let recipient = SubscriberEmail::parse("recipient@example.com").unwrap();
+
+        // Act
+        let outcome = email_client
+            .send_email(&recipient, "A subject", "<p>Hello</p>", "Hello", &[])
+            .await;
+
+        // Assert
+        assert_ok!(outcome);
+        let files: Vec<_> = std::fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(1, files.len());
+        assert_eq!(Some("eml"), files[0].extension().and_then(|e| e.to_str()));
+        let content = std::fs::read_to_string(&files[0]).unwrap();
+        assert!(content.contains("Subject: A subject"));
+        assert!(content.contains("To: recipient@example.com"));
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
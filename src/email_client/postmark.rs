@@ -0,0 +1,751 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chrono::Utc;
+use rand::Rng;
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::{Client, Response, StatusCode};
+use secrecy::{ExposeSecret, Secret};
+use serde::{Deserialize, Serialize};
+use tracing::{info, instrument, warn, Span};
+
+use crate::domain::SubscriberEmail;
+use crate::email_client::{EmailError, EmailHeader, EmailSender, SentEmail};
+
+/// Sends emails through Postmark's HTTP API.
+#[derive(Debug)]
+pub struct PostmarkEmailClient {
+    http_client: Client,
+    base_url: String,
+    sender: SubscriberEmail,
+    authorization_token: Secret<String>,
+    retry_policy: RetryPolicy,
+}
+
+impl PostmarkEmailClient {
+    pub fn new(
+        base_url: String,
+        sender: SubscriberEmail,
+        authorization_token: Secret<String>,
+        timeout: Duration,
+        retry_policy: RetryPolicy,
+    ) -> Self {
+        let http_client = Client::builder().timeout(timeout).build().unwrap();
+
+        Self {
+            http_client,
+            base_url,
+            sender,
+            authorization_token,
+            retry_policy,
+        }
+    }
+}
+
+#[async_trait]
+impl EmailSender for PostmarkEmailClient {
+    fn sender(&self) -> &SubscriberEmail {
+        &self.sender
+    }
+
+    /// Send an email, retrying transient failures according to the client's `RetryPolicy`.
+    ///
+    /// Timeouts, connection errors, `429 Too Many Requests` and `5xx` responses are retried,
+    /// everything else is returned to the caller straight away.
+    #[instrument(skip(self), fields(attempts = tracing::field::Empty))]
+    async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[EmailHeader],
+    ) -> Result<SentEmail, EmailError> {
+        let url = format!("{}/email", self.base_url);
+        let request_body = SendEmailRequest {
+            from: self.sender.as_ref(),
+            to: recipient.as_ref(),
+            subject,
+            html_body: html_content,
+            text_body: text_content,
+            headers,
+        };
+
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            Span::current().record("attempts", attempt);
+
+            let outcome = self
+                .http_client
+                .post(&url)
+                .header(
+                    "X-Postmark-Server-Token",
+                    self.authorization_token.expose_secret(),
+                )
+                .json(&request_body)
+                .send()
+                .await;
+            let error = match outcome {
+                Ok(response) => match parse_response(response).await {
+                    Ok(sent_email) => {
+                        info!(message_id = %sent_email.message_id, "Sent email");
+                        return Ok(sent_email);
+                    }
+                    Err(error) => error,
+                },
+                Err(error) => classify_transport_error(error),
+            };
+
+            if !error.is_transient() || attempt >= self.retry_policy.max_attempts {
+                return Err(error);
+            }
+            let delay = match error.retry_after() {
+                // We'd rather give up than hold on to the email for longer than we are willing to.
+                Some(retry_after) if retry_after > self.retry_policy.max_delay => {
+                    warn!(
+                        retry_after_ms = retry_after.as_millis() as u64,
+                        "Retry-After exceeds the maximum retry delay. Giving up."
+                    );
+                    return Err(error);
+                }
+                Some(retry_after) => retry_after,
+                None => self.retry_policy.backoff(attempt),
+            };
+            warn!(
+                attempt,
+                delay_ms = delay.as_millis() as u64,
+                error.message = %error,
+                "Transient failure while sending an email. Retrying."
+            );
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+/// Postmark answers with a JSON body whether it accepted the email or not:
+/// `ErrorCode` is `0` on success and tells us what went wrong otherwise.
+///
+/// See <https://postmarkapp.com/developer/api/overview#error-codes>.
+async fn parse_response(response: Response) -> Result<SentEmail, EmailError> {
+    let status = response.status();
+    if status == StatusCode::TOO_MANY_REQUESTS {
+        return Err(EmailError::RateLimited {
+            retry_after: retry_after(response.headers()),
+        });
+    }
+    if status.is_server_error() {
+        return Err(EmailError::ServerError {
+            retry_after: retry_after(response.headers()),
+            source: anyhow::anyhow!("Postmark responded with {}", status),
+        });
+    }
+
+    let body = response
+        .json::<PostmarkResponse>()
+        .await
+        .map_err(classify_transport_error)?;
+    match body.error_code {
+        0 if status.is_success() => {
+            let message_id = body.message_id.ok_or_else(|| {
+                anyhow::anyhow!("Postmark accepted the email without returning a MessageID")
+            })?;
+            Ok(SentEmail { message_id })
+        }
+        // The recipient hard bounced, complained or unsubscribed before.
+        406 => Err(EmailError::InactiveRecipient(body.message)),
+        // The request failed validation, which in practice means a malformed address.
+        300 => Err(EmailError::InvalidAddress(body.message)),
+        error_code => Err(EmailError::Rejected(format!(
+            "{} (error code {})",
+            body.message, error_code
+        ))),
+    }
+}
+
+fn classify_transport_error(error: reqwest::Error) -> EmailError {
+    if error.is_timeout() || error.is_connect() {
+        EmailError::ServerError {
+            retry_after: None,
+            source: error.into(),
+        }
+    } else {
+        EmailError::UnexpectedError(error.into())
+    }
+}
+
+/// How `PostmarkEmailClient` retries transient failures.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one.
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    /// Whether to apply "full jitter", i.e. to wait a random duration
+    /// between zero and the exponential backoff delay.
+    pub jitter: bool,
+}
+
+impl RetryPolicy {
+    /// The delay to wait for after the `attempt`-th attempt failed.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let exponential = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_delay);
+        if self.jitter {
+            exponential.mul_f64(rand::thread_rng().gen::<f64>())
+        } else {
+            exponential
+        }
+    }
+}
+
+/// Parse a `Retry-After` header, which holds either a number of seconds or an HTTP date.
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let retry_at = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    // A date in the past means we can retry right away.
+    Some(
+        (retry_at.with_timezone(&Utc) - Utc::now())
+            .to_std()
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct SendEmailRequest<'a> {
+    from: &'a str,
+    to: &'a str,
+    subject: &'a str,
+    html_body: &'a str,
+    text_body: &'a str,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    headers: &'a [EmailHeader],
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct PostmarkResponse {
+    error_code: u16,
+    message: String,
+    #[serde(rename = "MessageID")]
+    message_id: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_err, assert_matches, assert_ok};
+    use fake::faker::internet::en::SafeEmail;
+    use fake::faker::lorem::en::{Paragraph, Sentence};
+    use fake::{Fake, Faker};
+    use secrecy::Secret;
+    use wiremock::matchers::{any, header, header_exists, method, path};
+    use wiremock::{Mock, MockServer, Request, ResponseTemplate};
+
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::{
+        EmailError, EmailHeader, EmailSender, PostmarkEmailClient, RetryPolicy,
+    };
+
+    // Generate a random email subject
+    fn subject() -> String {
+        Sentence(1..2).fake()
+    }
+
+    // Generate a random email content
+    fn content() -> String {
+        Paragraph(1..10).fake()
+    }
+
+    // Generate a random subscriber email
+    fn email() -> SubscriberEmail {
+        SubscriberEmail::parse(SafeEmail().fake::<String>()).unwrap()
+    }
+
+    // What Postmark answers when it accepts an email
+    fn email_sent() -> ResponseTemplate {
+        ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "To": "recipient@example.com",
+            "SubmittedAt": "2024-09-15T09:00:00.0000000Z",
+            "MessageID": "b7bc2f4a-e38e-4336-af7d-e6c392c2f817",
+            "ErrorCode": 0,
+            "Message": "OK"
+        }))
+    }
+
+    // What Postmark answers when it refuses an email
+    fn email_refused(error_code: u16, message: &str) -> ResponseTemplate {
+        ResponseTemplate::new(422).set_body_json(serde_json::json!({
+            "ErrorCode": error_code,
+            "Message": message
+        }))
+    }
+
+    // Get a test instance of `PostmarkEmailClient` which does not retry
+    fn email_client(base_url: &str) -> PostmarkEmailClient {
+        email_client_with_retries(base_url, 1)
+    }
+
+    // Get a test instance of `PostmarkEmailClient` with short, deterministic retry delays
+    fn email_client_with_retries(base_url: &str, max_attempts: u32) -> PostmarkEmailClient {
+        PostmarkEmailClient::new(
+            base_url.into(),
+            email(),
+            Secret::new(Faker.fake()),
+            std::time::Duration::from_millis(200),
+            RetryPolicy {
+                max_attempts,
+                base_delay: std::time::Duration::from_millis(10),
+                max_delay: std::time::Duration::from_secs(2),
+                jitter: false,
+            },
+        )
+    }
+
+    #[tokio::test]
+    async fn send_email_sends_the_expected_request() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri().as_str());
+
+        struct SendEmailBodyMatcher;
+
+        impl wiremock::Match for SendEmailBodyMatcher {
+            fn matches(&self, request: &Request) -> bool {
+                let result: Result<serde_json::Value, _> = serde_json::from_slice(&request.body);
+
+                if let Ok(body) = result {
+                    body.get("From").is_some()
+                        && body.get("To").is_some()
+                        && body.get("Subject").is_some()
+                        && body.get("HtmlBody").is_some()
+                        && body.get("TextBody").is_some()
+                } else {
+                    false
+                }
+            }
+        }
+        Mock::given(header_exists("X-Postmark-Server-Token"))
+            .and(header("Content-Type", "application/json"))
+            .and(path("/email"))
+            .and(method("POST"))
+            .and(SendEmailBodyMatcher)
+            .respond_with(email_sent())
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let subscriber_email = email();
+        let subject: String = subject();
+        let content: String = content();
+
+        // Act
+        let outcome = email_client
+            .send_email(&subscriber_email, &subject, &content, &content, &[])
+            .await;
+
+        // Assert
+        // Mock expectations are checked on drop
+        let sent_email = assert_ok!(outcome);
+        assert_eq!(
+            "b7bc2f4a-e38e-4336-af7d-e6c392c2f817",
+            sent_email.message_id
+        );
+    }
+
+    #[tokio::test]
+    async fn send_email_forwards_custom_headers() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri().as_str());
+
+        struct HeadersMatcher;
+
+        impl wiremock::Match for HeadersMatcher {
+            fn matches(&self, request: &Request) -> bool {
+                let result: Result<serde_json::Value, _> = serde_json::from_slice(&request.body);
+
+                if let Ok(body) = result {
+                    body["Headers"]
+                        == serde_json::json!([{"Name": "X-Test-Header", "Value": "test-value"}])
+                } else {
+                    false
+                }
+            }
+        }
+        Mock::given(path("/email"))
+            .and(method("POST"))
+            .and(HeadersMatcher)
+            .respond_with(email_sent())
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let headers = [EmailHeader {
+            name: "X-Test-Header".into(),
+            value: "test-value".into(),
+        }];
+
+        // Act
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content(), &headers)
+            .await;
+
+        // Assert
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_if_the_server_returns_500() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri().as_str());
+
+        let subscriber_email = email();
+        let subject: String = subject();
+        let content: String = Paragraph(1..10).fake();
+
+        Mock::given(any())
+            // Not a 200 anymore!
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(&subscriber_email, &subject, &content, &content, &[])
+            .await;
+
+        // Assert
+        let error = assert_err!(outcome);
+        assert_matches!(error, EmailError::ServerError { .. });
+        assert!(error.is_transient());
+    }
+
+    #[tokio::test]
+    async fn send_email_times_out_if_the_server_takes_too_long() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri().as_str());
+
+        let subscriber_email = SubscriberEmail::parse(SafeEmail().fake::<String>()).unwrap();
+        let subject: String = subject();
+        let content: String = content();
+
+        let response = email_sent()
+            // 3 minutes!
+            .set_delay(std::time::Duration::from_secs(180));
+
+        Mock::given(any())
+            .respond_with(response)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(&subscriber_email, &subject, &content, &content, &[])
+            .await;
+
+        // Assert
+        assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_retries_server_errors_until_it_succeeds() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client_with_retries(mock_server.uri().as_str(), 3);
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(2)
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+        Mock::given(any())
+            .respond_with(email_sent())
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content(), &[])
+            .await;
+
+        // Assert
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_gives_up_after_max_attempts() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client_with_retries(mock_server.uri().as_str(), 3);
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(3)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content(), &[])
+            .await;
+
+        // Assert
+        assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_does_not_retry_client_errors() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client_with_retries(mock_server.uri().as_str(), 3);
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(422))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content(), &[])
+            .await;
+
+        // Assert
+        assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_retries_timeouts() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client_with_retries(mock_server.uri().as_str(), 2);
+
+        Mock::given(any())
+            .respond_with(email_sent().set_delay(std::time::Duration::from_secs(180)))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(any())
+            .respond_with(email_sent())
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content(), &[])
+            .await;
+
+        // Assert
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_waits_for_retry_after_when_rate_limited() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client_with_retries(mock_server.uri().as_str(), 2);
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "1"))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(any())
+            .respond_with(email_sent())
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let start = std::time::Instant::now();
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content(), &[])
+            .await;
+
+        // Assert
+        assert_ok!(outcome);
+        assert!(start.elapsed() >= std::time::Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn send_email_gives_up_if_retry_after_exceeds_the_max_delay() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client_with_retries(mock_server.uri().as_str(), 3);
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "3600"))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content(), &[])
+            .await;
+
+        // Assert
+        assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_reports_inactive_recipients() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client_with_retries(mock_server.uri().as_str(), 3);
+
+        Mock::given(any())
+            .respond_with(email_refused(
+                406,
+                "You tried to send to a recipient that has been marked as inactive.",
+            ))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content(), &[])
+            .await;
+
+        // Assert
+        let error = assert_err!(outcome);
+        assert_matches!(error, EmailError::InactiveRecipient(_));
+        assert!(!error.is_transient());
+    }
+
+    #[tokio::test]
+    async fn send_email_reports_invalid_addresses() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri().as_str());
+
+        Mock::given(any())
+            .respond_with(email_refused(300, "Invalid 'To' address: 'nope'."))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content(), &[])
+            .await;
+
+        // Assert
+        assert_matches!(assert_err!(outcome), EmailError::InvalidAddress(_));
+    }
+
+    #[tokio::test]
+    async fn send_email_reports_other_postmark_errors_as_rejections() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri().as_str());
+
+        Mock::given(any())
+            .respond_with(email_refused(412, "Your account is pending approval."))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content(), &[])
+            .await;
+
+        // Assert
+        assert_matches!(assert_err!(outcome), EmailError::Rejected(_));
+    }
+
+    #[tokio::test]
+    async fn send_email_reports_rate_limiting() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri().as_str());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "30"))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content(), &[])
+            .await;
+
+        // Assert
+        let error = assert_err!(outcome);
+        assert_matches!(error, EmailError::RateLimited { .. });
+        assert_eq!(
+            Some(std::time::Duration::from_secs(30)),
+            error.retry_after()
+        );
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_if_a_success_has_no_message_id() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri().as_str());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content(), &[])
+            .await;
+
+        // Assert
+        assert_matches!(assert_err!(outcome), EmailError::UnexpectedError(_));
+    }
+
+    #[test]
+    fn backoff_doubles_and_is_capped_at_the_max_delay() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            base_delay: std::time::Duration::from_millis(100),
+            max_delay: std::time::Duration::from_millis(500),
+            jitter: false,
+        };
+
+        let delays: Vec<_> = (1..=5).map(|attempt| policy.backoff(attempt)).collect();
+
+        assert_eq!(
+            vec![100, 200, 400, 500, 500],
+            delays.iter().map(|d| d.as_millis()).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn jittered_backoff_never_exceeds_the_exponential_delay() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            base_delay: std::time::Duration::from_millis(100),
+            max_delay: std::time::Duration::from_millis(500),
+            jitter: true,
+        };
+
+        for attempt in 1..=5 {
+            assert!(policy.backoff(attempt) <= std::time::Duration::from_millis(500));
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Tokio1Executor};
+use secrecy::{ExposeSecret, Secret};
+use tracing::{info, instrument};
+
+use crate::domain::SubscriberEmail;
+use crate::email_client::{build_message, EmailError, EmailHeader, EmailSender, SentEmail};
+
+/// Sends emails to an SMTP relay, upgrading the connection with STARTTLS.
+pub struct SmtpEmailClient {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    sender: SubscriberEmail,
+}
+
+impl SmtpEmailClient {
+    pub fn new(
+        host: &str,
+        port: u16,
+        username: String,
+        password: Secret<String>,
+        sender: SubscriberEmail,
+        timeout: Duration,
+    ) -> Result<Self, anyhow::Error> {
+        let transport = AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)?
+            .port(port)
+            .credentials(Credentials::new(
+                username,
+                password.expose_secret().to_owned(),
+            ))
+            .timeout(Some(timeout))
+            .build();
+
+        Ok(Self { transport, sender })
+    }
+}
+
+#[async_trait]
+impl EmailSender for SmtpEmailClient {
+    fn sender(&self) -> &SubscriberEmail {
+        &self.sender
+    }
+
+    #[instrument(skip(self))]
+    async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[EmailHeader],
+    ) -> Result<SentEmail, EmailError> {
+        let (message, sent_email) = build_message(
+            &self.sender,
+            recipient,
+            subject,
+            html_content,
+            text_content,
+            headers,
+        )?;
+        let response = self
+            .transport
+            .send(message)
+            .await
+            .map_err(classify_smtp_error)?;
+
+        info!(response=?response, message_id = %sent_email.message_id, "Sent email");
+        Ok(sent_email)
+    }
+}
+
+/// Map SMTP reply codes onto `EmailError`: `4xx` replies are transient,
+/// `5xx` replies are permanent.
+fn classify_smtp_error(error: lettre::transport::smtp::Error) -> EmailError {
+    let code = error.status().map(u16::from);
+    if error.is_transient() || error.is_timeout() {
+        // 421 and 450-452 are how relays usually tell us to slow down.
+        match code {
+            Some(421 | 450 | 451 | 452) => EmailError::RateLimited { retry_after: None },
+            _ => EmailError::ServerError {
+                retry_after: None,
+                source: error.into(),
+            },
+        }
+    } else if error.is_permanent() {
+        match code {
+            // Mailbox unavailable, user not local, mailbox name not allowed.
+            Some(550 | 551 | 553) => EmailError::InvalidAddress(error.to_string()),
+            _ => EmailError::Rejected(error.to_string()),
+        }
+    } else {
+        EmailError::UnexpectedError(error.into())
+    }
+}
@@ -0,0 +1,7 @@
+pub use expiry_worker::run_expiry_worker_until_stopped;
+pub use key::IdempotencyKey;
+pub use persistence::{save_response, try_processing, NextAction};
+
+mod expiry_worker;
+mod key;
+mod persistence;
@@ -0,0 +1,33 @@
+use std::time::Duration;
+
+use chrono::Utc;
+use sqlx::PgPool;
+use tracing::instrument;
+
+/// Periodically delete the idempotency keys which are older than `expiration`.
+pub async fn run_expiry_worker_until_stopped(
+    pool: PgPool,
+    expiration: Duration,
+    cleanup_interval: Duration,
+) -> Result<(), anyhow::Error> {
+    loop {
+        // A failed cleanup is not worth bringing the application down for:
+        // the next iteration will pick up the keys we've missed.
+        let _ = delete_expired_keys(&pool, expiration).await;
+        tokio::time::sleep(cleanup_interval).await;
+    }
+}
+
+#[instrument(skip(pool), err)]
+async fn delete_expired_keys(pool: &PgPool, expiration: Duration) -> Result<(), anyhow::Error> {
+    let expired_before = Utc::now() - expiration;
+    let n_deleted_rows = sqlx::query!(
+        r#"DELETE FROM idempotency WHERE created_at < $1"#,
+        expired_before
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+    tracing::info!(n_deleted_rows, "Deleted expired idempotency keys");
+    Ok(())
+}
@@ -0,0 +1,455 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::{Executor, PgPool, Postgres, QueryBuilder, Transaction};
+use tracing::{field::display, instrument, Span};
+use uuid::Uuid;
+
+use crate::domain::SubscriberEmail;
+use crate::email_client::{EmailError, EmailHeader, EmailSender};
+use crate::routes::{preferences_link, unsubscribe_link};
+use crate::segment::Segment;
+use crate::startup::{ApplicationBaseUrl, HmacSecret};
+use crate::template::{render_email, TemplateContext};
+
+/// How many times a delivery is put back in the queue after a transient failure
+/// before we give up on it.
+const MAX_DELIVERY_RETRIES: i16 = 5;
+
+/// Poll the delivery queue forever, sending one email per task.
+///
+/// Tasks are claimed with `FOR UPDATE SKIP LOCKED`,
+/// so it is safe to run as many workers in parallel as we like.
+pub async fn run_worker_until_stopped(
+    pool: PgPool,
+    email_client: Arc<dyn EmailSender>,
+    base_url: ApplicationBaseUrl,
+    hmac_secret: HmacSecret,
+) -> Result<(), anyhow::Error> {
+    loop {
+        match try_execute_task(&pool, email_client.as_ref(), &base_url, &hmac_secret).await {
+            Ok(ExecutionOutcome::EmptyQueue) => {
+                tokio::time::sleep(Duration::from_secs(10)).await;
+            }
+            Err(_) => {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+        }
+    }
+}
+
+pub enum ExecutionOutcome {
+    TaskCompleted,
+    EmptyQueue,
+}
+
+#[instrument(
+    skip_all,
+    fields(
+        newsletter_issue_id = tracing::field::Empty,
+        subscriber_id = tracing::field::Empty,
+    ),
+    err
+)]
+pub async fn try_execute_task(
+    pool: &PgPool,
+    email_client: &dyn EmailSender,
+    base_url: &ApplicationBaseUrl,
+    hmac_secret: &HmacSecret,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let task = dequeue_task(pool).await?;
+    let Some((mut transaction, issue_id, subscriber_id, n_retries)) = task else {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    };
+    Span::current()
+        .record("newsletter_issue_id", display(issue_id))
+        .record("subscriber_id", display(subscriber_id));
+
+    // The subscriber might have left the list, or paused delivery, since the issue
+    // was published, so we look them up again right before sending.
+    let attempt = match get_confirmed_subscriber(&mut transaction, issue_id, subscriber_id).await? {
+        Some(Ok(subscriber)) => {
+            let email = subscriber.email;
+            let issue = get_issue(pool, issue_id).await?;
+            let unsubscribe_link = unsubscribe_link(base_url, hmac_secret, subscriber_id);
+            let preferences_link = preferences_link(base_url, hmac_secret, subscriber_id);
+            let context = TemplateContext {
+                subscriber_name: &subscriber.name,
+                subscriber_email: email.as_ref(),
+                issue_title: &issue.title,
+                unsubscribe_url: &unsubscribe_link,
+                preferences_url: &preferences_link,
+            };
+            let (html_body, text_body) =
+                render_email(&issue.html_content, &issue.text_content, &context);
+            let headers = list_unsubscribe_headers(email_client, &unsubscribe_link);
+            match email_client
+                .send_email(&email, &issue.title, &html_body, &text_body, &headers)
+                .await
+            {
+                Ok(sent_email) => {
+                    tracing::info!(message_id = %sent_email.message_id, "Delivered issue.");
+                    DeliveryAttempt::sent(sent_email.message_id)
+                }
+                Err(e) if e.is_transient() && n_retries < MAX_DELIVERY_RETRIES => {
+                    tracing::warn!(
+                        error.cause_chain = ?e,
+                        error.message = %e,
+                        n_retries,
+                        "Failed to deliver issue to a confirmed subscriber. \
+                        Retrying later.",
+                    );
+                    let attempt = DeliveryAttempt::unsuccessful("queued", &e);
+                    record_delivery_attempt(&mut transaction, issue_id, subscriber_id, &attempt)
+                        .await?;
+                    let delay = e.retry_after().unwrap_or_else(|| retry_delay(n_retries));
+                    retry_task(transaction, issue_id, subscriber_id, delay).await?;
+                    return Ok(ExecutionOutcome::TaskCompleted);
+                }
+                Err(e @ (EmailError::InactiveRecipient(_) | EmailError::InvalidAddress(_))) => {
+                    tracing::warn!(
+                        error.message = %e,
+                        "The email provider won't deliver to this subscriber. \
+                        Skipping.",
+                    );
+                    // Providers deactivate recipients after hard bounces and spam complaints.
+                    let status = match e {
+                        EmailError::InactiveRecipient(_) => "bounced",
+                        _ => "failed",
+                    };
+                    DeliveryAttempt::unsuccessful(status, &e)
+                }
+                Err(e) => {
+                    tracing::error!(
+                        error.cause_chain = ?e,
+                        error.message = %e,
+                        "Failed to deliver issue to a confirmed subscriber. \
+                        Skipping.",
+                    );
+                    DeliveryAttempt::unsuccessful("failed", &e)
+                }
+            }
+        }
+        Some(Err(e)) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Skipping a confirmed subscriber. \
+                Their stored contact details are invalid",
+            );
+            DeliveryAttempt::unsuccessful("failed", &e)
+        }
+        None => {
+            tracing::info!("Skipping a subscriber who is no longer confirmed.");
+            // Nothing was delivered, nor should have been.
+            skip_delivery(&mut transaction, issue_id, subscriber_id).await?;
+            delete_task(transaction, issue_id, subscriber_id).await?;
+            return Ok(ExecutionOutcome::TaskCompleted);
+        }
+    };
+    record_delivery_attempt(&mut transaction, issue_id, subscriber_id, &attempt).await?;
+    delete_task(transaction, issue_id, subscriber_id).await?;
+
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+/// Queue one delivery task per confirmed member of the issue's list, within the segment if any.
+#[instrument(skip_all)]
+pub async fn enqueue_delivery_tasks(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    segment: Option<&Segment>,
+) -> Result<(), sqlx::Error> {
+    // The segment filter is only known at runtime, hence the query builder.
+    let mut query = QueryBuilder::new(
+        r#"
+        INSERT INTO issue_delivery_queue (
+            newsletter_issue_id,
+            subscriber_id
+        )
+        SELECT i.newsletter_issue_id, s.id
+        FROM newsletter_issues i
+        JOIN list_memberships m ON m.list_id = i.list_id
+        JOIN subscriptions s ON s.id = m.subscriber_id
+        WHERE
+            m.status = 'confirmed' AND
+            s.status = 'confirmed' AND
+            (s.paused_until IS NULL OR s.paused_until <= now()) AND
+            i.newsletter_issue_id = "#,
+    );
+    query.push_bind(newsletter_issue_id);
+    if let Some(segment) = segment {
+        query.push(" AND ");
+        segment.push_sql_filter(&mut query, "s.id");
+    }
+    transaction.execute(query.build()).await?;
+    transaction
+        .execute(sqlx::query!(
+            r#"
+            INSERT INTO deliveries (newsletter_issue_id, subscriber_id, status)
+            SELECT newsletter_issue_id, subscriber_id, 'queued'
+            FROM issue_delivery_queue
+            WHERE newsletter_issue_id = $1
+            "#,
+            newsletter_issue_id
+        ))
+        .await?;
+    Ok(())
+}
+
+/// Build the RFC 2369 `List-Unsubscribe` header, together with the RFC 8058
+/// `List-Unsubscribe-Post` header which advertises one-click unsubscription
+/// by `POST`ing to the https link.
+fn list_unsubscribe_headers(
+    email_client: &dyn EmailSender,
+    unsubscribe_link: &str,
+) -> [EmailHeader; 2] {
+    [
+        EmailHeader {
+            name: "List-Unsubscribe".into(),
+            value: format!(
+                "<mailto:{}?subject=unsubscribe>, <{}>",
+                email_client.sender(),
+                unsubscribe_link
+            ),
+        },
+        EmailHeader {
+            name: "List-Unsubscribe-Post".into(),
+            value: "List-Unsubscribe=One-Click".into(),
+        },
+    ]
+}
+
+/// Exponential backoff between delivery attempts: 1, 2, 4, 8 and 16 minutes.
+fn retry_delay(n_retries: i16) -> Duration {
+    Duration::from_secs(60 * 2u64.pow(n_retries as u32))
+}
+
+type PgTransaction = Transaction<'static, Postgres>;
+
+#[instrument(skip_all)]
+async fn dequeue_task(
+    pool: &PgPool,
+) -> Result<Option<(PgTransaction, Uuid, Uuid, i16)>, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let r = sqlx::query!(
+        r#"
+        SELECT newsletter_issue_id, subscriber_id, n_retries
+        FROM issue_delivery_queue
+        WHERE execute_after <= now()
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT 1
+        "#,
+    )
+    .fetch_optional(&mut *transaction)
+    .await?;
+
+    Ok(r.map(|r| {
+        (
+            transaction,
+            r.newsletter_issue_id,
+            r.subscriber_id,
+            r.n_retries,
+        )
+    }))
+}
+
+/// Put the task back in the queue, to be picked up again once `delay` has elapsed.
+#[instrument(skip(transaction))]
+async fn retry_task(
+    mut transaction: PgTransaction,
+    issue_id: Uuid,
+    subscriber_id: Uuid,
+    delay: Duration,
+) -> Result<(), anyhow::Error> {
+    let execute_after = Utc::now() + chrono::Duration::from_std(delay)?;
+    let query = sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET
+            n_retries = n_retries + 1,
+            execute_after = $3
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_id = $2
+        "#,
+        issue_id,
+        subscriber_id,
+        execute_after
+    );
+    transaction.execute(query).await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+#[instrument(skip_all)]
+async fn delete_task(
+    mut transaction: PgTransaction,
+    issue_id: Uuid,
+    subscriber_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    let query = sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_queue
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_id = $2
+        "#,
+        issue_id,
+        subscriber_id
+    );
+    transaction.execute(query).await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// The outcome of an attempt to deliver an issue to one subscriber, see `crate::deliveries`.
+struct DeliveryAttempt {
+    /// `queued` if the attempt is to be retried.
+    status: &'static str,
+    error: Option<String>,
+    message_id: Option<String>,
+}
+
+impl DeliveryAttempt {
+    fn sent(message_id: String) -> Self {
+        Self {
+            status: "sent",
+            error: None,
+            message_id: Some(message_id),
+        }
+    }
+
+    fn unsuccessful(status: &'static str, error: &impl std::fmt::Display) -> Self {
+        Self {
+            status,
+            error: Some(error.to_string()),
+            message_id: None,
+        }
+    }
+}
+
+#[instrument(skip(transaction, attempt), fields(status = attempt.status))]
+async fn record_delivery_attempt(
+    transaction: &mut PgTransaction,
+    issue_id: Uuid,
+    subscriber_id: Uuid,
+    attempt: &DeliveryAttempt,
+) -> Result<(), anyhow::Error> {
+    let query = sqlx::query!(
+        r#"
+        UPDATE deliveries
+        SET
+            status = $3,
+            n_attempts = n_attempts + 1,
+            last_error = $4,
+            message_id = $5,
+            updated_at = now()
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_id = $2
+        "#,
+        issue_id,
+        subscriber_id,
+        attempt.status,
+        attempt.error,
+        attempt.message_id
+    );
+    transaction.execute(query).await?;
+    Ok(())
+}
+
+/// Mark the delivery as `skipped`: no attempt was made, so none is counted.
+#[instrument(skip(transaction))]
+async fn skip_delivery(
+    transaction: &mut PgTransaction,
+    issue_id: Uuid,
+    subscriber_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    let query = sqlx::query!(
+        r#"
+        UPDATE deliveries
+        SET
+            status = 'skipped',
+            updated_at = now()
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_id = $2
+        "#,
+        issue_id,
+        subscriber_id
+    );
+    transaction.execute(query).await?;
+    Ok(())
+}
+
+struct Recipient {
+    email: SubscriberEmail,
+    name: String,
+}
+
+/// Return `None` if the subscriber is not a confirmed member of the issue's list (anymore),
+/// or if they paused delivery.
+///
+/// As in the rest of the codebase, a stored email that fails to parse
+/// is reported through the inner `Result` rather than failing the whole task.
+#[instrument(skip_all)]
+async fn get_confirmed_subscriber(
+    transaction: &mut PgTransaction,
+    issue_id: Uuid,
+    subscriber_id: Uuid,
+) -> Result<Option<Result<Recipient, anyhow::Error>>, anyhow::Error> {
+    let r = sqlx::query!(
+        r#"
+        SELECT s.email, s.name
+        FROM subscriptions s
+        JOIN list_memberships m ON m.subscriber_id = s.id
+        JOIN newsletter_issues i ON i.list_id = m.list_id
+        WHERE
+            s.id = $1 AND
+            i.newsletter_issue_id = $2 AND
+            s.status = 'confirmed' AND
+            m.status = 'confirmed' AND
+            (s.paused_until IS NULL OR s.paused_until <= now())
+        "#,
+        subscriber_id,
+        issue_id
+    )
+    .fetch_optional(&mut **transaction)
+    .await
+    .context("Failed to retrieve the subscriber's details.")?;
+
+    Ok(r.map(|r| {
+        let email = SubscriberEmail::parse(r.email).map_err(|e| anyhow::anyhow!(e))?;
+        Ok(Recipient {
+            email,
+            name: r.name,
+        })
+    }))
+}
+
+struct NewsletterIssue {
+    title: String,
+    text_content: String,
+    html_content: String,
+}
+
+#[instrument(skip_all)]
+async fn get_issue(pool: &PgPool, issue_id: Uuid) -> Result<NewsletterIssue, anyhow::Error> {
+    let issue = sqlx::query_as!(
+        NewsletterIssue,
+        r#"
+        SELECT title, text_content, html_content
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        "#,
+        issue_id
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(issue)
+}
@@ -0,0 +1,92 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use sqlx::{Executor, PgPool};
+use tracing::{field::display, instrument, Span};
+
+use crate::issue_delivery_worker::enqueue_delivery_tasks;
+use crate::segment::Segment;
+
+/// Release scheduled issues into the delivery queue once they are due, forever.
+///
+/// Scheduled issues are stored in the database, so none are lost across restarts.
+/// Issues are claimed with `FOR UPDATE SKIP LOCKED` and marked as published
+/// in the same transaction, so it is safe to run a scheduler in every instance.
+pub async fn run_scheduler_until_stopped(pool: PgPool) -> Result<(), anyhow::Error> {
+    loop {
+        match try_release_issue(&pool).await {
+            Ok(ReleaseOutcome::NothingDue) => {
+                tokio::time::sleep(Duration::from_secs(10)).await;
+            }
+            Err(_) => {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            Ok(ReleaseOutcome::IssueReleased) => {}
+        }
+    }
+}
+
+pub enum ReleaseOutcome {
+    IssueReleased,
+    NothingDue,
+}
+
+#[instrument(skip_all, fields(newsletter_issue_id = tracing::field::Empty), err)]
+pub async fn try_release_issue(pool: &PgPool) -> Result<ReleaseOutcome, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let Some(issue) = sqlx::query!(
+        r#"
+        SELECT newsletter_issue_id, segment
+        FROM newsletter_issues
+        WHERE status = 'scheduled' AND send_at <= now()
+        ORDER BY send_at
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT 1
+        "#,
+    )
+    .fetch_optional(&mut *transaction)
+    .await?
+    else {
+        return Ok(ReleaseOutcome::NothingDue);
+    };
+    Span::current().record("newsletter_issue_id", display(issue.newsletter_issue_id));
+
+    // The segment was validated when the issue was scheduled.
+    // Should it fail to parse anyway, retrying would block the issues due after this one.
+    let status = match issue.segment.as_deref().map(Segment::parse).transpose() {
+        Ok(segment) => {
+            enqueue_delivery_tasks(
+                &mut transaction,
+                issue.newsletter_issue_id,
+                segment.as_ref(),
+            )
+            .await
+            .context("Failed to enqueue delivery tasks")?;
+            tracing::info!("Released a scheduled issue.");
+            "published"
+        }
+        Err(e) => {
+            tracing::error!(
+                error.message = %e,
+                "Cancelling a scheduled issue. Its segment is invalid.",
+            );
+            "cancelled"
+        }
+    };
+    transaction
+        .execute(sqlx::query!(
+            r#"
+            UPDATE newsletter_issues
+            SET
+                status = $2,
+                published_at = CASE WHEN $2 = 'published' THEN now() END
+            WHERE newsletter_issue_id = $1
+            "#,
+            issue.newsletter_issue_id,
+            status
+        ))
+        .await?;
+    transaction.commit().await?;
+    Ok(ReleaseOutcome::IssueReleased)
+}
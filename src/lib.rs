@@ -1,6 +1,20 @@
+pub mod archive;
+pub mod authentication;
 pub mod configuration;
+pub mod deliveries;
 pub mod domain;
 pub mod email_client;
+pub mod idempotency;
+pub mod issue_delivery_worker;
+pub mod issue_scheduler;
+pub mod lists;
+pub mod markdown;
 pub mod routes;
+pub mod segment;
+pub mod session_state;
+pub mod session_store;
 pub mod startup;
+pub mod tags;
 pub mod telemetry;
+pub mod template;
+pub mod utils;
@@ -0,0 +1,76 @@
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::domain::ListSlug;
+
+/// Subscriptions and issues which don't name a list go to this one.
+/// It is created by the migration which introduced lists.
+pub const DEFAULT_LIST_SLUG: &str = "newsletter";
+
+pub struct List {
+    pub list_id: Uuid,
+    pub slug: String,
+    pub name: String,
+}
+
+pub async fn get_list_id(
+    transaction: &mut Transaction<'_, Postgres>,
+    slug: &ListSlug,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    sqlx::query_scalar!("SELECT list_id FROM lists WHERE slug = $1", slug.as_ref())
+        .fetch_optional(&mut **transaction)
+        .await
+}
+
+pub async fn get_default_list_id(
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<Uuid, sqlx::Error> {
+    sqlx::query_scalar!(
+        "SELECT list_id FROM lists WHERE slug = $1",
+        DEFAULT_LIST_SLUG
+    )
+    .fetch_one(&mut **transaction)
+    .await
+}
+
+pub async fn list_exists(
+    transaction: &mut Transaction<'_, Postgres>,
+    list_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"SELECT EXISTS (SELECT 1 FROM lists WHERE list_id = $1) as "exists!""#,
+        list_id
+    )
+    .fetch_one(&mut **transaction)
+    .await
+}
+
+pub async fn get_lists(pool: &PgPool) -> Result<Vec<List>, sqlx::Error> {
+    sqlx::query_as!(
+        List,
+        "SELECT list_id, slug, name FROM lists ORDER BY created_at"
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Return `None` if there is a list with the same slug already.
+pub async fn insert_list(
+    pool: &PgPool,
+    slug: &ListSlug,
+    name: &str,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"
+        INSERT INTO lists (list_id, slug, name)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (slug) DO NOTHING
+        RETURNING list_id
+        "#,
+        Uuid::new_v4(),
+        slug.as_ref(),
+        name
+    )
+    .fetch_optional(pool)
+    .await
+}
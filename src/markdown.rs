@@ -0,0 +1,192 @@
+//! Newsletter issues can be written in Markdown rather than in both HTML and plain text.
+use pulldown_cmark::{Event, Parser, Tag, TagEnd};
+
+/// Render `markdown` as HTML, stripped of anything unsafe (scripts, event handlers, etc.).
+///
+/// Template tags survive the conversion, including in link destinations:
+/// `[Unsubscribe]({{unsubscribe_url}})` works as expected.
+pub fn to_html(markdown: &str) -> String {
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, Parser::new(markdown));
+    restore_template_tags(&ammonia::clean(&html))
+}
+
+/// Link destinations are percent-encoded, braces and spaces included.
+fn restore_template_tags(html: &str) -> String {
+    let mut restored = String::new();
+    let mut rest = html;
+    while let Some(start) = rest.find("%7B%7B") {
+        let Some(length) = rest[start..].find("%7D%7D") else {
+            break;
+        };
+        let name = &rest[start + 6..start + length];
+        restored.push_str(&rest[..start]);
+        restored.push_str(&format!("{{{{{}}}}}", name.replace("%20", " ")));
+        rest = &rest[start + length + 6..];
+    }
+    restored.push_str(rest);
+    restored
+}
+
+/// Render `markdown` as plain text.
+///
+/// Formatting is dropped, and links are numbered: their destinations are listed at the end,
+/// like footnotes.
+pub fn to_text(markdown: &str) -> String {
+    let mut text = String::new();
+    let mut footnotes: Vec<String> = Vec::new();
+    // The destination of the links being rendered, and where their text starts.
+    let mut links: Vec<(String, usize)> = Vec::new();
+    // The next item number of the lists being rendered, `None` for bullet lists.
+    let mut lists: Vec<Option<u64>> = Vec::new();
+
+    for event in Parser::new(markdown) {
+        match event {
+            Event::Start(Tag::Link { dest_url, .. } | Tag::Image { dest_url, .. }) => {
+                links.push((dest_url.into_string(), text.len()));
+            }
+            Event::End(TagEnd::Link | TagEnd::Image) => {
+                let Some((destination, start)) = links.pop() else {
+                    continue;
+                };
+                // Autolinks show their destination already.
+                let label = &text[start..];
+                if label == destination || destination.strip_prefix("mailto:") == Some(label) {
+                    continue;
+                }
+                let index = match footnotes.iter().position(|f| *f == destination) {
+                    Some(index) => index,
+                    None => {
+                        footnotes.push(destination);
+                        footnotes.len() - 1
+                    }
+                };
+                text.push_str(&format!(" [{}]", index + 1));
+            }
+            Event::Start(Tag::List(first_number)) => {
+                // A list nested in a tight list item starts right after the item's text.
+                if !text.is_empty() && !text.ends_with('\n') {
+                    text.push('\n');
+                }
+                lists.push(first_number);
+            }
+            Event::End(TagEnd::List(_)) => {
+                lists.pop();
+                if lists.is_empty() {
+                    text.push('\n');
+                }
+            }
+            Event::Start(Tag::Item) => {
+                text.push_str(&"  ".repeat(lists.len().saturating_sub(1)));
+                match lists.last_mut() {
+                    Some(Some(number)) => {
+                        text.push_str(&format!("{}. ", number));
+                        *number += 1;
+                    }
+                    _ => text.push_str("- "),
+                }
+            }
+            Event::End(TagEnd::Item) if !text.ends_with('\n') => text.push('\n'),
+            Event::End(TagEnd::Paragraph) => {
+                text.push_str(if lists.is_empty() { "\n\n" } else { "\n" });
+            }
+            Event::End(TagEnd::Heading(_) | TagEnd::CodeBlock | TagEnd::BlockQuote(_))
+                if !text.ends_with("\n\n") =>
+            {
+                text.push_str(if text.ends_with('\n') { "\n" } else { "\n\n" });
+            }
+            Event::Text(s) | Event::Code(s) => text.push_str(&s),
+            Event::SoftBreak | Event::HardBreak => text.push('\n'),
+            Event::Rule => text.push_str("---\n\n"),
+            // Raw HTML has no plain text equivalent.
+            _ => {}
+        }
+    }
+
+    let mut text = text.trim_end().to_owned();
+    if !footnotes.is_empty() {
+        text.push_str("\n\n");
+        for (index, destination) in footnotes.iter().enumerate() {
+            text.push_str(&format!("[{}] {}\n", index + 1, destination));
+        }
+        text.pop();
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{to_html, to_text};
+
+    #[test]
+    fn markdown_is_rendered_as_html() {
+        assert_eq!(
+            to_html("# Hello\n\nSome *emphasis* and a [link](https://example.com)."),
+            "<h1>Hello</h1>\n<p>Some <em>emphasis</em> and a \
+            <a href=\"https://example.com\" rel=\"noopener noreferrer\">link</a>.</p>\n"
+        );
+    }
+
+    #[test]
+    fn unsafe_html_is_stripped() {
+        let html = to_html(
+            "<script>alert('xss')</script>\n\n\
+            <p onclick=\"alert('xss')\">Hi</p>\n\n\
+            [click](javascript:alert('xss'))",
+        );
+        assert!(!html.contains("script"));
+        assert!(!html.contains("onclick"));
+        assert!(!html.contains("javascript"));
+        assert!(html.contains("Hi"));
+    }
+
+    #[test]
+    fn template_tags_survive_in_html_links() {
+        let html = to_html("Hi {{ subscriber.name }}, [leave]({{unsubscribe_url}}) or [stay](<{{ preferences_url }}>).");
+        assert!(html.contains("Hi {{ subscriber.name }}"));
+        assert!(html.contains("href=\"{{unsubscribe_url}}\""));
+        assert!(html.contains("href=\"{{ preferences_url }}\""));
+    }
+
+    #[test]
+    fn links_become_footnotes_in_plain_text() {
+        assert_eq!(
+            to_text(
+                "See [our site](https://example.com), [the docs](https://example.com/docs) \
+                and [our site](https://example.com) again, or <https://example.org>."
+            ),
+            "See our site [1], the docs [2] and our site [1] again, or https://example.org.\n\
+            \n\
+            [1] https://example.com\n\
+            [2] https://example.com/docs"
+        );
+    }
+
+    #[test]
+    fn plain_text_keeps_the_structure_of_the_document() {
+        assert_eq!(
+            to_text(
+                "# Title\n\
+                \n\
+                Some **bold** text\nover two lines.\n\
+                \n\
+                - one\n\
+                - two\n  1. nested\n  2. list\n\
+                \n\
+                ```\ncode\n```\n\
+                \n\
+                The end."
+            ),
+            "Title\n\
+            \n\
+            Some bold text\nover two lines.\n\
+            \n\
+            - one\n\
+            - two\n  1. nested\n  2. list\n\
+            \n\
+            code\n\
+            \n\
+            The end."
+        );
+    }
+}
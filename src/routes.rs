@@ -1,9 +1,36 @@
+pub use admin::{
+    add_tag, admin_dashboard, change_password, change_password_form, clear_lockout, create_list,
+    issue_failures_csv, issue_report, issues, lists, lockouts, log_out, remove_tag, tags,
+};
+pub use archive::{archive, archived_issue};
+pub use feeds::{atom_feed, rss_feed};
 pub use health_check::health_check;
+pub use login::{login, login_form};
 pub use newsletters::publish_newsletter;
+pub use newsletters_drafts::{
+    create_draft, preview_draft, publish_draft, test_send_draft, update_draft,
+};
+pub use newsletters_scheduled::{cancel_issue, list_scheduled_issues, reschedule_issue};
 pub use subscriptions::{error_chain_fmt, subscribe, FormData};
 pub use subscriptions_confirm::confirm;
+pub use subscriptions_preferences::{
+    change_email, change_name, confirm_email_change, pause_delivery, preferences_form,
+    preferences_link,
+};
+pub use subscriptions_resend_confirmation::resend_confirmation;
+pub use subscriptions_unsubscribe::{unsubscribe, unsubscribe_form, unsubscribe_link};
 
+mod admin;
+mod archive;
+mod feeds;
 mod health_check;
+mod login;
 mod newsletters;
+mod newsletters_drafts;
+mod newsletters_scheduled;
+mod signed_links;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_preferences;
+mod subscriptions_resend_confirmation;
+mod subscriptions_unsubscribe;
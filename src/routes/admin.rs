@@ -0,0 +1,15 @@
+pub use dashboard::admin_dashboard;
+pub use issues::{issue_failures_csv, issue_report, issues};
+pub use lists::{create_list, lists};
+pub use lockouts::{clear_lockout, lockouts};
+pub use logout::log_out;
+pub use password::{change_password, change_password_form};
+pub use tags::{add_tag, remove_tag, tags};
+
+mod dashboard;
+mod issues;
+mod lists;
+mod lockouts;
+mod logout;
+mod password;
+mod tags;
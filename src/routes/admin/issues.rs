@@ -0,0 +1,183 @@
+use std::borrow::Cow;
+
+use actix_web::http::header::{ContentDisposition, ContentType, DispositionParam, DispositionType};
+use actix_web::{web, HttpResponse};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::deliveries::{
+    get_delivery_totals, get_failed_deliveries, get_published_issue, get_published_issues,
+};
+use crate::utils::e500;
+
+pub async fn issues(pool: web::Data<PgPool>) -> Result<HttpResponse, actix_web::Error> {
+    let issues = get_published_issues(&pool).await.map_err(e500)?;
+
+    let mut rows = String::new();
+    for issue in &issues {
+        rows.push_str(&format!(
+            r#"
+        <tr>
+            <td>{}</td>
+            <td>{}</td>
+            <td><a href="/admin/issues/{}/report">Delivery report</a></td>
+        </tr>"#,
+            htmlescape::encode_minimal(&issue.title),
+            issue.published_at.to_rfc3339(),
+            issue.newsletter_issue_id,
+        ));
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Newsletter issues</title>
+</head>
+<body>
+    <table>
+        <tr>
+            <th>Title</th>
+            <th>Published at</th>
+            <th></th>
+        </tr>{}
+    </table>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+            rows
+        )))
+}
+
+#[tracing::instrument(name = "Show the delivery report of an issue", skip(pool))]
+pub async fn issue_report(
+    issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue_id = issue_id.into_inner();
+    let Some(issue) = get_published_issue(&pool, issue_id).await.map_err(e500)? else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let totals = get_delivery_totals(&pool, issue_id).await.map_err(e500)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Delivery report</title>
+</head>
+<body>
+    <h1>{}</h1>
+    <p>Published at {}</p>
+    <table>
+        <tr><th>Queued</th><td>{}</td></tr>
+        <tr><th>Sent</th><td>{}</td></tr>
+        <tr><th>Failed</th><td>{}</td></tr>
+        <tr><th>Bounced</th><td>{}</td></tr>
+        <tr><th>Skipped</th><td>{}</td></tr>
+        <tr><th>Total</th><td>{}</td></tr>
+    </table>
+    <p><a href="/admin/issues/{}/report/failures.csv">Download the failed deliveries (CSV)</a></p>
+    <p><a href="/admin/issues">&lt;- Back</a></p>
+</body>
+</html>"#,
+            htmlescape::encode_minimal(&issue.title),
+            issue.published_at.to_rfc3339(),
+            totals.queued,
+            totals.sent,
+            totals.failed,
+            totals.bounced,
+            totals.skipped,
+            totals.total(),
+            issue_id,
+        )))
+}
+
+/// The failed and bounced deliveries of an issue, as a CSV attachment.
+#[tracing::instrument(name = "Export the failed deliveries of an issue", skip(pool))]
+pub async fn issue_failures_csv(
+    issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue_id = issue_id.into_inner();
+    if get_published_issue(&pool, issue_id)
+        .await
+        .map_err(e500)?
+        .is_none()
+    {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+    let failures = get_failed_deliveries(&pool, issue_id).await.map_err(e500)?;
+
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer
+        .write_record([
+            "email",
+            "status",
+            "attempts",
+            "last_error",
+            "message_id",
+            "updated_at",
+        ])
+        .map_err(e500)?;
+    for failure in &failures {
+        writer
+            .write_record([
+                &csv_cell(&failure.email),
+                failure.status.as_str(),
+                &failure.n_attempts.to_string(),
+                &csv_cell(failure.last_error.as_deref().unwrap_or_default()),
+                &csv_cell(failure.message_id.as_deref().unwrap_or_default()),
+                &failure.updated_at.to_rfc3339(),
+            ])
+            .map_err(e500)?;
+    }
+    let body = writer.into_inner().map_err(e500)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(format!(
+                "issue-{}-failures.csv",
+                issue_id
+            ))],
+        })
+        .body(body))
+}
+
+/// Defuse cells a spreadsheet would evaluate as a formula, by making them start with a quote.
+///
+/// Email addresses are up to subscribers, and errors up to the email provider.
+fn csv_cell(value: &str) -> Cow<'_, str> {
+    if value.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        Cow::Owned(format!("'{}", value))
+    } else {
+        Cow::Borrowed(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::csv_cell;
+
+    #[test]
+    fn cells_starting_like_a_formula_are_quoted() {
+        for value in ["=1+1", "+1", "-1", "@SUM(A1)", "\tx", "\rx"] {
+            assert_eq!(csv_cell(value), format!("'{}", value));
+        }
+    }
+
+    #[test]
+    fn other_cells_are_left_alone() {
+        for value in ["", "jane@example.com", "The recipient is inactive: a=b"] {
+            assert_eq!(csv_cell(value), value);
+        }
+    }
+}
@@ -0,0 +1,101 @@
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpRequest, HttpResponse};
+use serde::Deserialize;
+use sqlx::PgPool;
+
+use crate::domain::{ListSlug, SubscriberName};
+use crate::lists::{get_lists, insert_list};
+use crate::utils::{clear_flash_message, e500, flash_message_html, see_other_with_flash};
+
+pub async fn lists(
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let lists = get_lists(&pool).await.map_err(e500)?;
+
+    let mut rows = String::new();
+    for list in &lists {
+        rows.push_str(&format!(
+            r#"
+        <tr>
+            <td>{}</td>
+            <td>{}</td>
+            <td>{}</td>
+        </tr>"#,
+            htmlescape::encode_minimal(&list.name),
+            list.slug,
+            list.list_id,
+        ));
+    }
+
+    let mut response = HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Mailing lists</title>
+</head>
+<body>
+    {}
+    <table>
+        <tr>
+            <th>Name</th>
+            <th>Slug</th>
+            <th>List ID</th>
+        </tr>{}
+    </table>
+    <form action="/admin/lists" method="post">
+        <label>Name
+            <input type="text" placeholder="Enter the list name" name="name">
+        </label>
+        <label>Slug
+            <input type="text" placeholder="e.g. weekly-digest" name="slug">
+        </label>
+        <button type="submit">Create list</button>
+    </form>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+            flash_message_html(&request),
+            rows
+        ));
+    clear_flash_message(&mut response);
+    Ok(response)
+}
+
+#[derive(Deserialize)]
+pub struct FormData {
+    name: String,
+    slug: String,
+}
+
+#[tracing::instrument(name = "Create a mailing list", skip(form, pool))]
+pub async fn create_list(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let Ok(slug) = ListSlug::parse(&form.slug) else {
+        return Ok(see_other_with_flash(
+            "/admin/lists",
+            "The slug can only contain lowercase letters and digits, separated by single dashes.",
+        ));
+    };
+    // List names follow the same rules as subscriber names.
+    let Ok(name) = SubscriberName::parse(&form.name) else {
+        return Ok(see_other_with_flash(
+            "/admin/lists",
+            "The list name is not valid.",
+        ));
+    };
+
+    let message = match insert_list(&pool, &slug, name.as_ref())
+        .await
+        .map_err(e500)?
+    {
+        Some(_) => "The list has been created.",
+        None => "There is a list with the same slug already.",
+    };
+    Ok(see_other_with_flash("/admin/lists", message))
+}
@@ -0,0 +1,87 @@
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpRequest, HttpResponse};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::authentication::{clear_lockout as lift_lockout, get_active_lockouts, UserId};
+use crate::utils::{clear_flash_message, e500, flash_message_html, see_other_with_flash};
+
+pub async fn lockouts(
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let lockouts = get_active_lockouts(&pool).await.map_err(e500)?;
+
+    let mut rows = String::new();
+    for lockout in &lockouts {
+        rows.push_str(&format!(
+            r#"
+        <tr>
+            <td>{}</td>
+            <td>{}</td>
+            <td>{}</td>
+            <td>{}</td>
+            <td>{}</td>
+            <td>
+                <form action="/admin/lockouts/{}/clear" method="post">
+                    <button type="submit">Clear</button>
+                </form>
+            </td>
+        </tr>"#,
+            lockout.subject_kind,
+            htmlescape::encode_minimal(&lockout.subject),
+            lockout.n_failures,
+            lockout.locked_at.to_rfc3339(),
+            lockout.locked_until.to_rfc3339(),
+            lockout.lockout_id,
+        ));
+    }
+
+    let mut response = HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Login lockouts</title>
+</head>
+<body>
+    {}
+    <table>
+        <tr>
+            <th>Locked out by</th>
+            <th>Subject</th>
+            <th>Failed attempts</th>
+            <th>Locked at</th>
+            <th>Locked until</th>
+            <th></th>
+        </tr>{}
+    </table>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+            flash_message_html(&request),
+            rows
+        ));
+    clear_flash_message(&mut response);
+    Ok(response)
+}
+
+pub async fn clear_lockout(
+    lockout_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let cleared = lift_lockout(&pool, lockout_id.into_inner(), *user_id)
+        .await
+        .map_err(e500)?;
+
+    let message = if cleared {
+        "The lockout has been cleared."
+    } else {
+        "The lockout was already over."
+    };
+    Ok(see_other_with_flash("/admin/lockouts", message))
+}
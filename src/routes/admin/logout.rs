@@ -0,0 +1,9 @@
+use actix_web::HttpResponse;
+
+use crate::session_state::TypedSession;
+use crate::utils::see_other_with_flash;
+
+pub async fn log_out(session: TypedSession) -> HttpResponse {
+    session.log_out();
+    see_other_with_flash("/login", "You have successfully logged out.")
+}
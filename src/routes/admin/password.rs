@@ -0,0 +1,132 @@
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpRequest, HttpResponse};
+use secrecy::{ExposeSecret, Secret};
+use serde::Deserialize;
+use sqlx::PgPool;
+
+use super::dashboard::get_username;
+use crate::authentication::{
+    change_password as store_new_password, client_ip, validate_credentials_with_throttling,
+    AuthError, Credentials, PasswordHashing, PasswordPolicy, UserId,
+};
+use crate::configuration::LoginThrottlingSettings;
+use crate::utils::{
+    clear_flash_message, e500, flash_message_html, see_other_with_flash, too_many_requests,
+};
+
+pub async fn change_password_form(request: HttpRequest) -> HttpResponse {
+    let mut response = HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Change Password</title>
+</head>
+<body>
+    {}
+    <form action="/admin/password" method="post">
+        <label>Current password
+            <input type="password" placeholder="Enter current password" name="current_password">
+        </label>
+        <br>
+        <label>New password
+            <input type="password" placeholder="Enter new password" name="new_password">
+        </label>
+        <br>
+        <label>Confirm new password
+            <input type="password" placeholder="Type the new password again" name="new_password_check">
+        </label>
+        <br>
+        <button type="submit">Change password</button>
+    </form>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+            flash_message_html(&request)
+        ));
+    clear_flash_message(&mut response);
+    response
+}
+
+#[derive(Deserialize)]
+pub struct FormData {
+    current_password: Secret<String>,
+    new_password: Secret<String>,
+    new_password_check: Secret<String>,
+}
+
+#[tracing::instrument(
+    name = "Change password",
+    skip(form, pool, password_policy, password_hashing, login_throttling, user_id, request),
+    fields(user_id = %*user_id)
+)]
+pub async fn change_password(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+    password_policy: web::Data<PasswordPolicy>,
+    password_hashing: web::Data<PasswordHashing>,
+    login_throttling: web::Data<LoginThrottlingSettings>,
+    user_id: web::ReqData<UserId>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+
+    if form.new_password.expose_secret() != form.new_password_check.expose_secret() {
+        return Ok(see_other_with_flash(
+            "/admin/password",
+            "You entered two different new passwords - the field values must match.",
+        ));
+    }
+    if let Err(e) = password_policy.check(&form.new_password) {
+        return Ok(see_other_with_flash("/admin/password", &e.to_string()));
+    }
+
+    let username = get_username(*user_id, &pool).await.map_err(e500)?;
+    let credentials = Credentials {
+        username,
+        password: form.0.current_password,
+    };
+    // A stolen session must not become a way around the login throttling.
+    if let Err(e) = validate_credentials_with_throttling(
+        credentials,
+        &client_ip(&request, &login_throttling.trusted_proxies),
+        &password_hashing,
+        &login_throttling,
+        &pool,
+    )
+    .await
+    {
+        return match e {
+            AuthError::InvalidCredentials(_) => Ok(see_other_with_flash(
+                "/admin/password",
+                "The current password is incorrect.",
+            )),
+            AuthError::TooManyAttempts { retry_after } => Ok(too_many_requests(retry_after)
+                .content_type(ContentType::html())
+                .body(
+                    r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Too many attempts</title>
+</head>
+<body>
+    <p>Too many failed attempts. Please try again later.</p>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+                )),
+            AuthError::UnexpectedError(_) => Err(e500(e)),
+        };
+    }
+
+    store_new_password(*user_id, form.0.new_password, &password_hashing, &pool)
+        .await
+        .map_err(e500)?;
+    Ok(see_other_with_flash(
+        "/admin/password",
+        "Your password has been changed.",
+    ))
+}
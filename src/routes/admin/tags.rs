@@ -0,0 +1,123 @@
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpRequest, HttpResponse};
+use serde::Deserialize;
+use sqlx::PgPool;
+
+use crate::domain::TagName;
+use crate::tags::{get_tags, tag_subscribers, untag_subscribers};
+use crate::utils::{clear_flash_message, e500, flash_message_html, see_other_with_flash};
+
+pub async fn tags(
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let tags = get_tags(&pool).await.map_err(e500)?;
+
+    let mut rows = String::new();
+    for tag in &tags {
+        rows.push_str(&format!(
+            r#"
+        <tr>
+            <td>{}</td>
+            <td>{}</td>
+        </tr>"#,
+            tag.name, tag.n_subscribers,
+        ));
+    }
+
+    let mut response = HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Subscriber tags</title>
+</head>
+<body>
+    {}
+    <table>
+        <tr>
+            <th>Tag</th>
+            <th>Subscribers</th>
+        </tr>{}
+    </table>
+    <form method="post">
+        <label>Tag
+            <input type="text" placeholder="e.g. beta" name="tag">
+        </label>
+        <br>
+        <label>Email addresses, one per line
+            <textarea name="emails" rows="10" cols="50"></textarea>
+        </label>
+        <br>
+        <button type="submit" formaction="/admin/tags/add">Add tag</button>
+        <button type="submit" formaction="/admin/tags/remove">Remove tag</button>
+    </form>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+            flash_message_html(&request),
+            rows
+        ));
+    clear_flash_message(&mut response);
+    Ok(response)
+}
+
+#[derive(Deserialize)]
+pub struct FormData {
+    tag: String,
+    /// Separated by line breaks, commas or spaces.
+    emails: String,
+}
+
+impl FormData {
+    fn emails(&self) -> Vec<String> {
+        self.emails
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|email| !email.is_empty())
+            .map(str::to_owned)
+            .collect()
+    }
+}
+
+#[tracing::instrument(name = "Tag subscribers", skip(form, pool), fields(tag = %form.tag))]
+pub async fn add_tag(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let Ok(tag) = TagName::parse(&form.tag) else {
+        return Ok(invalid_tag_name());
+    };
+    let n_tagged = tag_subscribers(&pool, &tag, &form.emails())
+        .await
+        .map_err(e500)?;
+    Ok(see_other_with_flash(
+        "/admin/tags",
+        &format!("{} subscriber(s) tagged with {}.", n_tagged, tag),
+    ))
+}
+
+#[tracing::instrument(name = "Untag subscribers", skip(form, pool), fields(tag = %form.tag))]
+pub async fn remove_tag(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let Ok(tag) = TagName::parse(&form.tag) else {
+        return Ok(invalid_tag_name());
+    };
+    let n_untagged = untag_subscribers(&pool, &tag, &form.emails())
+        .await
+        .map_err(e500)?;
+    Ok(see_other_with_flash(
+        "/admin/tags",
+        &format!("{} subscriber(s) untagged from {}.", n_untagged, tag),
+    ))
+}
+
+fn invalid_tag_name() -> HttpResponse {
+    see_other_with_flash(
+        "/admin/tags",
+        "Tags can only contain lowercase letters and digits, separated by single dashes or underscores.",
+    )
+}
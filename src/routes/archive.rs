@@ -0,0 +1,116 @@
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use serde::Deserialize;
+use sqlx::PgPool;
+
+use crate::archive::{get_archived_issue, get_archived_issues};
+use crate::utils::e500;
+
+const ISSUES_PER_PAGE: u32 = 20;
+
+#[derive(Deserialize, Debug)]
+pub struct Pagination {
+    /// Starts from 1.
+    page: Option<u32>,
+}
+
+#[tracing::instrument(name = "Show the archive", skip(pool))]
+pub async fn archive(
+    query: web::Query<Pagination>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let page = query.page.unwrap_or(1).max(1);
+    // One more issue than we show, to know whether there is a next page.
+    let mut issues = get_archived_issues(
+        &pool,
+        i64::from(ISSUES_PER_PAGE) + 1,
+        i64::from(page - 1) * i64::from(ISSUES_PER_PAGE),
+    )
+    .await
+    .map_err(e500)?;
+    let has_next_page = issues.len() > ISSUES_PER_PAGE as usize;
+    issues.truncate(ISSUES_PER_PAGE as usize);
+
+    let mut items = String::new();
+    for issue in &issues {
+        items.push_str(&format!(
+            r#"
+        <li><a href="/archive/{}">{}</a>, <time datetime="{}">{}</time></li>"#,
+            issue.slug,
+            htmlescape::encode_minimal(&issue.title),
+            issue.published_at.to_rfc3339(),
+            issue.published_at.format("%B %-d, %Y"),
+        ));
+    }
+    if issues.is_empty() {
+        items.push_str("\n        <li>There are no issues here.</li>");
+    }
+    let mut navigation = Vec::new();
+    if page > 1 {
+        navigation.push(format!(
+            r#"<a href="/archive?page={}">Newer issues</a>"#,
+            page - 1
+        ));
+    }
+    if has_next_page {
+        navigation.push(format!(
+            r#"<a href="/archive?page={}">Older issues</a>"#,
+            page + 1
+        ));
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Newsletter archive</title>
+</head>
+<body>
+    <h1>Past issues</h1>
+    <ul>{}
+    </ul>
+    <nav>{}</nav>
+</body>
+</html>"#,
+            items,
+            navigation.join(" | ")
+        )))
+}
+
+#[tracing::instrument(name = "Show an archived issue", skip(pool))]
+pub async fn archived_issue(
+    slug: web::Path<String>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let Some(issue) = get_archived_issue(&pool, &slug).await.map_err(e500)? else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>{}</title>
+</head>
+<body>
+    <p><a href="/archive">&lt;- All issues</a></p>
+    <h1>{}</h1>
+    <p><time datetime="{}">{}</time></p>
+    <article>
+{}
+    </article>
+</body>
+</html>"#,
+            htmlescape::encode_minimal(&issue.title),
+            htmlescape::encode_minimal(&issue.title),
+            issue.published_at.to_rfc3339(),
+            issue.published_at.format("%B %-d, %Y"),
+            issue.render_html()
+        )))
+}
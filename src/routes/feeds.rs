@@ -0,0 +1,176 @@
+//! RSS and Atom feeds of the archive, for readers who would rather not use email.
+use std::time::{Duration, SystemTime};
+
+use actix_web::http::header::{
+    ETag, EntityTag, HttpDate, IfModifiedSince, IfNoneMatch, LastModified,
+};
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse};
+use atom_syndication::{Content, Entry, Feed, Link, Text};
+use chrono::{DateTime, Utc};
+use rss::{Channel, Guid, Item};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+
+use crate::archive::{get_archived_issues, ArchivedIssue};
+use crate::startup::ApplicationBaseUrl;
+use crate::utils::e500;
+
+const ISSUES_PER_FEED: i64 = 20;
+const FEED_TITLE: &str = "Newsletter";
+const FEED_DESCRIPTION: &str = "The latest issues of our newsletter.";
+
+#[tracing::instrument(name = "Serve the RSS feed", skip_all)]
+pub async fn rss_feed(
+    pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issues = get_archived_issues(&pool, ISSUES_PER_FEED, 0)
+        .await
+        .map_err(e500)?;
+    let items = issues
+        .iter()
+        .map(|issue| {
+            let link = issue_link(&base_url, issue);
+            Item {
+                title: Some(issue.title.clone()),
+                link: Some(link.clone()),
+                description: Some(issue.render_html()),
+                guid: Some(Guid {
+                    value: link,
+                    permalink: true,
+                }),
+                pub_date: Some(issue.published_at.to_rfc2822()),
+                ..Default::default()
+            }
+        })
+        .collect();
+    let channel = Channel {
+        title: FEED_TITLE.into(),
+        link: format!("{}/archive", base_url.0),
+        description: FEED_DESCRIPTION.into(),
+        last_build_date: last_modified(&issues).map(|date| date.to_rfc2822()),
+        items,
+        ..Default::default()
+    };
+    Ok(feed_response(
+        &request,
+        "application/rss+xml; charset=utf-8",
+        channel.to_string(),
+        last_modified(&issues),
+    ))
+}
+
+#[tracing::instrument(name = "Serve the Atom feed", skip_all)]
+pub async fn atom_feed(
+    pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issues = get_archived_issues(&pool, ISSUES_PER_FEED, 0)
+        .await
+        .map_err(e500)?;
+    let entries = issues
+        .iter()
+        .map(|issue| {
+            let link = issue_link(&base_url, issue);
+            Entry {
+                title: Text::plain(issue.title.clone()),
+                id: link.clone(),
+                updated: issue.published_at.fixed_offset(),
+                published: Some(issue.published_at.fixed_offset()),
+                links: vec![Link {
+                    href: link,
+                    rel: "alternate".into(),
+                    ..Default::default()
+                }],
+                content: Some(Content {
+                    value: Some(issue.render_html()),
+                    content_type: Some("html".into()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }
+        })
+        .collect();
+    let archive_link = format!("{}/archive", base_url.0);
+    let feed = Feed {
+        title: Text::plain(FEED_TITLE),
+        id: archive_link.clone(),
+        // An empty feed was never updated.
+        updated: last_modified(&issues)
+            .unwrap_or(DateTime::UNIX_EPOCH)
+            .fixed_offset(),
+        subtitle: Some(Text::plain(FEED_DESCRIPTION)),
+        links: vec![
+            Link {
+                href: format!("{}/feed.atom", base_url.0),
+                rel: "self".into(),
+                ..Default::default()
+            },
+            Link {
+                href: archive_link,
+                rel: "alternate".into(),
+                ..Default::default()
+            },
+        ],
+        entries,
+        ..Default::default()
+    };
+    Ok(feed_response(
+        &request,
+        "application/atom+xml; charset=utf-8",
+        feed.to_string(),
+        last_modified(&issues),
+    ))
+}
+
+fn issue_link(base_url: &ApplicationBaseUrl, issue: &ArchivedIssue) -> String {
+    format!("{}/archive/{}", base_url.0, issue.slug)
+}
+
+/// Issues are listed from the most recent, and never change once published.
+fn last_modified(issues: &[ArchivedIssue]) -> Option<DateTime<Utc>> {
+    issues.first().map(|issue| issue.published_at)
+}
+
+/// Serve `body`, or a 304 if the client's copy is still fresh.
+///
+/// The `ETag` is a hash of the body, so it changes whenever the feed does.
+/// `If-None-Match` takes precedence over `If-Modified-Since`, as per RFC 9110.
+fn feed_response(
+    request: &HttpRequest,
+    content_type: &str,
+    body: String,
+    last_modified: Option<DateTime<Utc>>,
+) -> HttpResponse {
+    let etag = EntityTag::new_strong(hex::encode(Sha256::digest(body.as_bytes())));
+    // HTTP dates have a one second resolution.
+    let last_modified = last_modified.map(|date| {
+        HttpDate::from(SystemTime::UNIX_EPOCH + Duration::from_secs(date.timestamp() as u64))
+    });
+
+    let is_fresh = match request.get_header::<IfNoneMatch>() {
+        Some(IfNoneMatch::Any) => true,
+        Some(IfNoneMatch::Items(etags)) => etags.iter().any(|e| e.weak_eq(&etag)),
+        None => match (request.get_header::<IfModifiedSince>(), last_modified) {
+            (Some(IfModifiedSince(since)), Some(last_modified)) => last_modified <= since,
+            _ => false,
+        },
+    };
+
+    let mut response = if is_fresh {
+        HttpResponse::NotModified()
+    } else {
+        HttpResponse::Ok()
+    };
+    response.insert_header(ETag(etag));
+    if let Some(last_modified) = last_modified {
+        response.insert_header(LastModified(last_modified));
+    }
+    if is_fresh {
+        response.finish()
+    } else {
+        response.content_type(content_type).body(body)
+    }
+}
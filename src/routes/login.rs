@@ -0,0 +1,144 @@
+use std::time::Duration;
+
+use actix_web::error::InternalError;
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpRequest, HttpResponse};
+use secrecy::Secret;
+use serde::Deserialize;
+use sqlx::PgPool;
+use tracing::instrument;
+
+use crate::authentication::{
+    client_ip, validate_credentials_with_throttling, AuthError, Credentials, PasswordHashing,
+};
+use crate::configuration::LoginThrottlingSettings;
+use crate::routes::error_chain_fmt;
+use crate::session_state::TypedSession;
+use crate::utils::{
+    clear_flash_message, flash_message_html, see_other, see_other_with_flash, too_many_requests,
+};
+
+#[derive(thiserror::Error)]
+pub enum LoginError {
+    #[error("Authentication failed")]
+    AuthError(#[source] anyhow::Error),
+    #[error("Too many failed login attempts")]
+    TooManyAttempts { retry_after: Duration },
+    #[error("Something went wrong")]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for LoginError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+pub async fn login_form(request: HttpRequest) -> HttpResponse {
+    let mut response = HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Login</title>
+</head>
+<body>
+    {}
+    <form action="/login" method="post">
+        <label>Username
+            <input type="text" placeholder="Enter Username" name="username">
+        </label>
+        <label>Password
+            <input type="password" placeholder="Enter Password" name="password">
+        </label>
+        <button type="submit">Login</button>
+    </form>
+</body>
+</html>"#,
+            flash_message_html(&request)
+        ));
+    clear_flash_message(&mut response);
+    response
+}
+
+#[derive(Deserialize)]
+pub struct FormData {
+    username: String,
+    password: Secret<String>,
+}
+
+#[instrument(
+    skip(form, pool, password_hashing, login_throttling, session, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn login(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+    password_hashing: web::Data<PasswordHashing>,
+    login_throttling: web::Data<LoginThrottlingSettings>,
+    session: TypedSession,
+    request: HttpRequest,
+) -> Result<HttpResponse, InternalError<LoginError>> {
+    let credentials = Credentials {
+        username: form.0.username,
+        password: form.0.password,
+    };
+    tracing::Span::current().record("username", tracing::field::display(&credentials.username));
+
+    match validate_credentials_with_throttling(
+        credentials,
+        &client_ip(&request, &login_throttling.trusted_proxies),
+        &password_hashing,
+        &login_throttling,
+        &pool,
+    )
+    .await
+    {
+        Ok(user_id) => {
+            tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+            session.renew();
+            session
+                .insert_user_id(user_id)
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e.into())))?;
+            Ok(see_other("/admin/dashboard"))
+        }
+        Err(e) => {
+            let e = match e {
+                AuthError::InvalidCredentials(_) => LoginError::AuthError(e.into()),
+                AuthError::TooManyAttempts { retry_after } => {
+                    return Err(too_many_attempts(retry_after));
+                }
+                AuthError::UnexpectedError(_) => LoginError::UnexpectedError(e.into()),
+            };
+            Err(login_redirect(e))
+        }
+    }
+}
+
+/// Send the user back to the login form, telling them what went wrong.
+fn login_redirect(e: LoginError) -> InternalError<LoginError> {
+    let response = see_other_with_flash("/login", &e.to_string());
+    InternalError::from_response(e, response)
+}
+
+/// Redirecting to the login form would invite yet another attempt:
+/// tell the user to come back later instead.
+fn too_many_attempts(retry_after: Duration) -> InternalError<LoginError> {
+    let response = too_many_requests(retry_after)
+        .content_type(ContentType::html())
+        .body(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Too many login attempts</title>
+</head>
+<body>
+    <p>Too many failed login attempts. Please try again later.</p>
+</body>
+</html>"#,
+        );
+    InternalError::from_response(LoginError::TooManyAttempts { retry_after }, response)
+}
@@ -3,21 +3,43 @@ use actix_web::http::header::{HeaderMap, HeaderValue};
 use actix_web::http::{header, StatusCode};
 use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
 use anyhow::Context;
-use argon2::{Argon2, PasswordHash, PasswordVerifier};
 use base64::Engine;
-use secrecy::{ExposeSecret, Secret};
+use chrono::{DateTime, Utc};
+use secrecy::Secret;
 use serde::Deserialize;
-use sqlx::PgPool;
+use sqlx::{Executor, PgPool, Postgres, Transaction};
 use tracing::instrument;
+use uuid::Uuid;
 
-use crate::domain::SubscriberEmail;
-use crate::email_client::EmailClient;
+use crate::authentication::{
+    client_ip, validate_credentials_with_throttling, AuthError, Credentials, PasswordHashing,
+};
+use crate::configuration::{IdempotencySettings, LoginThrottlingSettings};
+use crate::domain::IssueSlug;
+use crate::idempotency::{save_response, try_processing, IdempotencyKey, NextAction};
+use crate::issue_delivery_worker::enqueue_delivery_tasks;
+use crate::lists::{get_default_list_id, list_exists};
+use crate::markdown;
 use crate::routes::error_chain_fmt;
+use crate::segment::Segment;
+use crate::template::Template;
+use crate::utils::too_many_requests;
 
 #[derive(thiserror::Error)]
+#[allow(clippy::enum_variant_names)]
 pub enum PublishError {
     #[error("Authentication failed")]
     AuthError(#[source] anyhow::Error),
+    #[error("Too many failed authentication attempts")]
+    TooManyAttempts { retry_after: std::time::Duration },
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("There is no such newsletter issue.")]
+    UnknownIssue,
+    #[error("This newsletter issue is not scheduled anymore.")]
+    NotScheduled,
+    #[error("This newsletter issue is not a draft anymore.")]
+    NotADraft,
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -41,6 +63,16 @@ impl ResponseError for PublishError {
 
                 response
             }
+            PublishError::TooManyAttempts { retry_after } => {
+                too_many_requests(*retry_after).finish()
+            }
+            PublishError::ValidationError(message) => {
+                HttpResponse::BadRequest().body(message.clone())
+            }
+            PublishError::UnknownIssue => HttpResponse::NotFound().body(self.to_string()),
+            PublishError::NotScheduled | PublishError::NotADraft => {
+                HttpResponse::Conflict().body(self.to_string())
+            }
             PublishError::UnexpectedError(_) => {
                 HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR)
             }
@@ -52,93 +84,305 @@ impl ResponseError for PublishError {
 pub struct BodyData {
     title: String,
     content: Content,
+    /// The list to send the issue to. The default list if missing.
+    list_id: Option<Uuid>,
+    /// Only send the issue to the subscribers matching this expression,
+    /// e.g. `beta AND NOT churned`. See `crate::segment`.
+    segment: Option<String>,
+    /// Hold the issue back until then. Issues due already are sent right away.
+    send_at: Option<DateTime<Utc>>,
+    /// Leave the issue out of the public archive. See `is_private` for the default.
+    private: Option<bool>,
 }
 
+/// Either `markdown`, or both `html` and `text`.
+///
+/// Either way, the bodies are templates, rendered for each recipient. See `crate::template`.
 #[derive(Deserialize)]
 pub struct Content {
-    html: String,
-    text: String,
+    html: Option<String>,
+    text: Option<String>,
+    markdown: Option<String>,
+}
+
+impl Content {
+    /// Return the HTML and plain text bodies, once checked to be valid templates.
+    ///
+    /// Templates are checked now rather than once per recipient in the delivery worker.
+    pub fn bodies(&self) -> Result<(String, String), PublishError> {
+        match (&self.markdown, &self.html, &self.text) {
+            (Some(markdown), None, None) => {
+                // Check the source first, for errors to point at what the publisher wrote.
+                validate_template(markdown, "content.markdown")?;
+                let html = markdown::to_html(markdown);
+                let text = markdown::to_text(markdown);
+                validate_template(&html, "content.markdown")?;
+                validate_template(&text, "content.markdown")?;
+                Ok((html, text))
+            }
+            (None, Some(html), Some(text)) => {
+                validate_template(html, "content.html")?;
+                validate_template(text, "content.text")?;
+                Ok((html.clone(), text.clone()))
+            }
+            _ => Err(PublishError::ValidationError(
+                "Provide either content.markdown, or both content.html and content.text.".into(),
+            )),
+        }
+    }
+}
+
+fn validate_template(source: &str, field: &str) -> Result<(), PublishError> {
+    Template::parse(source).map_err(|e| {
+        PublishError::ValidationError(format!("Invalid template in {}: {}", field, e))
+    })?;
+    Ok(())
 }
 
 #[instrument(
     name = "Publish a newsletter issue",
-    skip(body, pool, email_client, request)
+    skip(
+        body,
+        pool,
+        idempotency_settings,
+        password_hashing,
+        login_throttling,
+        request
+    ),
+    fields(user_id = tracing::field::Empty)
 )]
 pub async fn publish_newsletter(
     body: web::Json<BodyData>,
     pool: web::Data<PgPool>,
-    email_client: web::Data<EmailClient>,
+    idempotency_settings: web::Data<IdempotencySettings>,
+    password_hashing: web::Data<PasswordHashing>,
+    login_throttling: web::Data<LoginThrottlingSettings>,
     request: HttpRequest,
 ) -> Result<HttpResponse, PublishError> {
-    let credentials = basic_authentication(request.headers()).map_err(PublishError::AuthError)?;
-    let _user_id = validate_credentials(credentials, &pool).await?;
-
-    let subscribers = get_confirmed_subscribers(&pool).await?;
-    for subscriber in subscribers {
-        match subscriber {
-            Ok(subscriber) => {
-                email_client
-                    .send_email(
-                        &subscriber.email,
-                        &body.title,
-                        &body.content.html,
-                        &body.content.text,
-                    )
-                    .await
-                    // `with_context` differs from `context` in that it is evaluated lazily.
-                    .with_context(|| {
-                        format!(
-                            "Failed to send newsletter to subscriber with email: {}",
-                            subscriber.email
-                        )
-                    })?;
-            }
-            Err(error) => {
-                tracing::warn!(
-                    // We record the error chain as a structured field
-                    // on the log record.
-                    error.cause_chain = ?error,
-                    // Using `\` to split a long string literal over
-                    // two lines, without creating a `\n` character.
-                    "Skipping a confirmed subscriber. \
-                    Their stored contact details are invalid",
-                )
-            }
+    let user_id =
+        authenticate_publisher(&request, &password_hashing, &login_throttling, &pool).await?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    let idempotency_key = get_idempotency_key(request.headers())?;
+    let segment = parse_segment(body.segment.as_deref())?;
+    let (html_content, text_content) = body.content.bodies()?;
+    let send_at = body.send_at.filter(|send_at| *send_at > Utc::now());
+    let mut transaction = match &idempotency_key {
+        Some(idempotency_key) => match try_processing(
+            &pool,
+            idempotency_key,
+            user_id,
+            idempotency_settings.expiration(),
+        )
+        .await?
+        {
+            NextAction::StartProcessing(transaction) => transaction,
+            NextAction::ReturnSavedResponse(saved_response) => return Ok(saved_response),
+        },
+        None => pool
+            .begin()
+            .await
+            .context("Failed to acquire a Postgres connection from the pool")?,
+    };
+
+    let list_id = resolve_list_id(&mut transaction, body.list_id).await?;
+
+    // Delivery happens in the background (see `issue_delivery_worker`):
+    // here we only persist the issue and one delivery task per confirmed member of the list.
+    // Scheduled issues get their delivery tasks once due (see `issue_scheduler`).
+    let issue_id = insert_newsletter_issue(
+        &mut transaction,
+        list_id,
+        &body.title,
+        &html_content,
+        &text_content,
+        body.segment.as_deref(),
+        send_at,
+        is_private(body.private, body.segment.as_deref()),
+    )
+    .await
+    .context("Failed to store newsletter issue details")?;
+    let response = match send_at {
+        None => {
+            enqueue_delivery_tasks(&mut transaction, issue_id, segment.as_ref())
+                .await
+                .context("Failed to enqueue delivery tasks")?;
+            HttpResponse::Ok().finish()
+        }
+        Some(send_at) => HttpResponse::Accepted().json(serde_json::json!({
+            "newsletter_issue_id": issue_id,
+            "send_at": send_at,
+        })),
+    };
+    let response = match &idempotency_key {
+        Some(idempotency_key) => save_response(transaction, idempotency_key, user_id, response)
+            .await
+            .context("Failed to save the response for the idempotency key")?,
+        None => {
+            transaction
+                .commit()
+                .await
+                .context("Failed to commit SQL transaction to publish a newsletter issue.")?;
+            response
         }
+    };
+    Ok(response)
+}
+
+/// Issues sent to a segment were written for part of the audience only:
+/// they are kept out of the public archive unless told otherwise.
+pub fn is_private(private: Option<bool>, segment: Option<&str>) -> bool {
+    private.unwrap_or(segment.is_some())
+}
+
+pub fn parse_segment(segment: Option<&str>) -> Result<Option<Segment>, PublishError> {
+    segment
+        .map(Segment::parse)
+        .transpose()
+        .map_err(|e| PublishError::ValidationError(e.to_string()))
+}
+
+/// Return the id of the list an issue is sent to: `list_id` if it exists, the default list if missing.
+pub async fn resolve_list_id(
+    transaction: &mut Transaction<'_, Postgres>,
+    list_id: Option<Uuid>,
+) -> Result<Uuid, PublishError> {
+    let Some(list_id) = list_id else {
+        return Ok(get_default_list_id(transaction)
+            .await
+            .context("Failed to retrieve the default list")?);
+    };
+    if !list_exists(transaction, list_id)
+        .await
+        .context("Failed to look up the list")?
+    {
+        return Err(PublishError::ValidationError(
+            "There is no such list.".into(),
+        ));
     }
-    Ok(HttpResponse::Ok().finish())
+    Ok(list_id)
 }
 
-struct ConfirmedSubscriber {
-    email: SubscriberEmail,
+/// Tell apart unknown issues from issues in another state than the one an operation expects.
+///
+/// `found` is whether the operation found the issue in the expected state,
+/// `conflict` the error to return if it exists nonetheless.
+pub async fn ensure_issue_was_found(
+    pool: &PgPool,
+    issue_id: Uuid,
+    found: bool,
+    conflict: PublishError,
+) -> Result<(), PublishError> {
+    if found {
+        return Ok(());
+    }
+    let exists = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM newsletter_issues WHERE newsletter_issue_id = $1
+        ) as "exists!"
+        "#,
+        issue_id
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to look up the issue.")?;
+    Err(if exists {
+        conflict
+    } else {
+        PublishError::UnknownIssue
+    })
 }
 
-#[instrument(name = "Get confirmed subscribers", skip(pool))]
-async fn get_confirmed_subscribers(
+/// Check the Basic credentials of the request, subject to the same throttling as the login form.
+#[instrument(skip_all)]
+pub async fn authenticate_publisher(
+    request: &HttpRequest,
+    password_hashing: &PasswordHashing,
+    login_throttling: &LoginThrottlingSettings,
     pool: &PgPool,
-    // We are returning a `Vec` of `Result`s in the happy case.
-    // This allows the caller to bubble up errors due to network issues or other
-    // transient failures using the `?` operator, while the compiler
-    // forces them to handle the subtler mapping error.
-    // See http://sled.rs/errors.html for a deep-dive about this technique.
-) -> Result<Vec<Result<ConfirmedSubscriber, anyhow::Error>>, anyhow::Error> {
-    let confirmed_subscribers =
-        sqlx::query!(r#"SELECT email FROM subscriptions WHERE status = 'confirmed'"#)
-            .fetch_all(pool)
-            .await?
-            .into_iter()
-            .map(|row| match SubscriberEmail::parse(row.email) {
-                Ok(email) => Ok(ConfirmedSubscriber { email }),
-                Err(error) => Err(anyhow::anyhow!(error)),
-            })
-            .collect();
-
-    Ok(confirmed_subscribers)
-}
-
-struct Credentials {
-    username: String,
-    password: Secret<String>,
+) -> Result<Uuid, PublishError> {
+    let credentials = basic_authentication(request.headers()).map_err(PublishError::AuthError)?;
+    validate_credentials_with_throttling(
+        credentials,
+        &client_ip(request, &login_throttling.trusted_proxies),
+        password_hashing,
+        login_throttling,
+        pool,
+    )
+    .await
+    .map_err(|e| match e {
+        AuthError::InvalidCredentials(_) => PublishError::AuthError(e.into()),
+        AuthError::TooManyAttempts { retry_after } => PublishError::TooManyAttempts { retry_after },
+        AuthError::UnexpectedError(_) => PublishError::UnexpectedError(e.into()),
+    })
+}
+
+/// The `Idempotency-Key` header is optional: requests without it are never deduplicated.
+fn get_idempotency_key(headers: &HeaderMap) -> Result<Option<IdempotencyKey>, PublishError> {
+    let Some(header_value) = headers.get("Idempotency-Key") else {
+        return Ok(None);
+    };
+    let idempotency_key = header_value
+        .to_str()
+        .map_err(|_| {
+            PublishError::ValidationError("The Idempotency-Key header is not valid UTF-8.".into())
+        })?
+        .to_owned()
+        .try_into()
+        .map_err(|e: anyhow::Error| PublishError::ValidationError(e.to_string()))?;
+    Ok(Some(idempotency_key))
+}
+
+#[instrument(skip_all)]
+#[allow(clippy::too_many_arguments)]
+async fn insert_newsletter_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    list_id: Uuid,
+    title: &str,
+    html_content: &str,
+    text_content: &str,
+    segment: Option<&str>,
+    send_at: Option<DateTime<Utc>>,
+    private: bool,
+) -> Result<Uuid, sqlx::Error> {
+    let newsletter_issue_id = Uuid::new_v4();
+    let slug = IssueSlug::new(title, newsletter_issue_id);
+    let (status, published_at) = match send_at {
+        Some(_) => ("scheduled", None),
+        None => ("published", Some(Utc::now())),
+    };
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues (
+            newsletter_issue_id,
+            list_id,
+            title,
+            text_content,
+            html_content,
+            segment,
+            status,
+            send_at,
+            published_at,
+            slug,
+            private
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+        "#,
+        newsletter_issue_id,
+        list_id,
+        title,
+        text_content,
+        html_content,
+        segment,
+        status,
+        send_at,
+        published_at,
+        slug.as_ref(),
+        private
+    );
+    transaction.execute(query).await?;
+    Ok(newsletter_issue_id)
 }
 
 fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, anyhow::Error> {
@@ -170,46 +414,3 @@ fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, anyhow::Erro
         password: Secret::new(password),
     })
 }
-
-#[instrument(name = "Validate credentials", skip(credentials, pool))]
-async fn validate_credentials(
-    credentials: Credentials,
-    pool: &PgPool,
-) -> Result<uuid::Uuid, PublishError> {
-    let (user_id, expected_password_hash) = get_stored_credentials(&credentials.username, pool)
-        .await
-        .map_err(PublishError::UnexpectedError)?
-        .ok_or_else(|| PublishError::AuthError(anyhow::anyhow!("Unknown username.")))?;
-
-    let expected_password_hash = PasswordHash::new(expected_password_hash.expose_secret())
-        .map_err(|e| anyhow::anyhow!(e))
-        .context("Failed to parse the stored password hash.")
-        .map_err(PublishError::UnexpectedError)?;
-
-    Argon2::default()
-        .verify_password(
-            credentials.password.expose_secret().as_bytes(),
-            &expected_password_hash,
-        )
-        .map_err(|e| anyhow::anyhow!(e))
-        .context("Invalid password.")
-        .map_err(PublishError::AuthError)?;
-
-    Ok(user_id)
-}
-
-async fn get_stored_credentials(
-    username: &str,
-    pool: &PgPool,
-) -> Result<Option<(uuid::Uuid, Secret<String>)>, anyhow::Error> {
-    let row = sqlx::query!(
-        r#"SELECT user_id, password_hash FROM users WHERE username = $1"#,
-        username
-    )
-    .fetch_optional(pool)
-    .await
-    .context("Failed to perform a query to retrieve stored credentials.")?
-    .map(|row| (row.user_id, Secret::new(row.password_hash)));
-
-    Ok(row)
-}
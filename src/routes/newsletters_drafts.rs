@@ -0,0 +1,386 @@
+//! Issues are written as drafts, previewed, test-sent, and then published as they are stored.
+use actix_web::http::header::{ContentType, CONTENT_SECURITY_POLICY};
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use sqlx::{Executor, PgPool};
+use tracing::instrument;
+use uuid::Uuid;
+
+use super::newsletters::{
+    authenticate_publisher, ensure_issue_was_found, is_private, parse_segment, resolve_list_id,
+    Content, PublishError,
+};
+use crate::authentication::PasswordHashing;
+use crate::configuration::LoginThrottlingSettings;
+use crate::domain::{IssueSlug, SubscriberEmail};
+use crate::email_client::EmailSender;
+use crate::issue_delivery_worker::enqueue_delivery_tasks;
+use crate::routes::{preferences_link, unsubscribe_link};
+use crate::startup::{ApplicationBaseUrl, HmacSecret};
+use crate::template::{render_email, TemplateContext};
+
+/// Test sends go to a handful of colleagues, not to an audience.
+const MAX_TEST_RECIPIENTS: usize = 10;
+
+/// Stands in for the subscriber in previews and test sends.
+const SAMPLE_SUBSCRIBER_NAME: &str = "Jane Doe";
+const SAMPLE_SUBSCRIBER_EMAIL: &str = "jane.doe@example.com";
+
+#[derive(Deserialize)]
+pub struct DraftData {
+    title: String,
+    content: Content,
+    /// The list to send the issue to. The default list if missing.
+    list_id: Option<Uuid>,
+    /// Only send the issue to the subscribers matching this expression. See `crate::segment`.
+    segment: Option<String>,
+    /// Leave the issue out of the public archive. See `is_private` for the default.
+    private: Option<bool>,
+}
+
+#[instrument(
+    name = "Create a newsletter draft",
+    skip_all,
+    fields(user_id = tracing::field::Empty)
+)]
+pub async fn create_draft(
+    body: web::Json<DraftData>,
+    pool: web::Data<PgPool>,
+    password_hashing: web::Data<PasswordHashing>,
+    login_throttling: web::Data<LoginThrottlingSettings>,
+    request: HttpRequest,
+) -> Result<HttpResponse, PublishError> {
+    let user_id =
+        authenticate_publisher(&request, &password_hashing, &login_throttling, &pool).await?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    parse_segment(body.segment.as_deref())?;
+    let (html_content, text_content) = body.content.bodies()?;
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let list_id = resolve_list_id(&mut transaction, body.list_id).await?;
+    let issue_id = Uuid::new_v4();
+    let slug = IssueSlug::new(&body.title, issue_id);
+    transaction
+        .execute(sqlx::query!(
+            r#"
+            INSERT INTO newsletter_issues (
+                newsletter_issue_id,
+                list_id,
+                title,
+                text_content,
+                html_content,
+                segment,
+                slug,
+                private,
+                status
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, 'draft')
+            "#,
+            issue_id,
+            list_id,
+            body.title,
+            text_content,
+            html_content,
+            body.segment,
+            slug.as_ref(),
+            is_private(body.private, body.segment.as_deref())
+        ))
+        .await
+        .context("Failed to store the draft.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to store a draft.")?;
+    Ok(HttpResponse::Created().json(serde_json::json!({ "newsletter_issue_id": issue_id })))
+}
+
+/// Replace the title, content and audience of a draft.
+#[instrument(
+    name = "Update a newsletter draft",
+    skip(body, pool, password_hashing, login_throttling, request),
+    fields(user_id = tracing::field::Empty)
+)]
+pub async fn update_draft(
+    issue_id: web::Path<Uuid>,
+    body: web::Json<DraftData>,
+    pool: web::Data<PgPool>,
+    password_hashing: web::Data<PasswordHashing>,
+    login_throttling: web::Data<LoginThrottlingSettings>,
+    request: HttpRequest,
+) -> Result<HttpResponse, PublishError> {
+    let user_id =
+        authenticate_publisher(&request, &password_hashing, &login_throttling, &pool).await?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    let issue_id = issue_id.into_inner();
+    parse_segment(body.segment.as_deref())?;
+    let (html_content, text_content) = body.content.bodies()?;
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let list_id = resolve_list_id(&mut transaction, body.list_id).await?;
+    let slug = IssueSlug::new(&body.title, issue_id);
+    let updated = transaction
+        .execute(sqlx::query!(
+            r#"
+            UPDATE newsletter_issues
+            SET
+                list_id = $2,
+                title = $3,
+                text_content = $4,
+                html_content = $5,
+                segment = $6,
+                slug = $7,
+                private = $8
+            WHERE newsletter_issue_id = $1 AND status = 'draft'
+            "#,
+            issue_id,
+            list_id,
+            body.title,
+            text_content,
+            html_content,
+            body.segment,
+            slug.as_ref(),
+            is_private(body.private, body.segment.as_deref())
+        ))
+        .await
+        .context("Failed to update the draft.")?
+        .rows_affected()
+        > 0;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to update a draft.")?;
+    ensure_issue_was_found(&pool, issue_id, updated, PublishError::NotADraft).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Render a draft the way subscribers will see it, with sample subscriber details.
+///
+/// The body is served as is, so the page is sandboxed: scripts in a draft don't run
+/// with the admin's session.
+#[instrument(
+    name = "Preview a newsletter draft",
+    skip(pool, password_hashing, login_throttling, request, base_url, hmac_secret),
+    fields(user_id = tracing::field::Empty)
+)]
+pub async fn preview_draft(
+    issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    password_hashing: web::Data<PasswordHashing>,
+    login_throttling: web::Data<LoginThrottlingSettings>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    hmac_secret: web::Data<HmacSecret>,
+    request: HttpRequest,
+) -> Result<HttpResponse, PublishError> {
+    let user_id =
+        authenticate_publisher(&request, &password_hashing, &login_throttling, &pool).await?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    let draft = get_draft(&pool, issue_id.into_inner()).await?;
+    let (html_body, _) = draft.render(SAMPLE_SUBSCRIBER_EMAIL, &base_url, &hmac_secret);
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .insert_header((CONTENT_SECURITY_POLICY, "sandbox"))
+        .body(html_body))
+}
+
+#[derive(Deserialize)]
+pub struct TestSendData {
+    emails: Vec<String>,
+}
+
+/// Send a draft to a few addresses, which don't need to belong to subscribers.
+///
+/// The subject is prefixed with `[TEST]`, and the subscription links point at nobody.
+#[instrument(
+    name = "Test-send a newsletter draft",
+    skip(body, pool, password_hashing, login_throttling, request, email_client, base_url, hmac_secret),
+    fields(user_id = tracing::field::Empty)
+)]
+#[allow(clippy::too_many_arguments)]
+pub async fn test_send_draft(
+    issue_id: web::Path<Uuid>,
+    body: web::Json<TestSendData>,
+    pool: web::Data<PgPool>,
+    password_hashing: web::Data<PasswordHashing>,
+    login_throttling: web::Data<LoginThrottlingSettings>,
+    email_client: web::Data<dyn EmailSender>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    hmac_secret: web::Data<HmacSecret>,
+    request: HttpRequest,
+) -> Result<HttpResponse, PublishError> {
+    let user_id =
+        authenticate_publisher(&request, &password_hashing, &login_throttling, &pool).await?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    if body.emails.is_empty() || body.emails.len() > MAX_TEST_RECIPIENTS {
+        return Err(PublishError::ValidationError(format!(
+            "Provide between 1 and {} email addresses.",
+            MAX_TEST_RECIPIENTS
+        )));
+    }
+    let recipients = body
+        .emails
+        .iter()
+        .map(|email| {
+            SubscriberEmail::parse(email.trim()).map_err(|_| {
+                PublishError::ValidationError(format!("{} is not a valid email address.", email))
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let draft = get_draft(&pool, issue_id.into_inner()).await?;
+    let subject = format!("[TEST] {}", draft.title);
+    for recipient in &recipients {
+        let (html_body, text_body) = draft.render(recipient.as_ref(), &base_url, &hmac_secret);
+        email_client
+            .send_email(recipient, &subject, &html_body, &text_body, &[])
+            .await
+            .with_context(|| format!("Failed to send a test email to {}", recipient.as_ref()))?;
+    }
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[derive(Deserialize)]
+pub struct PublishDraftData {
+    /// Hold the issue back until then. Issues due already are sent right away.
+    send_at: Option<DateTime<Utc>>,
+}
+
+/// Publish a draft as it is stored, right away or at `send_at`.
+#[instrument(
+    name = "Publish a newsletter draft",
+    skip(body, pool, password_hashing, login_throttling, request),
+    fields(user_id = tracing::field::Empty)
+)]
+pub async fn publish_draft(
+    issue_id: web::Path<Uuid>,
+    body: web::Json<PublishDraftData>,
+    pool: web::Data<PgPool>,
+    password_hashing: web::Data<PasswordHashing>,
+    login_throttling: web::Data<LoginThrottlingSettings>,
+    request: HttpRequest,
+) -> Result<HttpResponse, PublishError> {
+    let user_id =
+        authenticate_publisher(&request, &password_hashing, &login_throttling, &pool).await?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    let issue_id = issue_id.into_inner();
+    let send_at = body.send_at.filter(|send_at| *send_at > Utc::now());
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    // Locking the draft makes concurrent attempts to publish it wait, and then fail.
+    let draft = sqlx::query!(
+        r#"
+        SELECT segment, status
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        FOR UPDATE
+        "#,
+        issue_id
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to look up the draft.")?
+    .ok_or(PublishError::UnknownIssue)?;
+    if draft.status != "draft" {
+        return Err(PublishError::NotADraft);
+    }
+    // The segment was validated when the draft was saved.
+    let segment = parse_segment(draft.segment.as_deref())?;
+    let (status, published_at) = match send_at {
+        Some(_) => ("scheduled", None),
+        None => ("published", Some(Utc::now())),
+    };
+    transaction
+        .execute(sqlx::query!(
+            r#"
+            UPDATE newsletter_issues
+            SET status = $2, send_at = $3, published_at = $4
+            WHERE newsletter_issue_id = $1
+            "#,
+            issue_id,
+            status,
+            send_at,
+            published_at
+        ))
+        .await
+        .context("Failed to publish the draft.")?;
+    let response = match send_at {
+        None => {
+            enqueue_delivery_tasks(&mut transaction, issue_id, segment.as_ref())
+                .await
+                .context("Failed to enqueue delivery tasks")?;
+            HttpResponse::Ok().finish()
+        }
+        Some(send_at) => HttpResponse::Accepted().json(serde_json::json!({
+            "newsletter_issue_id": issue_id,
+            "send_at": send_at,
+        })),
+    };
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to publish a draft.")?;
+    Ok(response)
+}
+
+struct Draft {
+    title: String,
+    html_content: String,
+    text_content: String,
+}
+
+impl Draft {
+    /// Render the bodies of the email as the subscriber with `email` would receive it.
+    fn render(
+        &self,
+        email: &str,
+        base_url: &ApplicationBaseUrl,
+        hmac_secret: &HmacSecret,
+    ) -> (String, String) {
+        let unsubscribe_url = unsubscribe_link(base_url, hmac_secret, Uuid::nil());
+        let preferences_url = preferences_link(base_url, hmac_secret, Uuid::nil());
+        let context = TemplateContext {
+            subscriber_name: SAMPLE_SUBSCRIBER_NAME,
+            subscriber_email: email,
+            issue_title: &self.title,
+            unsubscribe_url: &unsubscribe_url,
+            preferences_url: &preferences_url,
+        };
+        render_email(&self.html_content, &self.text_content, &context)
+    }
+}
+
+#[instrument(skip(pool))]
+async fn get_draft(pool: &PgPool, issue_id: Uuid) -> Result<Draft, PublishError> {
+    let issue = sqlx::query!(
+        r#"
+        SELECT title, html_content, text_content, status
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        "#,
+        issue_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve the draft.")?
+    .ok_or(PublishError::UnknownIssue)?;
+    if issue.status != "draft" {
+        return Err(PublishError::NotADraft);
+    }
+    Ok(Draft {
+        title: issue.title,
+        html_content: issue.html_content,
+        text_content: issue.text_content,
+    })
+}
@@ -0,0 +1,131 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tracing::instrument;
+use uuid::Uuid;
+
+use super::newsletters::{authenticate_publisher, ensure_issue_was_found, PublishError};
+use crate::authentication::PasswordHashing;
+use crate::configuration::LoginThrottlingSettings;
+
+#[derive(Serialize)]
+pub struct ScheduledIssue {
+    newsletter_issue_id: Uuid,
+    title: String,
+    list_id: Uuid,
+    segment: Option<String>,
+    send_at: DateTime<Utc>,
+}
+
+#[instrument(
+    name = "List scheduled newsletter issues",
+    skip_all,
+    fields(user_id = tracing::field::Empty)
+)]
+pub async fn list_scheduled_issues(
+    pool: web::Data<PgPool>,
+    password_hashing: web::Data<PasswordHashing>,
+    login_throttling: web::Data<LoginThrottlingSettings>,
+    request: HttpRequest,
+) -> Result<HttpResponse, PublishError> {
+    let user_id =
+        authenticate_publisher(&request, &password_hashing, &login_throttling, &pool).await?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    let issues = sqlx::query_as!(
+        ScheduledIssue,
+        r#"
+        SELECT newsletter_issue_id, title, list_id, segment, send_at as "send_at!"
+        FROM newsletter_issues
+        WHERE status = 'scheduled'
+        ORDER BY send_at
+        "#
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    .context("Failed to retrieve the scheduled issues.")?;
+    Ok(HttpResponse::Ok().json(issues))
+}
+
+#[derive(Deserialize)]
+pub struct RescheduleData {
+    send_at: DateTime<Utc>,
+}
+
+/// Move a scheduled issue to another time. Issues moved to the past are sent right away.
+#[instrument(
+    name = "Reschedule a newsletter issue",
+    skip(body, pool, password_hashing, login_throttling, request),
+    fields(user_id = tracing::field::Empty)
+)]
+pub async fn reschedule_issue(
+    issue_id: web::Path<Uuid>,
+    body: web::Json<RescheduleData>,
+    pool: web::Data<PgPool>,
+    password_hashing: web::Data<PasswordHashing>,
+    login_throttling: web::Data<LoginThrottlingSettings>,
+    request: HttpRequest,
+) -> Result<HttpResponse, PublishError> {
+    let user_id =
+        authenticate_publisher(&request, &password_hashing, &login_throttling, &pool).await?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    let issue_id = issue_id.into_inner();
+    // The scheduler locks due issues while releasing them:
+    // this waits for it, and then finds the issue published already.
+    let rescheduled = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET send_at = $2
+        WHERE newsletter_issue_id = $1 AND status = 'scheduled'
+        "#,
+        issue_id,
+        body.send_at
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to reschedule the issue.")?
+    .rows_affected()
+        > 0;
+    ensure_issue_was_found(&pool, issue_id, rescheduled, PublishError::NotScheduled).await?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "newsletter_issue_id": issue_id,
+        "send_at": body.send_at,
+    })))
+}
+
+#[instrument(
+    name = "Cancel a newsletter issue",
+    skip(pool, password_hashing, login_throttling, request),
+    fields(user_id = tracing::field::Empty)
+)]
+pub async fn cancel_issue(
+    issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    password_hashing: web::Data<PasswordHashing>,
+    login_throttling: web::Data<LoginThrottlingSettings>,
+    request: HttpRequest,
+) -> Result<HttpResponse, PublishError> {
+    let user_id =
+        authenticate_publisher(&request, &password_hashing, &login_throttling, &pool).await?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    let issue_id = issue_id.into_inner();
+    let cancelled = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET status = 'cancelled'
+        WHERE newsletter_issue_id = $1 AND status = 'scheduled'
+        "#,
+        issue_id
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to cancel the issue.")?
+    .rows_affected()
+        > 0;
+    ensure_issue_was_found(&pool, issue_id, cancelled, PublishError::NotScheduled).await?;
+    Ok(HttpResponse::Ok().finish())
+}
@@ -0,0 +1,41 @@
+use hmac::{Hmac, Mac};
+use secrecy::ExposeSecret;
+use uuid::Uuid;
+
+use crate::startup::HmacSecret;
+
+/// Sign `subscriber_id` for a given `purpose` with the application's HMAC secret.
+///
+/// Links carrying the signature identify the subscriber without us storing a token per subscriber.
+/// The purpose is part of the signature, so that a link built for one page can't be used for another.
+pub fn sign_subscriber_id(hmac_secret: &HmacSecret, purpose: &str, subscriber_id: Uuid) -> String {
+    hex::encode(
+        mac(hmac_secret, purpose, subscriber_id)
+            .finalize()
+            .into_bytes(),
+    )
+}
+
+pub fn verify_subscriber_id(
+    hmac_secret: &HmacSecret,
+    purpose: &str,
+    subscriber_id: Uuid,
+    signature: &str,
+) -> bool {
+    let Ok(tag) = hex::decode(signature) else {
+        return false;
+    };
+    // `verify_slice` compares in constant time.
+    mac(hmac_secret, purpose, subscriber_id)
+        .verify_slice(&tag)
+        .is_ok()
+}
+
+fn mac(hmac_secret: &HmacSecret, purpose: &str, subscriber_id: Uuid) -> Hmac<sha2::Sha256> {
+    let mut mac = Hmac::<sha2::Sha256>::new_from_slice(hmac_secret.0.expose_secret().as_bytes())
+        .expect("HMAC can take a key of any size.");
+    mac.update(purpose.as_bytes());
+    mac.update(b":");
+    mac.update(subscriber_id.as_bytes());
+    mac
+}
@@ -1,3 +1,5 @@
+use std::sync::Arc;
+
 use actix_web::http::StatusCode;
 use actix_web::{web, HttpResponse};
 use anyhow::Context;
@@ -5,83 +7,355 @@ use chrono::Utc;
 use rand::{thread_rng, Rng};
 use serde::Deserialize;
 use sqlx::{Executor, PgPool, Postgres, Transaction};
-use tracing::{error, info, instrument};
+use tracing::{info, instrument, Instrument};
 use uuid::Uuid;
 
-use crate::domain::NewSubscriber;
-use crate::email_client::EmailClient;
+use crate::configuration::SubscriptionSettings;
+use crate::domain::{ListSlug, NewSubscriber, SubscriberName};
+use crate::email_client::EmailSender;
+use crate::lists::{get_default_list_id, get_list_id};
 use crate::startup::ApplicationBaseUrl;
 
 #[tracing::instrument(
     name = "Adding a new subscriber",
-    skip(form, pool, email_client, base_url),
+    skip(form, pool, email_client, base_url, subscription_settings),
     fields(
         subscriber_email = %form.email,
-        subscriber_name = %form.name
+        subscriber_name = %form.name,
+        list = ?form.list
     )
 )]
 pub async fn subscribe(
     form: web::Form<FormData>,
     pool: web::Data<PgPool>,
-    email_client: web::Data<EmailClient>,
+    email_client: web::Data<dyn EmailSender>,
     base_url: web::Data<ApplicationBaseUrl>,
+    subscription_settings: web::Data<SubscriptionSettings>,
 ) -> Result<HttpResponse, SubscribeError> {
+    let list_slug = form
+        .list
+        .as_deref()
+        .map(ListSlug::parse)
+        .transpose()
+        .map_err(SubscribeError::ValidationError)?;
     let new_subscriber = form.0.try_into().map_err(SubscribeError::ValidationError)?;
     let mut transaction = pool
         .begin()
         .await
         .context("Failed to acquire a Postgres connection from the pool")?;
-    let subscriber_id = insert_subscriber(&mut transaction, &new_subscriber)
+    let list_id = resolve_list_id(&mut transaction, list_slug.as_ref()).await?;
+    let (subscriber_id, new_subscriber) = match insert_subscriber(&mut transaction, &new_subscriber)
+        .await
+        .context("Failed to insert new subscriber in the database.")?
+    {
+        Some(subscriber_id) => (subscriber_id, new_subscriber),
+        None => {
+            let (subscriber_id, status, name) =
+                get_existing_subscriber(&mut transaction, &new_subscriber)
+                    .await
+                    .context("Failed to look up existing subscribers.")?
+                    .context("The subscriber holding the email address could not be found.")?;
+            if status == "confirmed" {
+                let membership_status =
+                    get_membership_status(&mut transaction, list_id, subscriber_id)
+                        .await
+                        .context("Failed to look up the list membership.")?;
+                // Confirmed members get the same response as everybody else,
+                // so that the form can't be used to find out who is on the list.
+                if membership_status.as_deref() == Some("confirmed") {
+                    return Ok(HttpResponse::Ok().finish());
+                }
+                // A known address joining another list: it needs to be confirmed all the same.
+            } else {
+                // Either the confirmation email got lost, or they unsubscribed and changed their mind:
+                // in both cases, they go (again) through double opt-in with a brand new token.
+                restart_confirmation(&mut transaction, subscriber_id)
+                    .await
+                    .context("Failed to reset an existing subscriber.")?;
+            }
+            // The email greets them with the name we have on file, not the one in the form.
+            let subscriber = NewSubscriber {
+                email: new_subscriber.email,
+                name: SubscriberName::parse(name).map_err(|e| anyhow::anyhow!(e))?,
+            };
+            (subscriber_id, subscriber)
+        }
+    };
+    let may_send =
+        may_send_confirmation_email(&mut transaction, subscriber_id, &subscription_settings)
+            .await
+            .context("Failed to count the confirmation emails sent recently.")?;
+    if !may_send {
+        // Dropping the transaction rolls back any change made to the subscriber.
+        tracing::warn!("Too many confirmation emails sent recently, not sending another one.");
+        return Ok(HttpResponse::Ok().finish());
+    }
+    request_membership(&mut transaction, list_id, subscriber_id)
         .await
-        .context("Failed to insert new subscriber in the database.")?;
+        .context("Failed to store the list membership.")?;
     let subscription_token = generate_subscription_token();
-    store_token(&mut transaction, subscriber_id, &subscription_token)
+    store_token(
+        &mut transaction,
+        subscriber_id,
+        list_id,
+        &subscription_token,
+    )
+    .await
+    .context("Failed to store the confirmation token for a new subscriber.")?;
+    log_confirmation_email(&mut transaction, subscriber_id)
         .await
-        .context("Failed to store the confirmation token for a new subscriber.")?;
+        .context("Failed to log the confirmation email.")?;
     transaction
         .commit()
         .await
         .context("Failed to commit SQL transaction to store a new subscriber.")?;
-    send_confirmation_email(
-        &email_client,
-        &new_subscriber,
-        &base_url,
-        &subscription_token,
+    spawn_confirmation_email(
+        email_client.into_inner(),
+        base_url.into_inner(),
+        new_subscriber,
+        subscription_token,
+    );
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// The list named by `list_slug`, or the default list if the subscriber didn't name any.
+pub async fn resolve_list_id(
+    transaction: &mut Transaction<'_, Postgres>,
+    list_slug: Option<&ListSlug>,
+) -> Result<Uuid, SubscribeError> {
+    let list_id = match list_slug {
+        None => Some(
+            get_default_list_id(transaction)
+                .await
+                .context("Failed to retrieve the default list.")?,
+        ),
+        Some(list_slug) => get_list_id(transaction, list_slug)
+            .await
+            .context("Failed to look up the list.")?,
+    };
+    list_id.ok_or_else(|| SubscribeError::ValidationError("There is no such list.".into()))
+}
+
+/// Return the id, status and name of the subscriber with the same email, if any.
+///
+/// The row is locked until the transaction ends, to serialise concurrent attempts.
+#[instrument(
+    name = "Looking up an existing subscriber.",
+    skip(transaction, new_subscriber)
+)]
+async fn get_existing_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    new_subscriber: &NewSubscriber,
+) -> Result<Option<(Uuid, String, String)>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT id, status, name
+        FROM subscriptions
+        WHERE email = $1
+        FOR UPDATE
+        "#,
+        new_subscriber.email.as_ref()
     )
+    .fetch_optional(&mut **transaction)
+    .await?;
+    Ok(row.map(|r| (r.id, r.status, r.name)))
+}
+
+async fn get_membership_status(
+    transaction: &mut Transaction<'_, Postgres>,
+    list_id: Uuid,
+    subscriber_id: Uuid,
+) -> Result<Option<String>, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT status
+        FROM list_memberships
+        WHERE list_id = $1 AND subscriber_id = $2
+        "#,
+        list_id,
+        subscriber_id
+    )
+    .fetch_optional(&mut **transaction)
     .await
-    .context("Failed to send a confirmation email.")?;
-    Ok(HttpResponse::Ok().finish())
+}
+
+/// Put an existing, not confirmed, subscriber back to `pending_confirmation`.
+///
+/// Their name is left as it is: anybody can post their address to the form,
+/// while only they can change it from their preferences.
+#[instrument(
+    name = "Restarting the confirmation of an existing subscriber.",
+    skip(transaction)
+)]
+async fn restart_confirmation(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    transaction
+        .execute(sqlx::query!(
+            r#"
+            UPDATE subscriptions
+            SET
+                status = 'pending_confirmation',
+                subscribed_at = $2,
+                unsubscribed_at = NULL
+            WHERE id = $1
+            "#,
+            subscriber_id,
+            Utc::now()
+        ))
+        .await?;
+    Ok(())
+}
+
+/// Add the subscriber to the list, pending confirmation,
+/// and invalidate the confirmation links for this list we sent them so far.
+#[instrument(name = "Requesting a list membership.", skip(transaction))]
+async fn request_membership(
+    transaction: &mut Transaction<'_, Postgres>,
+    list_id: Uuid,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    transaction
+        .execute(sqlx::query!(
+            r#"
+            INSERT INTO list_memberships (list_id, subscriber_id, status)
+            VALUES ($1, $2, 'pending_confirmation')
+            ON CONFLICT (list_id, subscriber_id) DO UPDATE
+            SET status = 'pending_confirmation'
+            "#,
+            list_id,
+            subscriber_id
+        ))
+        .await?;
+    delete_outstanding_tokens(transaction, subscriber_id, list_id).await
+}
+
+/// Invalidate the confirmation links for this list we sent the subscriber so far,
+/// so that only the one about to be sent can be used.
+#[instrument(name = "Deleting outstanding confirmation tokens.", skip(transaction))]
+pub async fn delete_outstanding_tokens(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    list_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    transaction
+        .execute(sqlx::query!(
+            r#"
+            DELETE FROM subscription_tokens
+            WHERE subscriber_id = $1 AND list_id = $2 AND consumed_at IS NULL
+            "#,
+            subscriber_id,
+            list_id
+        ))
+        .await?;
+    Ok(())
 }
 
 #[instrument(
     name = "Storing a new subscription token in the database.",
-    skip(transaction, subscriber_id, subscription_token)
+    skip(transaction, subscriber_id, list_id, subscription_token)
 )]
-async fn store_token(
+pub async fn store_token(
     transaction: &mut Transaction<'_, Postgres>,
     subscriber_id: Uuid,
+    list_id: Uuid,
     subscription_token: &str,
 ) -> Result<(), sqlx::Error> {
     let query = sqlx::query!(
-        r#"INSERT INTO subscription_tokens (subscription_token, subscriber_id) VALUES ($1, $2)"#,
+        r#"
+        INSERT INTO subscription_tokens (subscription_token, subscriber_id, list_id)
+        VALUES ($1, $2, $3)
+        "#,
         subscription_token,
-        subscriber_id
+        subscriber_id,
+        list_id
     );
 
     transaction.execute(query).await?;
     Ok(())
 }
 
+/// Whether the subscriber was sent fewer confirmation emails than allowed within the window.
+///
+/// The caller must hold a lock on the subscriber's row, for concurrent requests to be counted.
+#[instrument(
+    name = "Counting recent confirmation emails.",
+    skip(transaction, settings)
+)]
+pub async fn may_send_confirmation_email(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    settings: &SubscriptionSettings,
+) -> Result<bool, sqlx::Error> {
+    let n_recent_emails = sqlx::query_scalar!(
+        r#"
+        SELECT count(*) as "count!"
+        FROM confirmation_emails
+        WHERE subscriber_id = $1 AND sent_at > $2
+        "#,
+        subscriber_id,
+        Utc::now() - settings.confirmation_email_window()
+    )
+    .fetch_one(&mut **transaction)
+    .await?;
+    Ok(n_recent_emails < settings.max_confirmation_emails_per_window)
+}
+
+pub async fn log_confirmation_email(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    transaction
+        .execute(sqlx::query!(
+            "INSERT INTO confirmation_emails (subscriber_id) VALUES ($1)",
+            subscriber_id
+        ))
+        .await?;
+    Ok(())
+}
+
+/// Send the confirmation email in the background, logging any failure.
+///
+/// Waiting for the email provider, or failing along with it, would tell the addresses
+/// we send an email to apart from the others, e.g. the confirmed members of a list.
+pub fn spawn_confirmation_email(
+    email_client: Arc<dyn EmailSender>,
+    base_url: Arc<ApplicationBaseUrl>,
+    subscriber: NewSubscriber,
+    subscription_token: String,
+) {
+    tokio::spawn(
+        async move {
+            if let Err(e) = send_confirmation_email(
+                email_client.as_ref(),
+                &subscriber,
+                &base_url,
+                &subscription_token,
+            )
+            .await
+            {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Failed to send a confirmation email."
+                );
+            }
+        }
+        .in_current_span(),
+    );
+}
+
 #[instrument(
     name = "Sending a confirmation email to a new subscriber.",
     skip(email_client, new_subscriber)
 )]
-pub async fn send_confirmation_email(
-    email_client: &EmailClient,
+async fn send_confirmation_email(
+    email_client: &dyn EmailSender,
     new_subscriber: &NewSubscriber,
     base_url: &ApplicationBaseUrl,
     subscription_token: &str,
-) -> Result<(), reqwest::Error> {
+) -> Result<(), anyhow::Error> {
     let confirmation_link = format!(
         "{}/subscriptions/confirm?subscription_token={}",
         base_url.0, subscription_token
@@ -96,10 +370,20 @@ pub async fn send_confirmation_email(
     );
 
     email_client
-        .send_email(&new_subscriber.email, "Welcome!", &html_body, &plain_body)
-        .await
+        .send_email(
+            &new_subscriber.email,
+            "Welcome!",
+            &html_body,
+            &plain_body,
+            &[],
+        )
+        .await?;
+    Ok(())
 }
 
+/// Insert the subscriber, unless their email address is already taken: `None` in that case.
+///
+/// A concurrent insertion of the same address is waited for, so that it can't fail this one.
 #[instrument(
     name = "Saving new subscriber details in the database.",
     skip(new_subscriber, transaction)
@@ -107,25 +391,25 @@ pub async fn send_confirmation_email(
 pub async fn insert_subscriber(
     transaction: &mut Transaction<'_, Postgres>,
     new_subscriber: &NewSubscriber,
-) -> Result<Uuid, sqlx::Error> {
-    let subscriber_id = Uuid::new_v4();
-
-    let query = sqlx::query!(
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let subscriber_id = sqlx::query_scalar!(
         r#"
     INSERT INTO subscriptions (id, email, name, subscribed_at, status)
     VALUES ($1, $2, $3, $4, 'pending_confirmation')
+    ON CONFLICT (email) DO NOTHING
+    RETURNING id
     "#,
-        subscriber_id,
+        Uuid::new_v4(),
         new_subscriber.email.as_ref(),
         new_subscriber.name.as_ref(),
         Utc::now()
-    );
+    )
+    .fetch_optional(&mut **transaction)
+    .await?;
 
-    transaction.execute(query).await.map(|result| {
+    if subscriber_id.is_some() {
         info!("New subscriber details have been saved.");
-        result
-    })?;
-
+    }
     Ok(subscriber_id)
 }
 
@@ -133,9 +417,11 @@ pub async fn insert_subscriber(
 pub struct FormData {
     pub email: String,
     pub name: String,
+    /// The slug of the list to join. The default list if missing.
+    pub list: Option<String>,
 }
 
-fn generate_subscription_token() -> String {
+pub fn generate_subscription_token() -> String {
     let mut rng = thread_rng();
     std::iter::repeat_with(|| rng.sample(rand::distributions::Alphanumeric))
         .map(char::from)
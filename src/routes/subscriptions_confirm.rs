@@ -1,34 +1,52 @@
-use actix_web::{web, HttpResponse};
+use actix_web::body::BoxBody;
+use actix_web::http::header::ContentType;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
 use serde::Deserialize;
-use sqlx::PgPool;
-use tracing::error;
+use sqlx::{Executor, PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
-#[tracing::instrument(name = "Confirming a pending subscriber.", skip(params, pool))]
-pub async fn confirm(params: web::Query<Parameters>, pool: web::Data<PgPool>) -> HttpResponse {
-    let id = match get_subscriber_id_from_token(&pool, &params.subscription_token).await {
-        Ok(id) => id,
-        Err(e) => {
-            error!(
-                error = ?e,
-                "Failed to retrieve subscriber ID from the database."
-            );
-            return HttpResponse::InternalServerError().finish();
-        }
-    };
+use crate::configuration::SubscriptionSettings;
+use crate::routes::error_chain_fmt;
+
+#[derive(thiserror::Error)]
+pub enum ConfirmError {
+    #[error("This confirmation link is not valid.")]
+    UnknownToken,
+    #[error("This confirmation link has expired. Please subscribe again to get a new one.")]
+    ExpiredToken,
+    #[error("This confirmation link has already been used.")]
+    ConsumedToken,
+    #[error("This email address is already subscribed to the newsletter.")]
+    AddressTaken,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
 
-    match id {
-        None => HttpResponse::Unauthorized().finish(),
-        Some(subscriber_id) => {
-            if let Err(e) = confirm_subscriber(&pool, subscriber_id).await {
-                error!(
-                    error = ?e,
-                    "Failed to confirm subscriber in the database."
-                );
-                return HttpResponse::InternalServerError().finish();
-            }
+impl std::fmt::Debug for ConfirmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for ConfirmError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ConfirmError::UnknownToken => StatusCode::UNAUTHORIZED,
+            ConfirmError::ExpiredToken | ConfirmError::ConsumedToken => StatusCode::GONE,
+            ConfirmError::AddressTaken => StatusCode::CONFLICT,
+            ConfirmError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
 
-            HttpResponse::Ok().finish()
+    fn error_response(&self) -> HttpResponse<BoxBody> {
+        match self {
+            ConfirmError::UnexpectedError(_) => HttpResponse::new(self.status_code()),
+            _ => HttpResponse::build(self.status_code())
+                .content_type(ContentType::html())
+                .body(confirmation_page(&self.to_string())),
         }
     }
 }
@@ -38,33 +56,139 @@ pub struct Parameters {
     subscription_token: String,
 }
 
-pub async fn get_subscriber_id_from_token(
-    pool: &PgPool,
+#[tracing::instrument(
+    name = "Confirming a pending subscriber.",
+    skip(params, pool, subscription_settings)
+)]
+pub async fn confirm(
+    params: web::Query<Parameters>,
+    pool: web::Data<PgPool>,
+    subscription_settings: web::Data<SubscriptionSettings>,
+) -> Result<HttpResponse, ConfirmError> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let (subscriber_id, list_id) = consume_token(
+        &mut transaction,
+        &params.subscription_token,
+        subscription_settings.confirmation_token_expiration(),
+    )
+    .await?;
+    confirm_membership(&mut transaction, subscriber_id, list_id)
+        .await
+        .context("Failed to confirm subscriber in the database.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to confirm a subscriber.")?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(confirmation_page(
+            "Your subscription is confirmed. Welcome aboard!",
+        )))
+}
+
+/// Mark the token as used and return the subscriber and the list it was issued for.
+///
+/// The row is locked until the transaction ends,
+/// so two concurrent requests can't both consume the same token.
+#[tracing::instrument(name = "Consuming a subscription token.", skip_all)]
+async fn consume_token(
+    transaction: &mut Transaction<'_, Postgres>,
     subscription_token: &str,
-) -> Result<Option<Uuid>, sqlx::Error> {
-    let result = sqlx::query!(
+    expiration: std::time::Duration,
+) -> Result<(Uuid, Uuid), ConfirmError> {
+    let token = sqlx::query!(
         r#"
-        SELECT subscriber_id
+        SELECT subscriber_id, list_id, created_at, consumed_at
         FROM subscription_tokens
         WHERE subscription_token = $1
+        FOR UPDATE
         "#,
         subscription_token
     )
-    .fetch_optional(pool)
-    .await?;
-    Ok(result.map(|r| r.subscriber_id))
+    .fetch_optional(&mut **transaction)
+    .await
+    .context("Failed to retrieve the subscription token.")?
+    .ok_or(ConfirmError::UnknownToken)?;
+
+    ensure_token_is_usable(token.created_at, token.consumed_at, expiration)?;
+
+    transaction
+        .execute(sqlx::query!(
+            r#"
+            UPDATE subscription_tokens
+            SET consumed_at = now()
+            WHERE subscription_token = $1
+            "#,
+            subscription_token
+        ))
+        .await
+        .context("Failed to mark the subscription token as consumed.")?;
+    Ok((token.subscriber_id, token.list_id))
 }
 
-async fn confirm_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<(), sqlx::Error> {
-    sqlx::query!(
-        r#"
-        UPDATE subscriptions
-        SET status = 'confirmed'
-        WHERE id = $1
-        "#,
-        subscriber_id
-    )
-    .execute(pool)
-    .await?;
+/// Tokens sent by email can be used once, and only for a limited time.
+pub fn ensure_token_is_usable(
+    created_at: DateTime<Utc>,
+    consumed_at: Option<DateTime<Utc>>,
+    expiration: std::time::Duration,
+) -> Result<(), ConfirmError> {
+    if consumed_at.is_some() {
+        return Err(ConfirmError::ConsumedToken);
+    }
+    if created_at < Utc::now() - expiration {
+        return Err(ConfirmError::ExpiredToken);
+    }
+    Ok(())
+}
+
+/// Confirming any membership proves the subscriber owns their address,
+/// so the subscriber is confirmed along with it.
+async fn confirm_membership(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    list_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    // Subscribers who unsubscribed in the meantime stay unsubscribed.
+    transaction
+        .execute(sqlx::query!(
+            r#"
+            UPDATE subscriptions
+            SET status = 'confirmed'
+            WHERE id = $1 AND status = 'pending_confirmation'
+            "#,
+            subscriber_id
+        ))
+        .await?;
+    transaction
+        .execute(sqlx::query!(
+            r#"
+            UPDATE list_memberships
+            SET status = 'confirmed'
+            WHERE subscriber_id = $1 AND list_id = $2 AND status = 'pending_confirmation'
+            "#,
+            subscriber_id,
+            list_id
+        ))
+        .await?;
     Ok(())
 }
+
+pub fn confirmation_page(message: &str) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Subscription confirmation</title>
+</head>
+<body>
+    <p>{}</p>
+</body>
+</html>"#,
+        message
+    )
+}
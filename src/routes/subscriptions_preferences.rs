@@ -0,0 +1,542 @@
+use std::sync::Arc;
+
+use actix_web::http::header::ContentType;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use sqlx::{Executor, PgPool, Postgres, Transaction};
+use tracing::{instrument, Instrument};
+use uuid::Uuid;
+
+use super::signed_links::{sign_subscriber_id, verify_subscriber_id};
+use super::subscriptions::{
+    generate_subscription_token, log_confirmation_email, may_send_confirmation_email,
+};
+use super::subscriptions_confirm::{confirmation_page, ensure_token_is_usable, ConfirmError};
+use crate::configuration::SubscriptionSettings;
+use crate::domain::{SubscriberEmail, SubscriberName};
+use crate::email_client::EmailSender;
+use crate::routes::error_chain_fmt;
+use crate::startup::{ApplicationBaseUrl, HmacSecret};
+use crate::utils::{clear_flash_message, flash_message_html, see_other_with_flash};
+
+const SIGNATURE_PURPOSE: &str = "preferences";
+/// Longer pauses are better served by unsubscribing.
+const MAX_PAUSE_WEEKS: u16 = 52;
+
+#[derive(thiserror::Error)]
+pub enum PreferencesError {
+    #[error("The preferences link is invalid.")]
+    InvalidToken,
+    #[error("The subscriber is not subscribed anymore.")]
+    NotSubscribed,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for PreferencesError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for PreferencesError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            PreferencesError::InvalidToken => StatusCode::UNAUTHORIZED,
+            PreferencesError::NotSubscribed => StatusCode::GONE,
+            PreferencesError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Parameters {
+    subscriber_id: Uuid,
+    token: String,
+}
+
+impl Parameters {
+    fn verify(&self, hmac_secret: &HmacSecret) -> Result<(), PreferencesError> {
+        if verify_subscriber_id(
+            hmac_secret,
+            SIGNATURE_PURPOSE,
+            self.subscriber_id,
+            &self.token,
+        ) {
+            Ok(())
+        } else {
+            Err(PreferencesError::InvalidToken)
+        }
+    }
+
+    /// The preferences page, relative to the application's base URL.
+    fn page_path(&self) -> String {
+        format!(
+            "/subscriptions/preferences?subscriber_id={}&token={}",
+            self.subscriber_id, self.token
+        )
+    }
+}
+
+/// Build the link a subscriber can follow to manage their subscription.
+pub fn preferences_link(
+    base_url: &ApplicationBaseUrl,
+    hmac_secret: &HmacSecret,
+    subscriber_id: Uuid,
+) -> String {
+    let token = sign_subscriber_id(hmac_secret, SIGNATURE_PURPOSE, subscriber_id);
+    format!(
+        "{}/subscriptions/preferences?subscriber_id={}&token={}",
+        base_url.0, subscriber_id, token
+    )
+}
+
+struct Subscriber {
+    name: String,
+    email: String,
+    paused_until: Option<DateTime<Utc>>,
+}
+
+#[instrument(
+    name = "Showing the subscription preferences.",
+    skip(params, pool, hmac_secret, request),
+    fields(subscriber_id = %params.subscriber_id)
+)]
+pub async fn preferences_form(
+    params: web::Query<Parameters>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+    request: HttpRequest,
+) -> Result<HttpResponse, PreferencesError> {
+    params.verify(&hmac_secret)?;
+    let subscriber = get_confirmed_subscriber(&pool, params.subscriber_id).await?;
+
+    let query = format!(
+        "subscriber_id={}&amp;token={}",
+        params.subscriber_id, params.token
+    );
+    let pause_status = match subscriber.paused_until {
+        Some(paused_until) if paused_until > Utc::now() => format!(
+            r#"<p>Delivery is paused until {}.</p>
+    <form action="/subscriptions/preferences/pause?{}" method="post">
+        <input hidden type="number" name="weeks" value="0">
+        <button type="submit">Resume delivery</button>
+    </form>"#,
+            paused_until.format("%B %-d, %Y"),
+            query
+        ),
+        _ => String::new(),
+    };
+
+    let mut response = HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Subscription preferences</title>
+</head>
+<body>
+    {flash}
+    <p>You are subscribed as {name} ({email}).</p>
+    {pause_status}
+    <form action="/subscriptions/preferences/name?{query}" method="post">
+        <label>Name
+            <input type="text" name="name" value="{name_attribute}">
+        </label>
+        <button type="submit">Change name</button>
+    </form>
+    <form action="/subscriptions/preferences/email?{query}" method="post">
+        <label>Email
+            <input type="email" name="email" placeholder="Enter your new email address">
+        </label>
+        <button type="submit">Change email</button>
+    </form>
+    <form action="/subscriptions/preferences/pause?{query}" method="post">
+        <label>Pause delivery for
+            <input type="number" name="weeks" min="1" max="{MAX_PAUSE_WEEKS}" value="4">
+            weeks
+        </label>
+        <button type="submit">Pause</button>
+    </form>
+</body>
+</html>"#,
+            flash = flash_message_html(&request),
+            name = htmlescape::encode_minimal(&subscriber.name),
+            email = htmlescape::encode_minimal(&subscriber.email),
+            name_attribute = htmlescape::encode_attribute(&subscriber.name),
+        ));
+    clear_flash_message(&mut response);
+    Ok(response)
+}
+
+#[derive(Deserialize)]
+pub struct NameFormData {
+    name: String,
+}
+
+#[instrument(
+    name = "Changing a subscriber's name.",
+    skip(params, form, pool, hmac_secret),
+    fields(subscriber_id = %params.subscriber_id)
+)]
+pub async fn change_name(
+    params: web::Query<Parameters>,
+    form: web::Form<NameFormData>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, PreferencesError> {
+    params.verify(&hmac_secret)?;
+    get_confirmed_subscriber(&pool, params.subscriber_id).await?;
+    let Ok(name) = SubscriberName::parse(form.0.name) else {
+        return Ok(see_other_with_flash(
+            &params.page_path(),
+            "That is not a valid name.",
+        ));
+    };
+
+    sqlx::query!(
+        "UPDATE subscriptions SET name = $2 WHERE id = $1",
+        params.subscriber_id,
+        name.as_ref()
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to update the subscriber's name.")?;
+    Ok(see_other_with_flash(
+        &params.page_path(),
+        "Your name has been updated.",
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct EmailFormData {
+    email: String,
+}
+
+/// The new address only replaces the current one once it's been confirmed,
+/// see `confirm_email_change`.
+#[instrument(
+    name = "Requesting a change of a subscriber's email.",
+    skip(params, form, pool, email_client, base_url, hmac_secret, subscription_settings),
+    fields(subscriber_id = %params.subscriber_id)
+)]
+pub async fn change_email(
+    params: web::Query<Parameters>,
+    form: web::Form<EmailFormData>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<dyn EmailSender>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    hmac_secret: web::Data<HmacSecret>,
+    subscription_settings: web::Data<SubscriptionSettings>,
+) -> Result<HttpResponse, PreferencesError> {
+    params.verify(&hmac_secret)?;
+    let subscriber = get_confirmed_subscriber(&pool, params.subscriber_id).await?;
+    let Ok(new_email) = SubscriberEmail::parse(form.0.email) else {
+        return Ok(see_other_with_flash(
+            &params.page_path(),
+            "That is not a valid email address.",
+        ));
+    };
+    if new_email.as_ref() == subscriber.email {
+        return Ok(see_other_with_flash(
+            &params.page_path(),
+            "That is already your email address.",
+        ));
+    }
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    lock_subscriber(&mut transaction, params.subscriber_id)
+        .await
+        .context("Failed to lock the subscriber.")?;
+    // Links to arbitrary addresses count towards the same limit as the subscription's own,
+    // so that the form can't be used to send emails to anybody at will.
+    let may_send = may_send_confirmation_email(
+        &mut transaction,
+        params.subscriber_id,
+        &subscription_settings,
+    )
+    .await
+    .context("Failed to count the confirmation emails sent recently.")?;
+    if !may_send {
+        return Ok(see_other_with_flash(
+            &params.page_path(),
+            "We sent you too many confirmation links recently. Please try again later.",
+        ));
+    }
+    let email_change_token = generate_subscription_token();
+    transaction
+        .execute(sqlx::query!(
+            r#"
+            INSERT INTO email_change_requests (email_change_token, subscriber_id, new_email)
+            VALUES ($1, $2, $3)
+            "#,
+            email_change_token,
+            params.subscriber_id,
+            new_email.as_ref()
+        ))
+        .await
+        .context("Failed to store the email change request.")?;
+    log_confirmation_email(&mut transaction, params.subscriber_id)
+        .await
+        .context("Failed to log the confirmation email.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to store an email change request.")?;
+    spawn_email_change_confirmation(
+        email_client.into_inner(),
+        base_url.into_inner(),
+        new_email,
+        email_change_token,
+    );
+
+    Ok(see_other_with_flash(
+        &params.page_path(),
+        "We sent a confirmation link to your new address. \
+        The change will take effect once you follow it.",
+    ))
+}
+
+async fn lock_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    transaction
+        .execute(sqlx::query!(
+            "SELECT id FROM subscriptions WHERE id = $1 FOR UPDATE",
+            subscriber_id
+        ))
+        .await?;
+    Ok(())
+}
+
+/// Send the confirmation link in the background: the provider's latency or failures
+/// shouldn't hold up the redirect.
+fn spawn_email_change_confirmation(
+    email_client: Arc<dyn EmailSender>,
+    base_url: Arc<ApplicationBaseUrl>,
+    new_email: SubscriberEmail,
+    email_change_token: String,
+) {
+    tokio::spawn(
+        async move {
+            if let Err(e) = send_email_change_confirmation(
+                email_client.as_ref(),
+                &new_email,
+                &base_url,
+                &email_change_token,
+            )
+            .await
+            {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Failed to send the email change confirmation."
+                );
+            }
+        }
+        .in_current_span(),
+    );
+}
+
+#[instrument(
+    name = "Sending an email change confirmation.",
+    skip(email_client, email_change_token)
+)]
+async fn send_email_change_confirmation(
+    email_client: &dyn EmailSender,
+    new_email: &SubscriberEmail,
+    base_url: &ApplicationBaseUrl,
+    email_change_token: &str,
+) -> Result<(), anyhow::Error> {
+    let confirmation_link = format!(
+        "{}/subscriptions/preferences/confirm-email?email_change_token={}",
+        base_url.0, email_change_token
+    );
+    let plain_body = format!(
+        "Click here to receive the newsletter at this address from now on: {}",
+        confirmation_link
+    );
+    let html_body = format!(
+        "Click <a href=\"{}\">here</a> to receive the newsletter at this address from now on.",
+        confirmation_link
+    );
+
+    email_client
+        .send_email(
+            new_email,
+            "Confirm your new email address",
+            &html_body,
+            &plain_body,
+            &[],
+        )
+        .await?;
+    Ok(())
+}
+
+#[derive(Deserialize, Debug)]
+pub struct EmailChangeParameters {
+    email_change_token: String,
+}
+
+#[instrument(
+    name = "Confirming a change of a subscriber's email.",
+    skip(params, pool, subscription_settings)
+)]
+pub async fn confirm_email_change(
+    params: web::Query<EmailChangeParameters>,
+    pool: web::Data<PgPool>,
+    subscription_settings: web::Data<SubscriptionSettings>,
+) -> Result<HttpResponse, ConfirmError> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let request = sqlx::query!(
+        r#"
+        SELECT subscriber_id, new_email, created_at, consumed_at
+        FROM email_change_requests
+        WHERE email_change_token = $1
+        FOR UPDATE
+        "#,
+        params.email_change_token
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to retrieve the email change request.")?
+    .ok_or(ConfirmError::UnknownToken)?;
+    ensure_token_is_usable(
+        request.created_at,
+        request.consumed_at,
+        subscription_settings.confirmation_token_expiration(),
+    )?;
+
+    match apply_email_change(
+        &mut transaction,
+        &params.email_change_token,
+        request.subscriber_id,
+        &request.new_email,
+    )
+    .await
+    {
+        Ok(()) => {}
+        // Somebody else might have subscribed with this address since the change was requested.
+        Err(sqlx::Error::Database(e)) if e.constraint() == Some("subscriptions_email_key") => {
+            return Err(ConfirmError::AddressTaken);
+        }
+        Err(e) => {
+            return Err(anyhow::Error::from(e)
+                .context("Failed to change the subscriber's email.")
+                .into())
+        }
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to change a subscriber's email.")?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(confirmation_page("Your email address has been updated.")))
+}
+
+async fn apply_email_change(
+    transaction: &mut Transaction<'_, Postgres>,
+    email_change_token: &str,
+    subscriber_id: Uuid,
+    new_email: &str,
+) -> Result<(), sqlx::Error> {
+    transaction
+        .execute(sqlx::query!(
+            r#"
+            UPDATE email_change_requests
+            SET consumed_at = now()
+            WHERE email_change_token = $1
+            "#,
+            email_change_token
+        ))
+        .await?;
+    transaction
+        .execute(sqlx::query!(
+            "UPDATE subscriptions SET email = $2 WHERE id = $1",
+            subscriber_id,
+            new_email
+        ))
+        .await?;
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub struct PauseFormData {
+    /// Zero resumes delivery straight away.
+    weeks: u16,
+}
+
+#[instrument(
+    name = "Pausing delivery to a subscriber.",
+    skip(params, form, pool, hmac_secret),
+    fields(subscriber_id = %params.subscriber_id, weeks = form.weeks)
+)]
+pub async fn pause_delivery(
+    params: web::Query<Parameters>,
+    form: web::Form<PauseFormData>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, PreferencesError> {
+    params.verify(&hmac_secret)?;
+    get_confirmed_subscriber(&pool, params.subscriber_id).await?;
+    if form.weeks > MAX_PAUSE_WEEKS {
+        return Ok(see_other_with_flash(
+            &params.page_path(),
+            &format!(
+                "You can pause delivery for up to {} weeks.",
+                MAX_PAUSE_WEEKS
+            ),
+        ));
+    }
+
+    let paused_until =
+        (form.weeks > 0).then(|| Utc::now() + chrono::Duration::weeks(form.weeks.into()));
+    sqlx::query!(
+        "UPDATE subscriptions SET paused_until = $2 WHERE id = $1",
+        params.subscriber_id,
+        paused_until
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to update the subscriber's pause.")?;
+
+    let message = match form.weeks {
+        0 => "Delivery has resumed.".to_owned(),
+        1 => "Delivery is paused for 1 week.".to_owned(),
+        weeks => format!("Delivery is paused for {} weeks.", weeks),
+    };
+    Ok(see_other_with_flash(&params.page_path(), &message))
+}
+
+async fn get_confirmed_subscriber(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Subscriber, PreferencesError> {
+    sqlx::query_as!(
+        Subscriber,
+        r#"
+        SELECT name, email, paused_until
+        FROM subscriptions
+        WHERE id = $1 AND status = 'confirmed'
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve the subscriber.")?
+    .ok_or(PreferencesError::NotSubscribed)
+}
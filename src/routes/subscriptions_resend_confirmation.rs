@@ -0,0 +1,133 @@
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use serde::Deserialize;
+use sqlx::{PgPool, Postgres, Transaction};
+use tracing::instrument;
+use uuid::Uuid;
+
+use super::subscriptions::{
+    delete_outstanding_tokens, generate_subscription_token, log_confirmation_email,
+    may_send_confirmation_email, resolve_list_id, spawn_confirmation_email, store_token,
+    SubscribeError,
+};
+use crate::configuration::SubscriptionSettings;
+use crate::domain::{ListSlug, NewSubscriber, SubscriberEmail, SubscriberName};
+use crate::email_client::EmailSender;
+use crate::startup::ApplicationBaseUrl;
+
+#[derive(Deserialize)]
+pub struct FormData {
+    email: String,
+    /// The slug of the list to confirm the membership of. The default list if missing.
+    list: Option<String>,
+}
+
+/// Send a new confirmation link to a subscriber whose membership of the list is pending.
+///
+/// The response is the same whether or not the address belongs to a pending subscriber,
+/// so that the endpoint can't be used to find out who subscribed.
+#[instrument(
+    name = "Resending a confirmation email",
+    skip(form, pool, email_client, base_url, subscription_settings),
+    fields(subscriber_email = %form.email, list = ?form.list)
+)]
+pub async fn resend_confirmation(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<dyn EmailSender>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    subscription_settings: web::Data<SubscriptionSettings>,
+) -> Result<HttpResponse, SubscribeError> {
+    let list_slug = form
+        .list
+        .as_deref()
+        .map(ListSlug::parse)
+        .transpose()
+        .map_err(SubscribeError::ValidationError)?;
+    let email = SubscriberEmail::parse(form.0.email).map_err(SubscribeError::ValidationError)?;
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let list_id = resolve_list_id(&mut transaction, list_slug.as_ref()).await?;
+
+    if let Some((subscriber_id, name)) = get_pending_member(&mut transaction, &email, list_id)
+        .await
+        .context("Failed to look up the pending subscriber.")?
+    {
+        let may_send =
+            may_send_confirmation_email(&mut transaction, subscriber_id, &subscription_settings)
+                .await
+                .context("Failed to count the confirmation emails sent recently.")?;
+
+        if may_send {
+            let subscriber = NewSubscriber {
+                email,
+                name: SubscriberName::parse(name).map_err(|e| anyhow::anyhow!(e))?,
+            };
+            delete_outstanding_tokens(&mut transaction, subscriber_id, list_id)
+                .await
+                .context("Failed to invalidate the previous confirmation tokens.")?;
+            let subscription_token = generate_subscription_token();
+            store_token(
+                &mut transaction,
+                subscriber_id,
+                list_id,
+                &subscription_token,
+            )
+            .await
+            .context("Failed to store the new confirmation token.")?;
+            log_confirmation_email(&mut transaction, subscriber_id)
+                .await
+                .context("Failed to log the confirmation email.")?;
+            transaction
+                .commit()
+                .await
+                .context("Failed to commit SQL transaction to store a new confirmation token.")?;
+            spawn_confirmation_email(
+                email_client.into_inner(),
+                base_url.into_inner(),
+                subscriber,
+                subscription_token,
+            );
+        } else {
+            tracing::warn!("Too many confirmation emails sent recently, not sending another one.");
+        }
+    }
+
+    Ok(HttpResponse::Ok().content_type(ContentType::html()).body(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Confirmation email</title>
+</head>
+<body>
+    <p>If this address is waiting to be confirmed, a new confirmation email is on its way.</p>
+</body>
+</html>"#,
+    ))
+}
+
+#[instrument(name = "Looking up a pending list member.", skip_all)]
+async fn get_pending_member(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &SubscriberEmail,
+    list_id: Uuid,
+) -> Result<Option<(Uuid, String)>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT s.id, s.name
+        FROM subscriptions s
+        JOIN list_memberships m ON m.subscriber_id = s.id
+        WHERE s.email = $1 AND m.list_id = $2 AND m.status = 'pending_confirmation'
+        FOR UPDATE OF s
+        "#,
+        email.as_ref(),
+        list_id
+    )
+    .fetch_optional(&mut **transaction)
+    .await?;
+    Ok(row.map(|r| (r.id, r.name)))
+}
@@ -0,0 +1,160 @@
+use actix_web::http::header::ContentType;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::Utc;
+use serde::Deserialize;
+use sqlx::PgPool;
+use tracing::instrument;
+use uuid::Uuid;
+
+use super::signed_links::{sign_subscriber_id, verify_subscriber_id};
+use crate::routes::error_chain_fmt;
+use crate::startup::{ApplicationBaseUrl, HmacSecret};
+
+#[derive(thiserror::Error)]
+pub enum UnsubscribeError {
+    #[error("The unsubscribe link is invalid.")]
+    InvalidToken,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for UnsubscribeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for UnsubscribeError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            UnsubscribeError::InvalidToken => StatusCode::UNAUTHORIZED,
+            UnsubscribeError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Parameters {
+    subscriber_id: Uuid,
+    token: String,
+}
+
+/// Render a confirmation page rather than unsubscribing straight away:
+/// link scanners and mail clients prefetch `GET` links, and they should not
+/// be able to remove someone from the list on their behalf.
+#[instrument(name = "Showing the unsubscribe form.", skip(params, hmac_secret))]
+pub async fn unsubscribe_form(
+    params: web::Query<Parameters>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    verify_token(&hmac_secret, params.subscriber_id, &params.token)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Unsubscribe</title>
+</head>
+<body>
+    <p>Do you want to stop receiving our newsletter?</p>
+    <form action="/subscriptions/unsubscribe?subscriber_id={}&amp;token={}" method="post">
+        <button type="submit">Unsubscribe</button>
+    </form>
+</body>
+</html>"#,
+            params.subscriber_id, params.token
+        )))
+}
+
+/// Both the form rendered by `unsubscribe_form` and RFC 8058 one-click requests
+/// land here. The latter carry `List-Unsubscribe=One-Click` as their body and
+/// identify the subscriber through the query string only, so that is where we read
+/// the parameters from, and we ignore the body altogether.
+#[instrument(
+    name = "Unsubscribing a subscriber.",
+    skip(params, pool, hmac_secret),
+    fields(subscriber_id = %params.subscriber_id)
+)]
+pub async fn unsubscribe(
+    params: web::Query<Parameters>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    verify_token(&hmac_secret, params.subscriber_id, &params.token)?;
+    mark_as_unsubscribed(&pool, params.subscriber_id)
+        .await
+        .context("Failed to mark the subscriber as unsubscribed.")?;
+
+    Ok(HttpResponse::Ok().content_type(ContentType::html()).body(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Unsubscribed</title>
+</head>
+<body>
+    <p>You have been unsubscribed. You will not receive any further newsletters.</p>
+</body>
+</html>"#,
+    ))
+}
+
+const SIGNATURE_PURPOSE: &str = "unsubscribe";
+
+/// Build the link a subscriber can follow to leave the newsletter.
+pub fn unsubscribe_link(
+    base_url: &ApplicationBaseUrl,
+    hmac_secret: &HmacSecret,
+    subscriber_id: Uuid,
+) -> String {
+    let token = sign_subscriber_id(hmac_secret, SIGNATURE_PURPOSE, subscriber_id);
+    format!(
+        "{}/subscriptions/unsubscribe?subscriber_id={}&token={}",
+        base_url.0, subscriber_id, token
+    )
+}
+
+fn verify_token(
+    hmac_secret: &HmacSecret,
+    subscriber_id: Uuid,
+    token: &str,
+) -> Result<(), UnsubscribeError> {
+    if verify_subscriber_id(hmac_secret, SIGNATURE_PURPOSE, subscriber_id, token) {
+        Ok(())
+    } else {
+        Err(UnsubscribeError::InvalidToken)
+    }
+}
+
+/// Unsubscribing is not specific to a list: the subscriber leaves all of them.
+#[instrument(name = "Marking a subscriber as unsubscribed.", skip(pool))]
+async fn mark_as_unsubscribed(pool: &PgPool, subscriber_id: Uuid) -> Result<(), sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    sqlx::query!(
+        r#"
+        UPDATE list_memberships
+        SET status = 'unsubscribed'
+        WHERE subscriber_id = $1
+        "#,
+        subscriber_id
+    )
+    .execute(&mut *transaction)
+    .await?;
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET status = 'unsubscribed', unsubscribed_at = $2
+        WHERE id = $1 AND status <> 'unsubscribed'
+        "#,
+        subscriber_id,
+        Utc::now()
+    )
+    .execute(&mut *transaction)
+    .await?;
+    transaction.commit().await
+}
@@ -0,0 +1,416 @@
+//! Boolean expressions over subscriber tags, e.g. `beta AND NOT (churned OR paused)`.
+//!
+//! `NOT` binds tighter than `AND`, which binds tighter than `OR`.
+//! Operators are case-insensitive, tag names are not.
+use sqlx::{Postgres, QueryBuilder};
+
+use crate::domain::TagName;
+
+/// Deeper expressions are rejected rather than risking to overflow the stack while parsing them.
+const MAX_DEPTH: usize = 32;
+/// Tags and operators, counted together. This also bounds the depth of long `AND`/`OR` chains,
+/// which nest one level per operator, and the number of parameters bound in the SQL filter.
+const MAX_TERMS: usize = 100;
+
+#[derive(Debug, PartialEq)]
+pub enum Segment {
+    Tag(TagName),
+    Not(Box<Segment>),
+    And(Box<Segment>, Box<Segment>),
+    Or(Box<Segment>, Box<Segment>),
+}
+
+/// Columns are counted in characters, starting from 1.
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum SegmentError {
+    #[error("The segment expression is empty.")]
+    Empty,
+    #[error("Unexpected character `{character}` at column {column}.")]
+    UnexpectedCharacter { character: char, column: usize },
+    #[error("`{name}` at column {column} is not a valid tag name.")]
+    InvalidTagName { name: String, column: usize },
+    #[error("Expected a tag name, `NOT` or `(` at column {column}, found {found}.")]
+    ExpectedOperand { found: String, column: usize },
+    #[error("Expected `AND` or `OR` at column {column}, found {found}.")]
+    ExpectedOperator { found: String, column: usize },
+    #[error("The parenthesis opened at column {column} is never closed.")]
+    UnclosedParenthesis { column: usize },
+    #[error("The `)` at column {column} doesn't close any parenthesis.")]
+    UnmatchedParenthesis { column: usize },
+    #[error("The segment expression is nested more than {MAX_DEPTH} levels deep.")]
+    TooDeep,
+    #[error("The segment expression has more than {MAX_TERMS} tags and operators.")]
+    TooLong,
+}
+
+impl Segment {
+    pub fn parse<T: AsRef<str>>(s: T) -> Result<Self, SegmentError> {
+        let tokens = tokenize(s.as_ref())?;
+        if tokens.is_empty() {
+            return Err(SegmentError::Empty);
+        }
+        let n_terms = tokens
+            .iter()
+            .filter(|token| {
+                !matches!(
+                    token.kind,
+                    TokenKind::LeftParenthesis | TokenKind::RightParenthesis
+                )
+            })
+            .count();
+        if n_terms > MAX_TERMS {
+            return Err(SegmentError::TooLong);
+        }
+        let mut parser = Parser {
+            tokens,
+            position: 0,
+            depth: 0,
+        };
+        let segment = parser.parse_or()?;
+        match parser.next() {
+            None => Ok(segment),
+            Some(token) if token.kind == TokenKind::RightParenthesis => {
+                Err(SegmentError::UnmatchedParenthesis {
+                    column: token.column,
+                })
+            }
+            Some(token) => Err(SegmentError::ExpectedOperator {
+                found: token.describe(),
+                column: token.column,
+            }),
+        }
+    }
+
+    /// Append a SQL condition matching the subscribers in the segment.
+    ///
+    /// `subscriber_id` is the column holding the id of the subscriber to test, e.g. `s.id`.
+    pub fn push_sql_filter(&self, query: &mut QueryBuilder<'_, Postgres>, subscriber_id: &str) {
+        match self {
+            Segment::Tag(name) => {
+                query
+                    .push(
+                        "EXISTS (SELECT 1 FROM subscriber_tags st \
+                        JOIN tags t ON t.tag_id = st.tag_id \
+                        WHERE st.subscriber_id = ",
+                    )
+                    .push(subscriber_id)
+                    .push(" AND t.name = ")
+                    .push_bind(name.as_ref().to_owned())
+                    .push(")");
+            }
+            Segment::Not(segment) => {
+                query.push("NOT ");
+                segment.push_sql_filter(query, subscriber_id);
+            }
+            Segment::And(left, right) | Segment::Or(left, right) => {
+                let operator = match self {
+                    Segment::And(..) => " AND ",
+                    _ => " OR ",
+                };
+                query.push("(");
+                left.push_sql_filter(query, subscriber_id);
+                query.push(operator);
+                right.push_sql_filter(query, subscriber_id);
+                query.push(")");
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum TokenKind {
+    LeftParenthesis,
+    RightParenthesis,
+    And,
+    Or,
+    Not,
+    Tag(TagName),
+}
+
+struct Token {
+    kind: TokenKind,
+    text: String,
+    column: usize,
+}
+
+impl Token {
+    fn describe(&self) -> String {
+        format!("`{}`", self.text)
+    }
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, SegmentError> {
+    let is_word_character = |c: char| c.is_alphanumeric() || c == '-' || c == '_';
+    let mut tokens = Vec::new();
+    let mut characters = s.chars().zip(1..).peekable();
+    while let Some((character, column)) = characters.next() {
+        let kind = match character {
+            c if c.is_whitespace() => continue,
+            '(' => TokenKind::LeftParenthesis,
+            ')' => TokenKind::RightParenthesis,
+            c if is_word_character(c) => {
+                let mut word = c.to_string();
+                while let Some((c, _)) = characters.next_if(|(c, _)| is_word_character(*c)) {
+                    word.push(c);
+                }
+                let kind = match word.to_ascii_lowercase().as_str() {
+                    "and" => TokenKind::And,
+                    "or" => TokenKind::Or,
+                    "not" => TokenKind::Not,
+                    _ => TokenKind::Tag(TagName::parse(&word).map_err(|_| {
+                        SegmentError::InvalidTagName {
+                            name: word.clone(),
+                            column,
+                        }
+                    })?),
+                };
+                tokens.push(Token {
+                    kind,
+                    text: word,
+                    column,
+                });
+                continue;
+            }
+            character => {
+                return Err(SegmentError::UnexpectedCharacter { character, column });
+            }
+        };
+        tokens.push(Token {
+            kind,
+            text: character.to_string(),
+            column,
+        });
+    }
+    Ok(tokens)
+}
+
+/// A recursive descent parser, with one function per precedence level.
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&TokenKind> {
+        self.tokens.get(self.position).map(|token| &token.kind)
+    }
+
+    fn next(&mut self) -> Option<&Token> {
+        let token = self.tokens.get(self.position)?;
+        self.position += 1;
+        Some(token)
+    }
+
+    fn enter(&mut self) -> Result<(), SegmentError> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(SegmentError::TooDeep);
+        }
+        Ok(())
+    }
+
+    fn parse_or(&mut self) -> Result<Segment, SegmentError> {
+        let mut segment = self.parse_and()?;
+        while self.peek() == Some(&TokenKind::Or) {
+            self.position += 1;
+            segment = Segment::Or(Box::new(segment), Box::new(self.parse_and()?));
+        }
+        Ok(segment)
+    }
+
+    fn parse_and(&mut self) -> Result<Segment, SegmentError> {
+        let mut segment = self.parse_not()?;
+        while self.peek() == Some(&TokenKind::And) {
+            self.position += 1;
+            segment = Segment::And(Box::new(segment), Box::new(self.parse_not()?));
+        }
+        Ok(segment)
+    }
+
+    fn parse_not(&mut self) -> Result<Segment, SegmentError> {
+        if self.peek() != Some(&TokenKind::Not) {
+            return self.parse_operand();
+        }
+        self.position += 1;
+        self.enter()?;
+        let segment = Segment::Not(Box::new(self.parse_not()?));
+        self.depth -= 1;
+        Ok(segment)
+    }
+
+    fn parse_operand(&mut self) -> Result<Segment, SegmentError> {
+        let end_column = self
+            .tokens
+            .last()
+            .map_or(1, |token| token.column + token.text.chars().count());
+        let Some(token) = self.next() else {
+            return Err(SegmentError::ExpectedOperand {
+                found: "the end of the expression".into(),
+                column: end_column,
+            });
+        };
+        match &token.kind {
+            TokenKind::Tag(name) => Ok(Segment::Tag(name.clone())),
+            TokenKind::LeftParenthesis => {
+                let opened_at = token.column;
+                self.enter()?;
+                let segment = self.parse_or()?;
+                match self.next() {
+                    Some(token) if token.kind == TokenKind::RightParenthesis => {}
+                    Some(token) => {
+                        return Err(SegmentError::ExpectedOperator {
+                            found: token.describe(),
+                            column: token.column,
+                        })
+                    }
+                    None => return Err(SegmentError::UnclosedParenthesis { column: opened_at }),
+                }
+                self.depth -= 1;
+                Ok(segment)
+            }
+            _ => Err(SegmentError::ExpectedOperand {
+                found: token.describe(),
+                column: token.column,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::assert_err_eq;
+    use sqlx::{Postgres, QueryBuilder};
+
+    use super::{Segment, SegmentError, MAX_DEPTH, MAX_TERMS};
+    use crate::domain::TagName;
+
+    fn tag(name: &str) -> Box<Segment> {
+        Box::new(Segment::Tag(TagName::parse(name).unwrap()))
+    }
+
+    #[test]
+    fn not_binds_tighter_than_and_which_binds_tighter_than_or() {
+        let segment = Segment::parse("a OR NOT b AND c").unwrap();
+        assert_eq!(
+            segment,
+            Segment::Or(
+                tag("a"),
+                Box::new(Segment::And(Box::new(Segment::Not(tag("b"))), tag("c")))
+            )
+        );
+    }
+
+    #[test]
+    fn parentheses_override_precedence() {
+        let segment = Segment::parse("(a or b) and not(c)").unwrap();
+        assert_eq!(
+            segment,
+            Segment::And(
+                Box::new(Segment::Or(tag("a"), tag("b"))),
+                Box::new(Segment::Not(tag("c")))
+            )
+        );
+    }
+
+    #[test]
+    fn binary_operators_are_left_associative() {
+        let segment = Segment::parse("a AND b AND c").unwrap();
+        assert_eq!(
+            segment,
+            Segment::And(Box::new(Segment::And(tag("a"), tag("b"))), tag("c"))
+        );
+    }
+
+    #[test]
+    fn malformed_expressions_are_rejected_with_the_column_of_the_problem() {
+        let cases = [
+            ("  ", SegmentError::Empty),
+            (
+                "beta & customer",
+                SegmentError::UnexpectedCharacter {
+                    character: '&',
+                    column: 6,
+                },
+            ),
+            (
+                "Beta",
+                SegmentError::InvalidTagName {
+                    name: "Beta".into(),
+                    column: 1,
+                },
+            ),
+            (
+                "beta AND",
+                SegmentError::ExpectedOperand {
+                    found: "the end of the expression".into(),
+                    column: 9,
+                },
+            ),
+            (
+                "beta AND OR customer",
+                SegmentError::ExpectedOperand {
+                    found: "`OR`".into(),
+                    column: 10,
+                },
+            ),
+            (
+                "beta customer",
+                SegmentError::ExpectedOperator {
+                    found: "`customer`".into(),
+                    column: 6,
+                },
+            ),
+            (
+                "(beta OR customer",
+                SegmentError::UnclosedParenthesis { column: 1 },
+            ),
+            ("beta)", SegmentError::UnmatchedParenthesis { column: 5 }),
+        ];
+        for (expression, error) in cases {
+            assert_err_eq!(Segment::parse(expression), error, "{}", expression);
+        }
+    }
+
+    #[test]
+    fn deeply_nested_expressions_are_rejected() {
+        let expression = format!(
+            "{}beta{}",
+            "(".repeat(MAX_DEPTH + 1),
+            ")".repeat(MAX_DEPTH + 1)
+        );
+        assert_err_eq!(Segment::parse(expression), SegmentError::TooDeep);
+        assert_err_eq!(
+            Segment::parse(format!("{}beta", "NOT ".repeat(MAX_DEPTH + 1))),
+            SegmentError::TooDeep
+        );
+    }
+
+    #[test]
+    fn long_chains_of_operators_are_rejected() {
+        // `n` tags joined by `n - 1` operators.
+        let chain = |n: usize, operator: &str| vec!["beta"; n].join(operator);
+        assert!(Segment::parse(chain(MAX_TERMS / 2, " AND ")).is_ok());
+        assert_err_eq!(
+            Segment::parse(chain(MAX_TERMS, " AND ")),
+            SegmentError::TooLong
+        );
+        assert_err_eq!(
+            Segment::parse(chain(100_000, " OR ")),
+            SegmentError::TooLong
+        );
+    }
+
+    #[test]
+    fn tag_names_are_bound_as_query_parameters() {
+        let segment = Segment::parse("beta AND NOT churned").unwrap();
+        let mut query = QueryBuilder::<Postgres>::new("");
+        segment.push_sql_filter(&mut query, "s.id");
+        let sql = query.sql();
+        assert!(sql.starts_with("(EXISTS ("));
+        assert!(sql.contains("st.subscriber_id = s.id AND t.name = $1"));
+        assert!(sql.contains(" AND NOT EXISTS ("));
+        assert!(sql.contains("t.name = $2"));
+        assert!(!sql.contains("beta"));
+    }
+}
@@ -0,0 +1,97 @@
+use std::collections::HashMap;
+
+use actix_session::storage::{LoadError, SaveError, SessionKey, SessionStore, UpdateError};
+use actix_web::cookie::time::Duration;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use sqlx::PgPool;
+
+use crate::configuration::{SessionSettings, SessionStoreKind};
+
+pub use memory::MemorySessionStore;
+pub use postgres::PostgresSessionStore;
+
+mod memory;
+mod postgres;
+
+type SessionState = HashMap<String, String>;
+
+/// The server-side session storage selected in `SessionSettings`.
+///
+/// `SessionMiddleware` is generic over its store,
+/// so we dispatch over the supported backends with an enum.
+#[derive(Clone)]
+pub enum AppSessionStore {
+    Postgres(PostgresSessionStore),
+    Memory(MemorySessionStore),
+}
+
+impl AppSessionStore {
+    pub fn new(settings: &SessionSettings, pool: PgPool) -> Self {
+        match settings.store {
+            SessionStoreKind::Postgres => Self::Postgres(PostgresSessionStore::new(pool)),
+            SessionStoreKind::Memory => Self::Memory(MemorySessionStore::default()),
+        }
+    }
+}
+
+impl SessionStore for AppSessionStore {
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<SessionState>, LoadError> {
+        match self {
+            Self::Postgres(store) => store.load(session_key).await,
+            Self::Memory(store) => store.load(session_key).await,
+        }
+    }
+
+    async fn save(
+        &self,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, SaveError> {
+        match self {
+            Self::Postgres(store) => store.save(session_state, ttl).await,
+            Self::Memory(store) => store.save(session_state, ttl).await,
+        }
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        match self {
+            Self::Postgres(store) => store.update(session_key, session_state, ttl).await,
+            Self::Memory(store) => store.update(session_key, session_state, ttl).await,
+        }
+    }
+
+    async fn update_ttl(
+        &self,
+        session_key: &SessionKey,
+        ttl: &Duration,
+    ) -> Result<(), anyhow::Error> {
+        match self {
+            Self::Postgres(store) => store.update_ttl(session_key, ttl).await,
+            Self::Memory(store) => store.update_ttl(session_key, ttl).await,
+        }
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> Result<(), anyhow::Error> {
+        match self {
+            Self::Postgres(store) => store.delete(session_key).await,
+            Self::Memory(store) => store.delete(session_key).await,
+        }
+    }
+}
+
+/// A random 64 characters long alphanumeric key, i.e. ~380 bits of entropy.
+fn generate_session_key() -> SessionKey {
+    let key: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(64)
+        .map(char::from)
+        .collect();
+    key.try_into()
+        .expect("A 64 characters long key is a valid session key.")
+}
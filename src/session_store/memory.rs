@@ -0,0 +1,74 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
+
+use actix_session::storage::{LoadError, SaveError, SessionKey, SessionStore, UpdateError};
+use actix_web::cookie::time::Duration;
+
+use super::{generate_session_key, SessionState};
+
+/// Keeps session states in the memory of the current process.
+#[derive(Clone, Default)]
+pub struct MemorySessionStore {
+    sessions: Arc<RwLock<HashMap<String, (SessionState, Instant)>>>,
+}
+
+fn expires_at(ttl: &Duration) -> Instant {
+    Instant::now() + std::time::Duration::from_secs(ttl.whole_seconds().max(0) as u64)
+}
+
+impl SessionStore for MemorySessionStore {
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<SessionState>, LoadError> {
+        let sessions = self.sessions.read().unwrap();
+        Ok(sessions
+            .get(session_key.as_ref())
+            .filter(|(_, expires_at)| *expires_at > Instant::now())
+            .map(|(session_state, _)| session_state.clone()))
+    }
+
+    async fn save(
+        &self,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, SaveError> {
+        let session_key = generate_session_key();
+        let mut sessions = self.sessions.write().unwrap();
+        sessions.retain(|_, (_, expires_at)| *expires_at > Instant::now());
+        sessions.insert(
+            session_key.as_ref().to_owned(),
+            (session_state, expires_at(ttl)),
+        );
+        Ok(session_key)
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        let mut sessions = self.sessions.write().unwrap();
+        sessions.insert(
+            session_key.as_ref().to_owned(),
+            (session_state, expires_at(ttl)),
+        );
+        Ok(session_key)
+    }
+
+    async fn update_ttl(
+        &self,
+        session_key: &SessionKey,
+        ttl: &Duration,
+    ) -> Result<(), anyhow::Error> {
+        let mut sessions = self.sessions.write().unwrap();
+        if let Some((_, deadline)) = sessions.get_mut(session_key.as_ref()) {
+            *deadline = expires_at(ttl);
+        }
+        Ok(())
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> Result<(), anyhow::Error> {
+        self.sessions.write().unwrap().remove(session_key.as_ref());
+        Ok(())
+    }
+}
@@ -1,36 +1,55 @@
 use std::net::TcpListener;
+use std::sync::Arc;
 
+use actix_session::config::CookieContentSecurity;
+use actix_session::SessionMiddleware;
+use actix_web::cookie::{Key, SameSite};
 use actix_web::dev::Server;
+use actix_web::middleware::from_fn;
 use actix_web::{web, App, HttpServer};
+use anyhow::Context;
+use secrecy::{ExposeSecret, Secret};
 use sqlx::postgres::PgPoolOptions;
 use sqlx::PgPool;
+use tokio::task::JoinSet;
 use tracing_actix_web::TracingLogger;
 
-use crate::configuration::{DatabaseSettings, Settings};
-use crate::email_client::EmailClient;
-use crate::routes::{confirm, health_check, publish_newsletter, subscribe};
+use crate::authentication::{reject_anonymous_users, PasswordHashing, PasswordPolicy};
+use crate::configuration::{
+    DatabaseSettings, IdempotencySettings, LoginThrottlingSettings, SessionSettings, Settings,
+    SubscriptionSettings,
+};
+use crate::email_client::EmailSender;
+use crate::idempotency::run_expiry_worker_until_stopped;
+use crate::issue_delivery_worker::run_worker_until_stopped;
+use crate::issue_scheduler::run_scheduler_until_stopped;
+use crate::routes::{
+    add_tag, admin_dashboard, archive, archived_issue, atom_feed, cancel_issue, change_email,
+    change_name, change_password, change_password_form, clear_lockout, confirm,
+    confirm_email_change, create_draft, create_list, health_check, issue_failures_csv,
+    issue_report, issues, list_scheduled_issues, lists, lockouts, log_out, login, login_form,
+    pause_delivery, preferences_form, preview_draft, publish_draft, publish_newsletter, remove_tag,
+    reschedule_issue, resend_confirmation, rss_feed, subscribe, tags, test_send_draft, unsubscribe,
+    unsubscribe_form, update_draft,
+};
+use crate::session_store::AppSessionStore;
 
 pub struct Application {
     port: u16,
     server: Server,
+    background_workers: JoinSet<Result<(), anyhow::Error>>,
 }
 
 impl Application {
     pub async fn build(configuration: Settings) -> Result<Self, std::io::Error> {
         let connection_pool = get_connection_pool(&configuration.database);
-
-        let sender_email = configuration
-            .email_client
-            .sender()
-            .expect("Invalid sender email address.");
-        let timeout = configuration.email_client.timeout();
-
-        let email_client = EmailClient::new(
-            configuration.email_client.base_url,
-            sender_email,
-            configuration.email_client.authorization_token,
-            timeout,
-        );
+        let email_client = configuration.email_client.client();
+        let base_url = ApplicationBaseUrl(configuration.application.base_url);
+        let hmac_secret = HmacSecret(configuration.application.hmac_secret);
+        let session_key = session_key(&configuration.session)?;
+        let password_policy = PasswordPolicy::from_settings(&configuration.password_policy)?;
+        let password_hashing = PasswordHashing::new(configuration.password_hashing.params())
+            .map_err(std::io::Error::other)?;
 
         let address = format!(
             "{}:{}",
@@ -40,48 +59,217 @@ impl Application {
         // This is to get the actual port we're listening on in the case when configuration port is 0.
         // In other cases, this should be the same as configuration port.
         let port = listener.local_addr().unwrap().port();
+
+        let mut background_workers = JoinSet::new();
+        for _ in 0..configuration.application.delivery_workers {
+            background_workers.spawn(run_worker_until_stopped(
+                connection_pool.clone(),
+                email_client.clone(),
+                base_url.clone(),
+                hmac_secret.clone(),
+            ));
+        }
+        if configuration.application.issue_scheduler {
+            background_workers.spawn(run_scheduler_until_stopped(connection_pool.clone()));
+        }
+        background_workers.spawn(run_expiry_worker_until_stopped(
+            connection_pool.clone(),
+            configuration.idempotency.expiration(),
+            configuration.idempotency.cleanup_interval(),
+        ));
+
         let server = run(
             listener,
             connection_pool,
             email_client,
-            configuration.application.base_url,
+            base_url,
+            hmac_secret,
+            configuration.idempotency,
+            configuration.session,
+            session_key,
+            password_policy,
+            password_hashing,
+            configuration.login_throttling,
+            configuration.subscriptions,
         )?;
 
-        Ok(Self { port, server })
+        Ok(Self {
+            port,
+            server,
+            background_workers,
+        })
     }
 
     pub fn port(&self) -> u16 {
         self.port
     }
 
-    pub async fn run_until_stopped(self) -> Result<(), std::io::Error> {
-        self.server.await
+    /// Run the API until either it or one of the background workers exits.
+    pub async fn run_until_stopped(mut self) -> Result<(), anyhow::Error> {
+        tokio::select! {
+            outcome = self.server => outcome.context("The API server failed"),
+            Some(outcome) = self.background_workers.join_next() => {
+                outcome.context("A background worker panicked")??;
+                Err(anyhow::anyhow!("A background worker exited unexpectedly"))
+            }
+        }
     }
 }
 
+/// The key signing session cookies, rejected upfront if too short for `Key::from`.
+fn session_key(settings: &SessionSettings) -> Result<Key, std::io::Error> {
+    Key::try_from(settings.secret_key.expose_secret().as_bytes()).map_err(|_| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "The session secret key must be at least 64 bytes long.",
+        )
+    })
+}
+
 pub fn get_connection_pool(configuration: &DatabaseSettings) -> PgPool {
     PgPoolOptions::new().connect_lazy_with(configuration.with_db())
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn run(
     listener: TcpListener,
     db_pool: PgPool,
-    email_client: EmailClient,
-    base_url: String,
+    email_client: Arc<dyn EmailSender>,
+    base_url: ApplicationBaseUrl,
+    hmac_secret: HmacSecret,
+    idempotency_settings: IdempotencySettings,
+    session_settings: SessionSettings,
+    session_key: Key,
+    password_policy: PasswordPolicy,
+    password_hashing: PasswordHashing,
+    login_throttling: LoginThrottlingSettings,
+    subscription_settings: SubscriptionSettings,
 ) -> Result<Server, std::io::Error> {
+    let session_store = AppSessionStore::new(&session_settings, db_pool.clone());
     let db_pool = web::Data::new(db_pool);
-    let email_client = web::Data::new(email_client);
-    let base_url = web::Data::new(ApplicationBaseUrl(base_url));
+    let email_client = web::Data::from(email_client);
+    let base_url = web::Data::new(base_url);
+    let hmac_secret = web::Data::new(hmac_secret);
+    let idempotency_settings = web::Data::new(idempotency_settings);
+    let password_policy = web::Data::new(password_policy);
+    let password_hashing = web::Data::new(password_hashing);
+    let login_throttling = web::Data::new(login_throttling);
+    let subscription_settings = web::Data::new(subscription_settings);
     let server = HttpServer::new(move || {
         App::new()
+            .wrap(
+                SessionMiddleware::builder(session_store.clone(), session_key.clone())
+                    .cookie_content_security(CookieContentSecurity::Signed)
+                    .cookie_http_only(true)
+                    .cookie_same_site(SameSite::Strict)
+                    .cookie_secure(session_settings.cookie_secure)
+                    .build(),
+            )
             .wrap(TracingLogger::default())
             .route("/health_check", web::get().to(health_check))
+            .route("/login", web::get().to(login_form))
+            .route("/login", web::post().to(login))
+            .service(
+                web::scope("/admin")
+                    .wrap(from_fn(reject_anonymous_users))
+                    .route("/dashboard", web::get().to(admin_dashboard))
+                    .route("/password", web::get().to(change_password_form))
+                    .route("/password", web::post().to(change_password))
+                    .route("/issues", web::get().to(issues))
+                    .route(
+                        "/issues/{newsletter_issue_id}/report",
+                        web::get().to(issue_report),
+                    )
+                    .route(
+                        "/issues/{newsletter_issue_id}/report/failures.csv",
+                        web::get().to(issue_failures_csv),
+                    )
+                    .route("/lists", web::get().to(lists))
+                    .route("/lists", web::post().to(create_list))
+                    .route("/lockouts", web::get().to(lockouts))
+                    .route(
+                        "/lockouts/{lockout_id}/clear",
+                        web::post().to(clear_lockout),
+                    )
+                    .route("/tags", web::get().to(tags))
+                    .route("/tags/add", web::post().to(add_tag))
+                    .route("/tags/remove", web::post().to(remove_tag))
+                    .route("/logout", web::post().to(log_out)),
+            )
             .route("/subscriptions", web::post().to(subscribe))
             .route("/subscriptions/confirm", web::get().to(confirm))
+            .route(
+                "/subscriptions/preferences",
+                web::get().to(preferences_form),
+            )
+            .route(
+                "/subscriptions/preferences/name",
+                web::post().to(change_name),
+            )
+            .route(
+                "/subscriptions/preferences/email",
+                web::post().to(change_email),
+            )
+            .route(
+                "/subscriptions/preferences/confirm-email",
+                web::get().to(confirm_email_change),
+            )
+            .route(
+                "/subscriptions/preferences/pause",
+                web::post().to(pause_delivery),
+            )
+            .route(
+                "/subscriptions/resend-confirmation",
+                web::post().to(resend_confirmation),
+            )
+            .route(
+                "/subscriptions/unsubscribe",
+                web::get().to(unsubscribe_form),
+            )
+            .route("/subscriptions/unsubscribe", web::post().to(unsubscribe))
+            .route("/archive", web::get().to(archive))
+            .route("/archive/{slug}", web::get().to(archived_issue))
+            .route("/feed.rss", web::get().to(rss_feed))
+            .route("/feed.atom", web::get().to(atom_feed))
             .route("/newsletters", web::post().to(publish_newsletter))
+            .route("/newsletters/drafts", web::post().to(create_draft))
+            .route(
+                "/newsletters/drafts/{newsletter_issue_id}",
+                web::put().to(update_draft),
+            )
+            .route(
+                "/newsletters/drafts/{newsletter_issue_id}/preview",
+                web::get().to(preview_draft),
+            )
+            .route(
+                "/newsletters/drafts/{newsletter_issue_id}/test-send",
+                web::post().to(test_send_draft),
+            )
+            .route(
+                "/newsletters/drafts/{newsletter_issue_id}/publish",
+                web::post().to(publish_draft),
+            )
+            .route(
+                "/newsletters/scheduled",
+                web::get().to(list_scheduled_issues),
+            )
+            .route(
+                "/newsletters/scheduled/{newsletter_issue_id}/reschedule",
+                web::post().to(reschedule_issue),
+            )
+            .route(
+                "/newsletters/scheduled/{newsletter_issue_id}/cancel",
+                web::post().to(cancel_issue),
+            )
             .app_data(db_pool.clone())
             .app_data(email_client.clone())
             .app_data(base_url.clone())
+            .app_data(hmac_secret.clone())
+            .app_data(idempotency_settings.clone())
+            .app_data(password_policy.clone())
+            .app_data(password_hashing.clone())
+            .app_data(login_throttling.clone())
+            .app_data(subscription_settings.clone())
     })
     .listen(listener)?
     .run();
@@ -92,5 +280,10 @@ pub fn run(
 /// We need to define a wrapper type in order to retrieve the URL in the `subscribe` handler.
 /// Retrieval from the context, in actix-web, is type-based:
 /// using a raw `String` would expose us to conflicts.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ApplicationBaseUrl(pub String);
+
+/// The secret used to sign links we hand out to subscribers (e.g. unsubscribe links).
+/// Wrapped for the same reason as `ApplicationBaseUrl`.
+#[derive(Clone)]
+pub struct HmacSecret(pub Secret<String>);
@@ -0,0 +1,90 @@
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::domain::TagName;
+
+pub struct Tag {
+    pub name: String,
+    pub n_subscribers: i64,
+}
+
+pub async fn get_tags(pool: &PgPool) -> Result<Vec<Tag>, sqlx::Error> {
+    sqlx::query_as!(
+        Tag,
+        r#"
+        SELECT t.name, count(st.subscriber_id) as "n_subscribers!"
+        FROM tags t
+        LEFT JOIN subscriber_tags st ON st.tag_id = t.tag_id
+        GROUP BY t.tag_id
+        ORDER BY t.name
+        "#
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Tag the subscribers with the given email addresses, creating the tag if needed.
+///
+/// Return how many subscribers were not tagged already.
+/// Addresses without a subscriber are ignored.
+pub async fn tag_subscribers(
+    pool: &PgPool,
+    tag: &TagName,
+    emails: &[String],
+) -> Result<u64, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    // `DO UPDATE` rather than `DO NOTHING`, so that the id of an existing tag is returned too.
+    let tag_id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO tags (tag_id, name)
+        VALUES ($1, $2)
+        ON CONFLICT (name) DO UPDATE SET name = EXCLUDED.name
+        RETURNING tag_id
+        "#,
+        Uuid::new_v4(),
+        tag.as_ref()
+    )
+    .fetch_one(&mut *transaction)
+    .await?;
+    let n_tagged = sqlx::query!(
+        r#"
+        INSERT INTO subscriber_tags (subscriber_id, tag_id)
+        SELECT id, $1
+        FROM subscriptions
+        WHERE email = ANY($2)
+        ON CONFLICT DO NOTHING
+        "#,
+        tag_id,
+        emails
+    )
+    .execute(&mut *transaction)
+    .await?
+    .rows_affected();
+    transaction.commit().await?;
+    Ok(n_tagged)
+}
+
+/// Return how many subscribers were actually tagged.
+pub async fn untag_subscribers(
+    pool: &PgPool,
+    tag: &TagName,
+    emails: &[String],
+) -> Result<u64, sqlx::Error> {
+    let n_untagged = sqlx::query!(
+        r#"
+        DELETE FROM subscriber_tags st
+        USING tags t, subscriptions s
+        WHERE
+            t.tag_id = st.tag_id AND
+            s.id = st.subscriber_id AND
+            t.name = $1 AND
+            s.email = ANY($2)
+        "#,
+        tag.as_ref(),
+        emails
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+    Ok(n_untagged)
+}
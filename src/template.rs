@@ -0,0 +1,237 @@
+//! Newsletter bodies are templates, personalised for each recipient.
+//!
+//! `{{ subscriber.name }}` is replaced with the name of the recipient, and so on:
+//! see `Variable` for the full list. There are no other constructs.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Variable {
+    SubscriberName,
+    SubscriberEmail,
+    IssueTitle,
+    UnsubscribeUrl,
+    PreferencesUrl,
+}
+
+impl Variable {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "subscriber.name" => Some(Self::SubscriberName),
+            "subscriber.email" => Some(Self::SubscriberEmail),
+            "issue.title" => Some(Self::IssueTitle),
+            "unsubscribe_url" => Some(Self::UnsubscribeUrl),
+            "preferences_url" => Some(Self::PreferencesUrl),
+            _ => None,
+        }
+    }
+}
+
+/// The values of the variables for one recipient.
+pub struct TemplateContext<'a> {
+    pub subscriber_name: &'a str,
+    pub subscriber_email: &'a str,
+    pub issue_title: &'a str,
+    pub unsubscribe_url: &'a str,
+    pub preferences_url: &'a str,
+}
+
+impl TemplateContext<'_> {
+    fn value(&self, variable: Variable) -> &str {
+        match variable {
+            Variable::SubscriberName => self.subscriber_name,
+            Variable::SubscriberEmail => self.subscriber_email,
+            Variable::IssueTitle => self.issue_title,
+            Variable::UnsubscribeUrl => self.unsubscribe_url,
+            Variable::PreferencesUrl => self.preferences_url,
+        }
+    }
+}
+
+/// Lines and columns are counted in characters, starting from 1.
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum TemplateError {
+    #[error("The `{{{{` at line {line}, column {column} is never closed.")]
+    UnclosedTag { line: usize, column: usize },
+    #[error("Unknown variable `{name}` at line {line}, column {column}.")]
+    UnknownVariable {
+        name: String,
+        line: usize,
+        column: usize,
+    },
+}
+
+#[derive(Debug, PartialEq)]
+enum Part {
+    Literal(String),
+    Variable(Variable),
+}
+
+#[derive(Debug)]
+pub struct Template(Vec<Part>);
+
+impl Template {
+    pub fn parse(source: &str) -> Result<Self, TemplateError> {
+        let mut parts = Vec::new();
+        let mut rest = source;
+        while let Some(start) = rest.find("{{") {
+            if start > 0 {
+                parts.push(Part::Literal(rest[..start].to_owned()));
+            }
+            let offset = source.len() - rest.len() + start;
+            let Some(length) = rest[start..].find("}}") else {
+                let (line, column) = position(source, offset);
+                return Err(TemplateError::UnclosedTag { line, column });
+            };
+            let name = rest[start + 2..start + length].trim();
+            let variable = Variable::parse(name).ok_or_else(|| {
+                let (line, column) = position(source, offset);
+                TemplateError::UnknownVariable {
+                    name: name.to_owned(),
+                    line,
+                    column,
+                }
+            })?;
+            parts.push(Part::Variable(variable));
+            rest = &rest[start + length + 2..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Literal(rest.to_owned()));
+        }
+        Ok(Self(parts))
+    }
+
+    /// A template rendering `source` as is, whatever it contains.
+    pub fn verbatim(source: &str) -> Self {
+        Self(vec![Part::Literal(source.to_owned())])
+    }
+
+    /// Values are escaped: they are safe to use in text and in quoted attributes.
+    pub fn render_html(&self, context: &TemplateContext) -> String {
+        self.render(|variable| htmlescape::encode_minimal(context.value(variable)))
+    }
+
+    pub fn render_text(&self, context: &TemplateContext) -> String {
+        self.render(|variable| context.value(variable).to_owned())
+    }
+
+    fn render(&self, value: impl Fn(Variable) -> String) -> String {
+        let mut rendered = String::new();
+        for part in &self.0 {
+            match part {
+                Part::Literal(literal) => rendered.push_str(literal),
+                Part::Variable(variable) => rendered.push_str(&value(*variable)),
+            }
+        }
+        rendered
+    }
+}
+
+/// Render the bodies of the email delivering an issue to one recipient, footer included.
+pub fn render_email(
+    html_content: &str,
+    text_content: &str,
+    context: &TemplateContext,
+) -> (String, String) {
+    let html_body = format!(
+        "{}<p><a href=\"{}\">Manage your subscription</a> or \
+        <a href=\"{}\">unsubscribe</a> from this newsletter.</p>",
+        parse_template(html_content).render_html(context),
+        context.preferences_url,
+        context.unsubscribe_url
+    );
+    let text_body = format!(
+        "{}\n\nManage your subscription: {}\nUnsubscribe from this newsletter: {}",
+        parse_template(text_content).render_text(context),
+        context.preferences_url,
+        context.unsubscribe_url
+    );
+    (html_body, text_body)
+}
+
+/// Templates are validated when an issue is published,
+/// but issues published before templates were introduced are rendered as they are.
+pub fn parse_template(source: &str) -> Template {
+    Template::parse(source).unwrap_or_else(|e| {
+        tracing::warn!(error.message = %e, "Rendering an invalid template verbatim.");
+        Template::verbatim(source)
+    })
+}
+
+/// Return the line and column of the character starting at byte `offset`.
+fn position(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    (line, before[line_start..].chars().count() + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_err_eq, assert_ok};
+
+    use super::{Template, TemplateContext, TemplateError};
+
+    fn context() -> TemplateContext<'static> {
+        TemplateContext {
+            subscriber_name: "Ursula <Le Guin>",
+            subscriber_email: "ursula@example.com",
+            issue_title: "Tom & Jerry",
+            unsubscribe_url: "https://example.com/unsubscribe?a=1&b=2",
+            preferences_url: "https://example.com/preferences",
+        }
+    }
+
+    #[test]
+    fn variables_are_replaced_with_their_value() {
+        let template =
+            Template::parse("Hi {{ subscriber.name }}, welcome to {{issue.title}}!").unwrap();
+        assert_eq!(
+            template.render_text(&context()),
+            "Hi Ursula <Le Guin>, welcome to Tom & Jerry!"
+        );
+    }
+
+    #[test]
+    fn values_are_escaped_in_html() {
+        let template = Template::parse(
+            r#"<p>Hi {{ subscriber.name }}</p><a href="{{ unsubscribe_url }}">Bye</a>"#,
+        )
+        .unwrap();
+        assert_eq!(
+            template.render_html(&context()),
+            r#"<p>Hi Ursula &lt;Le Guin&gt;</p><a href="https://example.com/unsubscribe?a=1&amp;b=2">Bye</a>"#
+        );
+    }
+
+    #[test]
+    fn a_template_without_variables_is_rendered_as_is() {
+        let template = Template::parse("<p>Plain {content} }}</p>").unwrap();
+        assert_eq!(
+            template.render_html(&context()),
+            "<p>Plain {content} }}</p>"
+        );
+        assert_ok!(Template::parse(""));
+    }
+
+    #[test]
+    fn unknown_variables_are_rejected_with_their_position() {
+        assert_err_eq!(
+            Template::parse("Hello\nDear {{ subscriber.age }}"),
+            TemplateError::UnknownVariable {
+                name: "subscriber.age".into(),
+                line: 2,
+                column: 6
+            }
+        );
+    }
+
+    #[test]
+    fn unclosed_tags_are_rejected_with_their_position() {
+        assert_err_eq!(
+            Template::parse("Dear {{ subscriber.name }}, bye {{ unsubscribe_url"),
+            TemplateError::UnclosedTag {
+                line: 1,
+                column: 33
+            }
+        );
+    }
+}
@@ -0,0 +1,64 @@
+use std::time::Duration;
+
+use actix_web::cookie::Cookie;
+use actix_web::http::header::{LOCATION, RETRY_AFTER};
+use actix_web::{HttpRequest, HttpResponse, HttpResponseBuilder};
+
+const FLASH_COOKIE_NAME: &str = "_flash";
+
+/// Return an opaque 500 while preserving the error root cause for logging.
+pub fn e500<T>(e: T) -> actix_web::Error
+where
+    T: std::fmt::Debug + std::fmt::Display + 'static,
+{
+    actix_web::error::ErrorInternalServerError(e)
+}
+
+pub fn see_other(location: &str) -> HttpResponse {
+    HttpResponse::SeeOther()
+        .insert_header((LOCATION, location))
+        .finish()
+}
+
+/// A 429, telling the client how many seconds to wait before trying again.
+pub fn too_many_requests(retry_after: Duration) -> HttpResponseBuilder {
+    // Round up: retrying a fraction of a second too early would be pointless.
+    let retry_after = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+    let mut response = HttpResponse::TooManyRequests();
+    response.insert_header((RETRY_AFTER, retry_after.max(1)));
+    response
+}
+
+/// Redirect to `location`, leaving a one-off message for the next page to display.
+pub fn see_other_with_flash(location: &str, message: &str) -> HttpResponse {
+    HttpResponse::SeeOther()
+        .insert_header((LOCATION, location))
+        .cookie(
+            Cookie::build(FLASH_COOKIE_NAME, message.to_owned())
+                // Without an explicit path, the cookie would be scoped to the redirecting route.
+                .path("/")
+                .http_only(true)
+                .finish(),
+        )
+        .finish()
+}
+
+/// Render the message left by `see_other_with_flash`, if any, as an HTML paragraph.
+///
+/// The cookie is not signed, so its content is escaped: anybody can set it.
+pub fn flash_message_html(request: &HttpRequest) -> String {
+    match request.cookie(FLASH_COOKIE_NAME) {
+        Some(cookie) => format!(
+            "<p><i>{}</i></p>",
+            htmlescape::encode_minimal(cookie.value())
+        ),
+        None => String::new(),
+    }
+}
+
+/// Make sure the flash message is only displayed once.
+pub fn clear_flash_message(response: &mut HttpResponse) {
+    response
+        .add_removal_cookie(&Cookie::build(FLASH_COOKIE_NAME, "").path("/").finish())
+        .expect("The flash cookie name is a valid header value.");
+}
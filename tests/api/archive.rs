@@ -0,0 +1,205 @@
+use chrono::{Duration, Utc};
+
+use crate::helpers::{spawn_app, TestApp};
+
+async fn publish_issue(app: &TestApp, title: &str, private: bool) {
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": title,
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Hi {{ subscriber.name }}, this is {{ issue.title }}</p>",
+            },
+            "private": private,
+        }))
+        .await;
+    assert_eq!(200, response.status().as_u16());
+}
+
+/// Return the paths the archive page links issues at, in order.
+fn issue_links(html: &str) -> Vec<String> {
+    html.split(r#"<a href=""#)
+        .skip(1)
+        .filter_map(|s| s.split_once('"').map(|(link, _)| link.to_owned()))
+        .filter(|link| link.starts_with("/archive/"))
+        .collect()
+}
+
+#[tokio::test]
+async fn published_issues_are_listed_and_readable_without_logging_in() {
+    // Arrange
+    let app = spawn_app().await;
+    publish_issue(&app, "Tom & Jerry", false).await;
+
+    // Act - Part 1 - The archive links to the issue
+    let html = app.get_archive_html("").await;
+    assert!(html.contains("Tom &amp; Jerry"));
+    let links = issue_links(&html);
+    assert_eq!(links.len(), 1);
+    assert!(links[0].starts_with("/archive/tom-jerry-"));
+
+    // Act - Part 2 - Follow the link
+    let response = app
+        .get_archive(links[0].trim_start_matches("/archive"))
+        .await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let html = response.text().await.unwrap();
+    assert!(html.contains("<p>Hi reader, this is Tom &amp; Jerry</p>"));
+    assert!(!html.contains("Manage your subscription"));
+}
+
+#[tokio::test]
+async fn scripts_are_stripped_from_archived_issues() {
+    // Arrange
+    let app = spawn_app().await;
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p onclick=\"steal()\">Hello</p><script>steal()</script>",
+            },
+            "private": false,
+        }))
+        .await;
+    assert_eq!(200, response.status().as_u16());
+    let slug = sqlx::query_scalar!("SELECT slug FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let html = app.get_archive_html(&format!("/{}", slug)).await;
+    let feed = app.get_feed("/feed.rss").await.text().await.unwrap();
+
+    // Assert
+    assert!(html.contains("<p>Hello</p>"));
+    assert!(!html.contains("steal()"));
+    assert!(!feed.contains("steal()"));
+}
+
+#[tokio::test]
+async fn private_issues_are_not_archived() {
+    // Arrange
+    let app = spawn_app().await;
+    publish_issue(&app, "Public issue", false).await;
+    publish_issue(&app, "Private issue", true).await;
+    let slug = sqlx::query_scalar!("SELECT slug FROM newsletter_issues WHERE private")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let html = app.get_archive_html("").await;
+    let response = app.get_archive(&format!("/{}", slug)).await;
+
+    // Assert
+    assert!(html.contains("Public issue"));
+    assert!(!html.contains("Private issue"));
+    assert_eq!(404, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn issues_sent_to_a_segment_are_private_unless_told_otherwise() {
+    // Arrange
+    let app = spawn_app().await;
+    for (title, private) in [
+        ("Segment issue", None),
+        ("Public segment issue", Some(false)),
+    ] {
+        let response = app
+            .post_newsletters(serde_json::json!({
+                "title": title,
+                "content": {
+                    "text": "Newsletter body as plain text",
+                    "html": "<p>Newsletter body as HTML</p>",
+                },
+                "segment": "beta",
+                "private": private,
+            }))
+            .await;
+        assert_eq!(200, response.status().as_u16());
+    }
+
+    // Act
+    let html = app.get_archive_html("").await;
+
+    // Assert
+    assert!(html.contains("Public segment issue"));
+    assert!(!html.contains(">Segment issue</a>"));
+}
+
+#[tokio::test]
+async fn drafts_and_scheduled_issues_are_not_archived() {
+    // Arrange
+    let app = spawn_app().await;
+    let response = app
+        .post_drafts(serde_json::json!({
+            "title": "Draft issue",
+            "content": { "markdown": "Not ready yet" },
+        }))
+        .await;
+    assert_eq!(201, response.status().as_u16());
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Scheduled issue",
+            "content": { "markdown": "Not due yet" },
+            "send_at": Utc::now() + Duration::hours(1),
+        }))
+        .await;
+    assert_eq!(202, response.status().as_u16());
+
+    // Act
+    let html = app.get_archive_html("").await;
+
+    // Assert
+    assert!(issue_links(&html).is_empty());
+    let slugs = sqlx::query_scalar!("SELECT slug FROM newsletter_issues")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    for slug in slugs {
+        let response = app.get_archive(&format!("/{}", slug)).await;
+        assert_eq!(404, response.status().as_u16());
+    }
+}
+
+#[tokio::test]
+async fn the_archive_is_paginated_from_the_most_recent_issue() {
+    // Arrange
+    let app = spawn_app().await;
+    for i in 1..=21 {
+        publish_issue(&app, &format!("Issue {}", i), false).await;
+    }
+
+    // Act
+    let first_page = app.get_archive_html("").await;
+    let second_page = app.get_archive_html("?page=2").await;
+
+    // Assert
+    let first_links = issue_links(&first_page);
+    assert_eq!(first_links.len(), 20);
+    assert!(first_links[0].starts_with("/archive/issue-21-"));
+    assert!(first_page.contains(r#"<a href="/archive?page=2">Older issues</a>"#));
+    assert!(!first_page.contains("Newer issues"));
+
+    let second_links = issue_links(&second_page);
+    assert_eq!(second_links.len(), 1);
+    assert!(second_links[0].starts_with("/archive/issue-1-"));
+    assert!(second_page.contains(r#"<a href="/archive?page=1">Newer issues</a>"#));
+    assert!(!second_page.contains("Older issues"));
+}
+
+#[tokio::test]
+async fn unknown_issues_are_not_found() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.get_archive("/no-such-issue-00000000").await;
+
+    // Assert
+    assert_eq!(404, response.status().as_u16());
+}
@@ -0,0 +1,143 @@
+use crate::helpers::{spawn_app, TestApp};
+
+async fn publish_issue(app: &TestApp, title: &str, private: bool) {
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": title,
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>This is {{ issue.title }}</p>",
+            },
+            "private": private,
+        }))
+        .await;
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn the_rss_feed_lists_archived_issues_with_absolute_links() {
+    // Arrange
+    let app = spawn_app().await;
+    publish_issue(&app, "First issue", false).await;
+    publish_issue(&app, "Private issue", true).await;
+    publish_issue(&app, "Second issue", false).await;
+
+    // Act
+    let response = app.get_feed("/feed.rss").await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "application/rss+xml; charset=utf-8"
+    );
+    let channel = rss::Channel::read_from(&response.bytes().await.unwrap()[..]).unwrap();
+    let titles: Vec<_> = channel.items.iter().map(|i| i.title.as_deref()).collect();
+    assert_eq!(titles, [Some("Second issue"), Some("First issue")]);
+    let item = &channel.items[0];
+    let link = item.link.as_deref().unwrap();
+    assert!(link.starts_with(&format!("{}/archive/second-issue-", app.base_url.0)));
+    assert_eq!(item.guid.as_ref().unwrap().value, link);
+    assert!(item.pub_date.is_some());
+    assert_eq!(
+        item.description.as_deref(),
+        Some("<p>This is Second issue</p>")
+    );
+}
+
+#[tokio::test]
+async fn the_atom_feed_lists_archived_issues_with_absolute_links() {
+    // Arrange
+    let app = spawn_app().await;
+    publish_issue(&app, "First issue", false).await;
+    publish_issue(&app, "Private issue", true).await;
+    publish_issue(&app, "Second issue", false).await;
+
+    // Act
+    let response = app.get_feed("/feed.atom").await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "application/atom+xml; charset=utf-8"
+    );
+    let feed = atom_syndication::Feed::read_from(&response.bytes().await.unwrap()[..]).unwrap();
+    let titles: Vec<_> = feed.entries.iter().map(|e| e.title.as_str()).collect();
+    assert_eq!(titles, ["Second issue", "First issue"]);
+    let entry = &feed.entries[0];
+    assert!(entry.links[0]
+        .href
+        .starts_with(&format!("{}/archive/second-issue-", app.base_url.0)));
+    assert_eq!(feed.updated, entry.updated);
+    assert!(feed
+        .links
+        .iter()
+        .any(|l| l.rel == "self" && l.href == format!("{}/feed.atom", app.base_url.0)));
+}
+
+#[tokio::test]
+async fn empty_feeds_are_valid() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let rss = app.get_feed("/feed.rss").await;
+    let atom = app.get_feed("/feed.atom").await;
+
+    // Assert
+    assert!(rss.headers().get("Last-Modified").is_none());
+    let channel = rss::Channel::read_from(&rss.bytes().await.unwrap()[..]).unwrap();
+    assert!(channel.items.is_empty());
+    let feed = atom_syndication::Feed::read_from(&atom.bytes().await.unwrap()[..]).unwrap();
+    assert!(feed.entries.is_empty());
+}
+
+#[tokio::test]
+async fn unchanged_feeds_are_not_sent_again() {
+    // Arrange
+    let app = spawn_app().await;
+    publish_issue(&app, "First issue", false).await;
+
+    for path in ["/feed.rss", "/feed.atom"] {
+        let response = app.get_feed(path).await;
+        let etag = response.headers()["ETag"].to_str().unwrap().to_owned();
+        let last_modified = response.headers()["Last-Modified"]
+            .to_str()
+            .unwrap()
+            .to_owned();
+
+        // Act
+        let by_etag = app
+            .get_feed_with_header(path, Some(("If-None-Match", &etag)))
+            .await;
+        let by_date = app
+            .get_feed_with_header(path, Some(("If-Modified-Since", &last_modified)))
+            .await;
+
+        // Assert
+        assert_eq!(304, by_etag.status().as_u16());
+        assert_eq!(by_etag.headers()["ETag"].to_str().unwrap(), etag);
+        assert!(by_etag.bytes().await.unwrap().is_empty());
+        assert_eq!(304, by_date.status().as_u16());
+    }
+}
+
+#[tokio::test]
+async fn feeds_are_sent_again_once_a_new_issue_is_published() {
+    // Arrange
+    let app = spawn_app().await;
+    publish_issue(&app, "First issue", false).await;
+    let response = app.get_feed("/feed.rss").await;
+    let etag = response.headers()["ETag"].to_str().unwrap().to_owned();
+
+    // Act
+    publish_issue(&app, "Second issue", false).await;
+    let response = app
+        .get_feed_with_header("/feed.rss", Some(("If-None-Match", &etag)))
+        .await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    assert_ne!(response.headers()["ETag"].to_str().unwrap(), etag);
+}
@@ -1,14 +1,23 @@
-use std::sync::LazyLock;
+use std::sync::{Arc, LazyLock};
 
 use argon2::password_hash::SaltString;
 use argon2::{Argon2, PasswordHasher};
+use fake::faker::internet::en::SafeEmail;
+use fake::faker::name::en::FirstName;
+use fake::Fake;
 use sqlx::postgres::PgPoolOptions;
 use sqlx::{Executor, PgPool};
 use uuid::Uuid;
-use wiremock::MockServer;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
 
-use zero2prod::configuration::{get_configuration, DatabaseSettings};
-use zero2prod::startup::Application;
+use zero2prod::configuration::{
+    get_configuration, DatabaseSettings, EmailProvider, SessionStoreKind, Settings,
+};
+use zero2prod::email_client::EmailSender;
+use zero2prod::issue_delivery_worker::{try_execute_task, ExecutionOutcome};
+use zero2prod::issue_scheduler::{try_release_issue, ReleaseOutcome};
+use zero2prod::startup::{Application, ApplicationBaseUrl, HmacSecret};
 use zero2prod::telemetry::{get_subscriber, init_subscriber};
 
 static TRACING: LazyLock<()> = LazyLock::new(|| {
@@ -19,6 +28,11 @@ static TRACING: LazyLock<()> = LazyLock::new(|| {
 /// Spin up an instance of our application
 /// and return its address (i.e. http://localhost:XXXX)
 pub async fn spawn_app() -> TestApp {
+    spawn_app_with(|_| {}).await
+}
+
+/// Like `spawn_app`, with a chance to tweak the configuration before the application starts.
+pub async fn spawn_app_with(customise: impl FnOnce(&mut Settings)) -> TestApp {
     *TRACING;
 
     let email_server = MockServer::start().await;
@@ -30,7 +44,17 @@ pub async fn spawn_app() -> TestApp {
         // Use a random OS port
         c.application.port = 0;
         // Use the mock server as email API
+        c.email_client.provider = EmailProvider::Postmark;
         c.email_client.base_url = email_server.uri();
+        // Tests dispatch pending emails explicitly, see `TestApp::dispatch_all_pending_emails`
+        c.application.delivery_workers = 0;
+        // Tests release scheduled issues explicitly, see `TestApp::release_due_issues`
+        c.application.issue_scheduler = false;
+        // Sessions don't need to outlive the test, nor to be shared across instances
+        c.session.store = SessionStoreKind::Memory;
+        // The test server speaks plain HTTP
+        c.session.cookie_secure = false;
+        customise(&mut c);
         c
     };
 
@@ -42,12 +66,22 @@ pub async fn spawn_app() -> TestApp {
     let application_port = application.port();
     tokio::spawn(application.run_until_stopped());
 
+    let api_client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .cookie_store(true)
+        .build()
+        .unwrap();
+
     let test_app = TestApp {
         address: format!("http://localhost:{}", application_port),
         port: application_port,
         db_pool,
         email_server,
         test_user: TestUser::generate(),
+        email_client: configuration.email_client.client(),
+        base_url: ApplicationBaseUrl(configuration.application.base_url),
+        hmac_secret: HmacSecret(configuration.application.hmac_secret),
+        api_client,
     };
     test_app.test_user.store(&test_app.db_pool).await;
     test_app
@@ -61,14 +95,54 @@ pub struct TestApp {
     pub port: u16,
     pub db_pool: PgPool,
     pub email_server: MockServer,
-    test_user: TestUser,
+    pub test_user: TestUser,
+    pub email_client: Arc<dyn EmailSender>,
+    pub base_url: ApplicationBaseUrl,
+    pub hmac_secret: HmacSecret,
+    /// Keeps cookies across requests and doesn't follow redirects,
+    /// so that tests can act as a logged-in user and inspect where they are sent to.
+    pub api_client: reqwest::Client,
 }
 
 impl TestApp {
+    /// Run the delivery worker logic until the queue is empty.
+    pub async fn dispatch_all_pending_emails(&self) {
+        loop {
+            if let ExecutionOutcome::EmptyQueue = try_execute_task(
+                &self.db_pool,
+                self.email_client.as_ref(),
+                &self.base_url,
+                &self.hmac_secret,
+            )
+            .await
+            .unwrap()
+            {
+                break;
+            }
+        }
+    }
+
+    /// Wait until the email server received at least `n` requests, for emails sent in the background.
+    pub async fn wait_for_emails(&self, n: usize) -> Vec<wiremock::Request> {
+        for _ in 0..50 {
+            let requests = self.email_server.received_requests().await.unwrap();
+            if requests.len() >= n {
+                return requests;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+        panic!("The email server did not receive {} requests in time.", n);
+    }
+
+    /// Run the scheduler logic until no scheduled issue is due.
+    pub async fn release_due_issues(&self) {
+        while let ReleaseOutcome::IssueReleased = try_release_issue(&self.db_pool).await.unwrap() {}
+    }
+
     pub async fn post_subscriptions(&self, body: &str) -> reqwest::Response {
         let client = reqwest::Client::new();
         client
-            .post(&format!("{}/subscriptions", &self.address))
+            .post(format!("{}/subscriptions", &self.address))
             .header("Content-Type", "application/x-www-form-urlencoded")
             .body(body.to_string())
             .send()
@@ -76,9 +150,68 @@ impl TestApp {
             .expect("Failed to execute request.")
     }
 
+    pub async fn post_resend_confirmation(&self, email: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!(
+                "{}/subscriptions/resend-confirmation",
+                &self.address
+            ))
+            .form(&serde_json::json!({ "email": email }))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
     pub async fn post_newsletters(&self, body: serde_json::Value) -> reqwest::Response {
+        self.newsletters_request(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_newsletters_with_idempotency_key(
+        &self,
+        body: serde_json::Value,
+        idempotency_key: &str,
+    ) -> reqwest::Response {
+        self.newsletters_request(body)
+            .header("Idempotency-Key", idempotency_key)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_newsletters_as(
+        &self,
+        username: &str,
+        password: &str,
+        body: serde_json::Value,
+    ) -> reqwest::Response {
+        self.newsletters_request_as(username, password, body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    fn newsletters_request(&self, body: serde_json::Value) -> reqwest::RequestBuilder {
+        self.newsletters_request_as(&self.test_user.username, &self.test_user.password, body)
+    }
+
+    fn newsletters_request_as(
+        &self,
+        username: &str,
+        password: &str,
+        body: serde_json::Value,
+    ) -> reqwest::RequestBuilder {
         reqwest::Client::new()
-            .post(&format!("{}/newsletters", &self.address))
+            .post(format!("{}/newsletters", &self.address))
+            .basic_auth(username, Some(password))
+            .json(&body)
+    }
+
+    pub async fn post_drafts(&self, body: serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/newsletters/drafts", &self.address))
             .basic_auth(&self.test_user.username, Some(&self.test_user.password))
             .json(&body)
             .send()
@@ -86,31 +219,454 @@ impl TestApp {
             .expect("Failed to execute request.")
     }
 
-    pub fn get_confirmation_links(&self, email_request: &wiremock::Request) -> ConfirmationLinks {
+    pub async fn put_draft(&self, issue_id: Uuid, body: serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .put(format!("{}/newsletters/drafts/{}", &self.address, issue_id))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_draft_preview(&self, issue_id: Uuid) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!(
+                "{}/newsletters/drafts/{}/preview",
+                &self.address, issue_id
+            ))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_test_send_draft(
+        &self,
+        issue_id: Uuid,
+        body: serde_json::Value,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!(
+                "{}/newsletters/drafts/{}/test-send",
+                &self.address, issue_id
+            ))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_publish_draft(
+        &self,
+        issue_id: Uuid,
+        body: serde_json::Value,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!(
+                "{}/newsletters/drafts/{}/publish",
+                &self.address, issue_id
+            ))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// `path` is relative to `/archive`, e.g. `?page=2` or `/some-issue-1b4e28ba`.
+    pub async fn get_archive(&self, path: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/archive{}", &self.address, path))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_archive_html(&self, path: &str) -> String {
+        self.get_archive(path).await.text().await.unwrap()
+    }
+
+    /// `path` is either `/feed.rss` or `/feed.atom`.
+    pub async fn get_feed(&self, path: &str) -> reqwest::Response {
+        self.get_feed_with_header(path, None).await
+    }
+
+    /// Send a conditional request, e.g. with an `If-None-Match` header.
+    pub async fn get_feed_with_header(
+        &self,
+        path: &str,
+        header: Option<(&str, &str)>,
+    ) -> reqwest::Response {
+        let mut request = reqwest::Client::new().get(format!("{}{}", &self.address, path));
+        if let Some((name, value)) = header {
+            request = request.header(name, value);
+        }
+        request.send().await.expect("Failed to execute request.")
+    }
+
+    pub async fn get_scheduled_issues(&self) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/newsletters/scheduled", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_reschedule_issue(
+        &self,
+        issue_id: Uuid,
+        body: serde_json::Value,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!(
+                "{}/newsletters/scheduled/{}/reschedule",
+                &self.address, issue_id
+            ))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_cancel_issue(&self, issue_id: Uuid) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!(
+                "{}/newsletters/scheduled/{}/cancel",
+                &self.address, issue_id
+            ))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_login<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/login", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_login_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/login", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn get_admin_dashboard(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/dashboard", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_dashboard_html(&self) -> String {
+        self.get_admin_dashboard().await.text().await.unwrap()
+    }
+
+    pub async fn post_logout(&self) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/logout", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_change_password(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/password", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_change_password_html(&self) -> String {
+        self.get_change_password().await.text().await.unwrap()
+    }
+
+    pub async fn post_change_password<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/password", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_lockouts_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/lockouts", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_clear_lockout(&self, lockout_id: Uuid) -> reqwest::Response {
+        self.api_client
+            .post(format!(
+                "{}/admin/lockouts/{}/clear",
+                &self.address, lockout_id
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_lists_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/lists", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_lists<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/lists", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_tags_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/tags", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    /// `action` is either `add` or `remove`.
+    pub async fn post_tags<Body>(&self, action: &str, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/tags/{}", &self.address, action))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_issues_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/issues", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn get_issue_report(&self, issue_id: Uuid) -> reqwest::Response {
+        self.api_client
+            .get(format!(
+                "{}/admin/issues/{}/report",
+                &self.address, issue_id
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_issue_failures_csv(&self, issue_id: Uuid) -> reqwest::Response {
+        self.api_client
+            .get(format!(
+                "{}/admin/issues/{}/report/failures.csv",
+                &self.address, issue_id
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// Log in as the test user.
+    pub async fn log_in(&self) {
+        let response = self
+            .post_login(&serde_json::json!({
+                "username": &self.test_user.username,
+                "password": &self.test_user.password
+            }))
+            .await;
+        assert_is_redirect_to(&response, "/admin/dashboard");
+    }
+
+    /// Submit an unsubscribe request the same way an RFC 8058 one-click client does.
+    pub async fn post_unsubscribe<Query>(&self, query: &Query) -> reqwest::Response
+    where
+        Query: serde::Serialize,
+    {
+        reqwest::Client::new()
+            .post(format!("{}/subscriptions/unsubscribe", &self.address))
+            .query(query)
+            .form(&[("List-Unsubscribe", "One-Click")])
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub fn get_confirmation_links(&self, email_request: &wiremock::Request) -> EmailLinks {
+        self.get_email_links(email_request, "/subscriptions/confirm")
+    }
+
+    pub fn get_unsubscribe_links(&self, email_request: &wiremock::Request) -> EmailLinks {
+        self.get_email_links(email_request, "/subscriptions/unsubscribe")
+    }
+
+    pub fn get_preferences_links(&self, email_request: &wiremock::Request) -> EmailLinks {
+        self.get_email_links(email_request, "/subscriptions/preferences")
+    }
+
+    pub fn get_email_change_links(&self, email_request: &wiremock::Request) -> EmailLinks {
+        self.get_email_links(email_request, "/subscriptions/preferences/confirm-email")
+    }
+
+    /// Extract the single link to `path` we expect in both the HTML and the plain text body of an email.
+    fn get_email_links(&self, email_request: &wiremock::Request, path: &str) -> EmailLinks {
         let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
 
-        let get_link = |s: &str| {
-            let links: Vec<_> = linkify::LinkFinder::new()
-                .kinds(&[linkify::LinkKind::Url])
-                .links(s)
-                .collect();
-            assert_eq!(1, links.len());
-            let raw_link = links[0].as_str().to_owned();
-            let mut confirmation_link = reqwest::Url::parse(&raw_link).unwrap();
-
-            // Let's make sure we don't call random APIs on the web
-            assert_eq!("localhost", confirmation_link.host_str().unwrap());
-            // In the test environment, without the line below,
-            // a request can be made without a port being specified.
-            // This is a non-issue for production workloads where the DNS domain is enough.
-            confirmation_link.set_port(Some(self.port)).unwrap();
-            confirmation_link
-        };
-
-        let html = get_link(&body["HtmlBody"].as_str().unwrap());
-        let plain_text = get_link(&body["TextBody"].as_str().unwrap());
-        ConfirmationLinks { html, plain_text }
+        let html = self.get_link(body["HtmlBody"].as_str().unwrap(), path);
+        let plain_text = self.get_link(body["TextBody"].as_str().unwrap(), path);
+        EmailLinks { html, plain_text }
     }
+
+    fn get_link(&self, s: &str, path: &str) -> reqwest::Url {
+        let links: Vec<_> = linkify::LinkFinder::new()
+            .kinds(&[linkify::LinkKind::Url])
+            .links(s)
+            .map(|link| reqwest::Url::parse(link.as_str()).unwrap())
+            .filter(|link| link.path() == path)
+            .collect();
+        assert_eq!(1, links.len());
+        let mut link = links[0].clone();
+
+        // Let's make sure we don't call random APIs on the web
+        assert_eq!("localhost", link.host_str().unwrap());
+        // In the test environment, without the line below,
+        // a request can be made without a port being specified.
+        // This is a non-issue for production workloads where the DNS domain is enough.
+        link.set_port(Some(self.port)).unwrap();
+        link
+    }
+}
+
+pub fn assert_is_redirect_to(response: &reqwest::Response, location: &str) {
+    assert_eq!(303, response.status().as_u16());
+    assert_eq!(location, response.headers().get("Location").unwrap());
+}
+
+/// What Postmark answers when it accepts an email.
+pub fn email_sent_response() -> ResponseTemplate {
+    ResponseTemplate::new(200).set_body_json(serde_json::json!({
+        "To": "recipient@example.com",
+        "SubmittedAt": "2024-09-15T09:00:00.0000000Z",
+        "MessageID": Uuid::new_v4().to_string(),
+        "ErrorCode": 0,
+        "Message": "OK"
+    }))
+}
+
+/// Use the public API of the application under test
+/// to create an unconfirmed subscriber.
+pub async fn create_unconfirmed_subscriber(app: &TestApp) -> EmailLinks {
+    // We are working with multiple subscribers now,
+    // so their details must be randomised to avoid conflicts.
+    let name: String = FirstName().fake();
+    let email: String = SafeEmail().fake();
+    let body = serde_urlencoded::to_string(serde_json::json!({
+        "name": name,
+        "email": email
+    }))
+    .unwrap();
+
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(email_sent_response())
+        .named("Create unconfirmed subscriber")
+        .expect(1)
+        // We're not using `mount`
+        // This is because to avoid conflict with the logic in the parent responding to `any()`
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    let n_emails = app.email_server.received_requests().await.unwrap().len();
+    app.post_subscriptions(&body)
+        .await
+        .error_for_status()
+        .expect("Failed to create subscriber.");
+
+    let email_request = &app.wait_for_emails(n_emails + 1).await.pop().unwrap();
+
+    app.get_confirmation_links(email_request)
+}
+
+pub async fn create_confirmed_subscriber(app: &TestApp) {
+    let confirmation_link = create_unconfirmed_subscriber(app).await;
+    reqwest::get(confirmation_link.html)
+        .await
+        .expect("Failed to confirm subscription.")
+        .error_for_status()
+        .expect("Failed to confirm subscription.");
+}
+
+/// Like `create_confirmed_subscriber`, with the given details.
+pub async fn create_confirmed_subscriber_with(app: &TestApp, name: &str, email: &str) {
+    let body = serde_urlencoded::to_string(serde_json::json!({
+        "name": name,
+        "email": email
+    }))
+    .unwrap();
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(email_sent_response())
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    let n_emails = app.email_server.received_requests().await.unwrap().len();
+    app.post_subscriptions(&body)
+        .await
+        .error_for_status()
+        .expect("Failed to create subscriber.");
+
+    let email_request = app.wait_for_emails(n_emails + 1).await.pop().unwrap();
+    reqwest::get(app.get_confirmation_links(&email_request).html)
+        .await
+        .expect("Failed to confirm subscription.")
+        .error_for_status()
+        .expect("Failed to confirm subscription.");
 }
 
 async fn configure_database(config: &DatabaseSettings) -> PgPool {
@@ -133,7 +689,7 @@ async fn configure_database(config: &DatabaseSettings) -> PgPool {
     connection_pool
 }
 
-pub struct ConfirmationLinks {
+pub struct EmailLinks {
     pub html: reqwest::Url,
     pub plain_text: reqwest::Url,
 }
@@ -0,0 +1,287 @@
+use uuid::Uuid;
+use wiremock::matchers::{body_partial_json, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{
+    assert_is_redirect_to, create_confirmed_subscriber_with, email_sent_response, spawn_app,
+    TestApp,
+};
+
+/// Respond to emails sent to `email` with `response`.
+async fn mock_delivery_to(app: &TestApp, email: &str, response: ResponseTemplate) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .and(body_partial_json(serde_json::json!({ "To": email })))
+        .respond_with(response)
+        .mount(&app.email_server)
+        .await;
+}
+
+fn email_refused(error_code: u16, message: &str) -> ResponseTemplate {
+    ResponseTemplate::new(422).set_body_json(serde_json::json!({
+        "ErrorCode": error_code,
+        "Message": message
+    }))
+}
+
+/// Publish an issue to four subscribers, one for each delivery status, and return its id.
+async fn publish_issue_with_mixed_outcomes(app: &TestApp) -> Uuid {
+    for (name, email) in [
+        ("Alice", "alice@example.com"),
+        ("Bob", "bob@example.com"),
+        ("Carol", "carol@example.com"),
+        ("Dave", "dave@example.com"),
+    ] {
+        create_confirmed_subscriber_with(app, name, email).await;
+    }
+    mock_delivery_to(app, "alice@example.com", email_sent_response()).await;
+    mock_delivery_to(
+        app,
+        "bob@example.com",
+        email_refused(406, "The recipient is marked as inactive."),
+    )
+    .await;
+    mock_delivery_to(
+        app,
+        "carol@example.com",
+        email_refused(300, "Invalid 'To' address."),
+    )
+    .await;
+    mock_delivery_to(app, "dave@example.com", ResponseTemplate::new(500)).await;
+
+    app.post_newsletters(serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    }))
+    .await
+    .error_for_status()
+    .expect("Failed to publish the newsletter.");
+    // Dave's delivery is retried later, past the end of this call.
+    app.dispatch_all_pending_emails().await;
+
+    sqlx::query_scalar!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn every_recipient_has_their_delivery_logged() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let issue_id = publish_issue_with_mixed_outcomes(&app).await;
+
+    // Assert
+    let deliveries = sqlx::query!(
+        r#"
+        SELECT s.email, d.status, d.n_attempts, d.last_error, d.message_id
+        FROM deliveries d
+        JOIN subscriptions s ON s.id = d.subscriber_id
+        WHERE d.newsletter_issue_id = $1
+        ORDER BY s.email
+        "#,
+        issue_id
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    let statuses: Vec<_> = deliveries
+        .iter()
+        .map(|d| (d.email.as_str(), d.status.as_str(), d.n_attempts))
+        .collect();
+    assert_eq!(
+        statuses,
+        [
+            ("alice@example.com", "sent", 1),
+            ("bob@example.com", "bounced", 1),
+            ("carol@example.com", "failed", 1),
+            ("dave@example.com", "queued", 1),
+        ]
+    );
+    assert!(deliveries[0].message_id.is_some());
+    assert!(deliveries[0].last_error.is_none());
+    for delivery in &deliveries[1..] {
+        assert!(delivery.message_id.is_none());
+        assert!(delivery.last_error.is_some());
+    }
+}
+
+#[tokio::test]
+async fn recipients_who_left_before_their_turn_are_kept_as_skipped() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber_with(&app, "Alice", "alice@example.com").await;
+    create_confirmed_subscriber_with(&app, "Bob", "bob@example.com").await;
+    mock_delivery_to(&app, "alice@example.com", email_sent_response()).await;
+    app.post_newsletters(serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    }))
+    .await
+    .error_for_status()
+    .expect("Failed to publish the newsletter.");
+    sqlx::query!(
+        "UPDATE subscriptions SET status = 'unsubscribed' WHERE email = 'bob@example.com'"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let deliveries = sqlx::query!(
+        r#"
+        SELECT s.email, d.status, d.n_attempts
+        FROM deliveries d
+        JOIN subscriptions s ON s.id = d.subscriber_id
+        ORDER BY s.email
+        "#
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    let statuses: Vec<_> = deliveries
+        .iter()
+        .map(|d| (d.email.as_str(), d.status.as_str(), d.n_attempts))
+        .collect();
+    assert_eq!(
+        statuses,
+        [
+            ("alice@example.com", "sent", 1),
+            ("bob@example.com", "skipped", 0),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn the_report_shows_the_totals_of_each_status() {
+    // Arrange
+    let app = spawn_app().await;
+    let issue_id = publish_issue_with_mixed_outcomes(&app).await;
+    app.log_in().await;
+
+    // Act - Part 1 - The issues page links to the report
+    let html = app.get_issues_html().await;
+    assert!(html.contains(&format!(r#"<a href="/admin/issues/{}/report">"#, issue_id)));
+
+    // Act - Part 2 - Open the report
+    let response = app.get_issue_report(issue_id).await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let html = response.text().await.unwrap();
+    assert!(html.contains("<h1>Newsletter title</h1>"));
+    for (status, total) in [
+        ("Queued", 1),
+        ("Sent", 1),
+        ("Failed", 1),
+        ("Bounced", 1),
+        ("Skipped", 0),
+        ("Total", 4),
+    ] {
+        assert!(html.contains(&format!("<tr><th>{}</th><td>{}</td></tr>", status, total)));
+    }
+}
+
+#[tokio::test]
+async fn failed_deliveries_can_be_downloaded_as_csv() {
+    // Arrange
+    let app = spawn_app().await;
+    let issue_id = publish_issue_with_mixed_outcomes(&app).await;
+    app.log_in().await;
+
+    // Act
+    let response = app.get_issue_failures_csv(issue_id).await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "text/csv; charset=utf-8"
+    );
+    assert!(response.headers()["Content-Disposition"]
+        .to_str()
+        .unwrap()
+        .starts_with("attachment;"));
+    let body = response.bytes().await.unwrap();
+    let mut reader = csv::Reader::from_reader(&body[..]);
+    assert_eq!(
+        reader.headers().unwrap(),
+        vec![
+            "email",
+            "status",
+            "attempts",
+            "last_error",
+            "message_id",
+            "updated_at"
+        ]
+    );
+    let mut records: Vec<(String, String, String)> = reader
+        .records()
+        .map(|record| {
+            let record = record.unwrap();
+            (
+                record[0].to_owned(),
+                record[1].to_owned(),
+                record[3].to_owned(),
+            )
+        })
+        .collect();
+    records.sort();
+    assert_eq!(
+        records,
+        [
+            (
+                "bob@example.com".to_owned(),
+                "bounced".to_owned(),
+                "The recipient is inactive: The recipient is marked as inactive.".to_owned()
+            ),
+            (
+                "carol@example.com".to_owned(),
+                "failed".to_owned(),
+                "The recipient's address is invalid: Invalid 'To' address.".to_owned()
+            ),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_delivery_reports() {
+    // Arrange
+    let app = spawn_app().await;
+    let issue_id = Uuid::new_v4();
+
+    // Act
+    let report = app.get_issue_report(issue_id).await;
+    let csv = app.get_issue_failures_csv(issue_id).await;
+
+    // Assert
+    assert_is_redirect_to(&report, "/login");
+    assert_is_redirect_to(&csv, "/login");
+}
+
+#[tokio::test]
+async fn reports_of_unknown_issues_are_not_found() {
+    // Arrange
+    let app = spawn_app().await;
+    app.log_in().await;
+    let issue_id = Uuid::new_v4();
+
+    // Act
+    let report = app.get_issue_report(issue_id).await;
+    let csv = app.get_issue_failures_csv(issue_id).await;
+
+    // Assert
+    assert_eq!(404, report.status().as_u16());
+    assert_eq!(404, csv.status().as_u16());
+}
@@ -0,0 +1,214 @@
+use uuid::Uuid;
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{
+    assert_is_redirect_to, create_confirmed_subscriber, email_sent_response, spawn_app, TestApp,
+};
+
+async fn create_list(app: &TestApp, slug: &str) -> Uuid {
+    let response = app
+        .post_lists(&serde_json::json!({ "name": "Weekly digest", "slug": slug }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/lists");
+    sqlx::query_scalar!("SELECT list_id FROM lists WHERE slug = $1", slug)
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch the created list.")
+}
+
+async fn subscribe_to(app: &TestApp, email: &str, slug: &str) {
+    let body = serde_urlencoded::to_string(serde_json::json!({
+        "name": "le guin",
+        "email": email,
+        "list": slug
+    }))
+    .unwrap();
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(email_sent_response())
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    let n_emails = app.email_server.received_requests().await.unwrap().len();
+    let response = app.post_subscriptions(&body).await;
+    assert_eq!(200, response.status().as_u16());
+
+    let email_request = app.wait_for_emails(n_emails + 1).await.pop().unwrap();
+    let confirmation_links = app.get_confirmation_links(&email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+#[tokio::test]
+async fn an_admin_can_create_a_list() {
+    // Arrange
+    let app = spawn_app().await;
+    app.log_in().await;
+
+    // Act - Part 1 - Create the list
+    let response = app
+        .post_lists(&serde_json::json!({ "name": "Weekly digest", "slug": "weekly-digest" }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/lists");
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = app.get_lists_html().await;
+
+    // Assert
+    assert!(html_page.contains("<p><i>The list has been created.</i></p>"));
+    assert!(html_page.contains("weekly-digest"));
+    assert!(html_page.contains("newsletter"));
+}
+
+#[tokio::test]
+async fn list_slugs_must_be_valid_and_unique() {
+    // Arrange
+    let app = spawn_app().await;
+    app.log_in().await;
+    create_list(&app, "weekly-digest").await;
+
+    // Act - Part 1 - Reuse the slug
+    let response = app
+        .post_lists(&serde_json::json!({ "name": "Another", "slug": "weekly-digest" }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/lists");
+    let html_page = app.get_lists_html().await;
+    assert!(html_page.contains("<p><i>There is a list with the same slug already.</i></p>"));
+
+    // Act - Part 2 - Use an invalid slug
+    let response = app
+        .post_lists(&serde_json::json!({ "name": "Another", "slug": "Weekly Digest" }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/lists");
+    let html_page = app.get_lists_html().await;
+    assert!(html_page.contains("The slug can only contain"));
+
+    // Assert
+    let n_lists = sqlx::query_scalar!(r#"SELECT count(*) as "count!" FROM lists"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(n_lists, 2);
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_create_a_list() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_lists(&serde_json::json!({ "name": "Weekly digest", "slug": "weekly-digest" }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn subscribing_to_a_list_confirms_only_that_membership() {
+    // Arrange
+    let app = spawn_app().await;
+    app.log_in().await;
+    let list_id = create_list(&app, "weekly-digest").await;
+
+    // Act
+    subscribe_to(&app, "ursula_le_guin@gmail.com", "weekly-digest").await;
+
+    // Assert
+    let memberships = sqlx::query!("SELECT list_id, status FROM list_memberships")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(memberships.len(), 1);
+    assert_eq!(memberships[0].list_id, list_id);
+    assert_eq!(memberships[0].status, "confirmed");
+}
+
+#[tokio::test]
+async fn subscribing_to_an_unknown_list_is_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com&list=no-such-list")
+        .await;
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn newsletters_are_delivered_to_the_members_of_the_list_only() {
+    // Arrange
+    let app = spawn_app().await;
+    app.log_in().await;
+    let list_id = create_list(&app, "weekly-digest").await;
+    // Subscribed to the default list only.
+    create_confirmed_subscriber(&app).await;
+    subscribe_to(&app, "ursula_le_guin@gmail.com", "weekly-digest").await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(email_sent_response())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            },
+            "list_id": list_id,
+        }))
+        .await;
+    assert_eq!(200, response.status().as_u16());
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["To"], "ursula_le_guin@gmail.com");
+}
+
+#[tokio::test]
+async fn publishing_to_an_unknown_list_is_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            },
+            "list_id": Uuid::new_v4(),
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+}
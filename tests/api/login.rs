@@ -0,0 +1,130 @@
+use argon2::password_hash::SaltString;
+use argon2::{Algorithm, Argon2, Params, PasswordHash, PasswordHasher, Version};
+
+use zero2prod::configuration::get_configuration;
+
+use crate::helpers::{assert_is_redirect_to, spawn_app};
+
+#[tokio::test]
+async fn an_error_flash_message_is_set_on_failure() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act - Part 1 - Try to login
+    let login_body = serde_json::json!({
+        "username": "random-username",
+        "password": "random-password"
+    });
+    let response = app.post_login(&login_body).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("<p><i>Authentication failed</i></p>"));
+
+    // Act - Part 3 - Reload the login page
+    let html_page = app.get_login_html().await;
+    assert!(!html_page.contains("Authentication failed"));
+}
+
+#[tokio::test]
+async fn redirect_to_admin_dashboard_after_login_success() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act - Part 1 - Login
+    let login_body = serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    });
+    let response = app.post_login(&login_body).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
+}
+
+#[tokio::test]
+async fn the_session_cookie_is_http_only_and_same_site() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password
+        }))
+        .await;
+
+    // Assert
+    let session_cookie = response
+        .cookies()
+        .find(|c| c.name() == "id")
+        .expect("No session cookie was set.");
+    assert!(session_cookie.http_only());
+    assert!(session_cookie.same_site_strict());
+}
+
+#[tokio::test]
+async fn the_login_form_escapes_the_flash_message() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let html_page = app
+        .api_client
+        .get(format!("{}/login", &app.address))
+        .header("Cookie", "_flash=<script>alert(1)</script>")
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .text()
+        .await
+        .unwrap();
+
+    // Assert
+    assert!(!html_page.contains("<script>"));
+    assert!(html_page.contains("&lt;script&gt;"));
+}
+
+#[tokio::test]
+async fn an_outdated_password_hash_is_upgraded_on_login() {
+    // Arrange
+    let app = spawn_app().await;
+    let outdated_params = Params::new(4096, 1, 1, None).unwrap();
+    let salt = SaltString::generate(&mut rand::thread_rng());
+    let outdated_hash = Argon2::new(Algorithm::Argon2id, Version::V0x13, outdated_params)
+        .hash_password(app.test_user.password.as_bytes(), &salt)
+        .unwrap()
+        .to_string();
+    sqlx::query!(
+        "UPDATE users SET password_hash = $1 WHERE user_id = $2",
+        outdated_hash,
+        app.test_user.user_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    app.log_in().await;
+
+    // Assert
+    let stored_hash = sqlx::query!(
+        "SELECT password_hash FROM users WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .password_hash;
+    let stored_params = Params::try_from(&PasswordHash::new(&stored_hash).unwrap()).unwrap();
+    let configured_params = get_configuration().unwrap().password_hashing.params();
+    assert_eq!(configured_params.m_cost(), stored_params.m_cost());
+    assert_eq!(configured_params.t_cost(), stored_params.t_cost());
+    assert_eq!(configured_params.p_cost(), stored_params.p_cost());
+}
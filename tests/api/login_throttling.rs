@@ -0,0 +1,178 @@
+use uuid::Uuid;
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, spawn_app_with, TestApp};
+
+fn newsletter_request_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter content",
+            "html": "<h1>Newsletter content</h1>"
+        }
+    })
+}
+
+async fn fail_to_publish_as(app: &TestApp, username: &str, n_attempts: usize) {
+    for _ in 0..n_attempts {
+        let response = app
+            .post_newsletters_as(
+                username,
+                &Uuid::new_v4().to_string(),
+                newsletter_request_body(),
+            )
+            .await;
+        assert_eq!(401, response.status().as_u16());
+    }
+}
+
+#[tokio::test]
+async fn too_many_wrong_passwords_lock_the_username_out_of_publishing() {
+    // Arrange
+    let app = spawn_app().await;
+    let username = app.test_user.username.clone();
+    fail_to_publish_as(&app, &username, 5).await;
+
+    // Act - Even the right password is refused now
+    let response = app.post_newsletters(newsletter_request_body()).await;
+
+    // Assert
+    assert_eq!(429, response.status().as_u16());
+    let retry_after: u64 = response
+        .headers()
+        .get("Retry-After")
+        .expect("Missing Retry-After header.")
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(retry_after > 0 && retry_after <= 900);
+}
+
+#[tokio::test]
+async fn a_lockout_leaves_an_audit_record() {
+    // Arrange
+    let app = spawn_app().await;
+    let username = app.test_user.username.clone();
+
+    // Act
+    fail_to_publish_as(&app, &username, 5).await;
+
+    // Assert
+    let lockout =
+        sqlx::query!("SELECT subject_kind, subject, n_failures, cleared_at FROM login_lockouts")
+            .fetch_one(&app.db_pool)
+            .await
+            .expect("Failed to fetch the lockout.");
+    assert_eq!(lockout.subject_kind, "username");
+    assert_eq!(lockout.subject, username);
+    assert_eq!(lockout.n_failures, 5);
+    assert!(lockout.cleared_at.is_none());
+}
+
+#[tokio::test]
+async fn too_many_failures_from_one_ip_lock_every_username_out() {
+    // Arrange
+    let app = spawn_app_with(|c| c.login_throttling.max_failures_per_ip = 3).await;
+    for _ in 0..3 {
+        fail_to_publish_as(&app, &Uuid::new_v4().to_string(), 1).await;
+    }
+
+    // Act
+    let response = app.post_newsletters(newsletter_request_body()).await;
+
+    // Assert
+    assert_eq!(429, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn a_successful_login_resets_the_failure_count() {
+    // Arrange
+    let app = spawn_app().await;
+    let username = app.test_user.username.clone();
+    fail_to_publish_as(&app, &username, 4).await;
+    let response = app.post_newsletters(newsletter_request_body()).await;
+    assert_eq!(200, response.status().as_u16());
+
+    // Act
+    fail_to_publish_as(&app, &username, 4).await;
+    let response = app.post_newsletters(newsletter_request_body()).await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn the_login_form_is_locked_out_too() {
+    // Arrange
+    let app = spawn_app().await;
+    for _ in 0..5 {
+        let response = app
+            .post_login(&serde_json::json!({
+                "username": &app.test_user.username,
+                "password": Uuid::new_v4().to_string()
+            }))
+            .await;
+        assert_is_redirect_to(&response, "/login");
+    }
+
+    // Act
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(429, response.status().as_u16());
+    assert!(response.headers().contains_key("Retry-After"));
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("Too many failed login attempts"));
+}
+
+#[tokio::test]
+async fn an_admin_can_clear_a_lockout() {
+    // Arrange
+    let app = spawn_app().await;
+    let username = Uuid::new_v4().to_string();
+    fail_to_publish_as(&app, &username, 5).await;
+    let lockout_id = sqlx::query_scalar!("SELECT lockout_id FROM login_lockouts")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    app.log_in().await;
+    assert!(app.get_lockouts_html().await.contains(&username));
+
+    // Act - Part 1 - Clear the lockout
+    let response = app.post_clear_lockout(lockout_id).await;
+    assert_is_redirect_to(&response, "/admin/lockouts");
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = app.get_lockouts_html().await;
+    assert!(html_page.contains("<p><i>The lockout has been cleared.</i></p>"));
+    assert!(!html_page.contains(&username));
+
+    // Assert
+    let cleared_by = sqlx::query_scalar!("SELECT cleared_by FROM login_lockouts")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(cleared_by, Some(app.test_user.user_id));
+    // Wrong credentials are merely rejected again.
+    fail_to_publish_as(&app, &username, 1).await;
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_clear_a_lockout() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.post_clear_lockout(Uuid::new_v4()).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
@@ -1,5 +1,20 @@
+mod admin_dashboard;
+mod archive;
+mod change_password;
+mod feeds;
 mod health_check;
 mod helpers;
+mod issue_reports;
+mod lists;
+mod login;
+mod login_throttling;
 mod newsletter;
+mod newsletter_drafts;
+mod newsletter_scheduling;
+mod session_store;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_preferences;
+mod subscriptions_resend_confirmation;
+mod subscriptions_unsubscribe;
+mod tags;
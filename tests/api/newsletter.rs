@@ -1,7 +1,13 @@
+use std::time::{Duration, Instant};
+
+use uuid::Uuid;
 use wiremock::matchers::{any, method, path};
 use wiremock::{Mock, ResponseTemplate};
 
-use crate::helpers::{spawn_app, ConfirmationLinks, TestApp};
+use crate::helpers::{
+    create_confirmed_subscriber, create_confirmed_subscriber_with, create_unconfirmed_subscriber,
+    email_sent_response, spawn_app, spawn_app_with, TestApp,
+};
 
 #[tokio::test]
 async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
@@ -10,7 +16,7 @@ async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
     create_unconfirmed_subscriber(&app).await;
 
     Mock::given(any())
-        .respond_with(ResponseTemplate::new(200))
+        .respond_with(email_sent_response())
         .expect(0)
         .mount(&app.email_server)
         .await;
@@ -31,6 +37,7 @@ async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
 
     // Assert
     assert_eq!(200, response.status().as_u16());
+    app.dispatch_all_pending_emails().await;
     // Mock verifies on Drop that we haven't sent the newsletter email
 }
 
@@ -42,7 +49,7 @@ async fn newsletters_are_delivered_to_confirmed_subscribers() {
 
     Mock::given(path("/email"))
         .and(method("POST"))
-        .respond_with(ResponseTemplate::new(200))
+        .respond_with(email_sent_response())
         .expect(1)
         .mount(&app.email_server)
         .await;
@@ -60,6 +67,7 @@ async fn newsletters_are_delivered_to_confirmed_subscribers() {
 
     // Assert
     assert_eq!(200, response.status().as_u16());
+    app.dispatch_all_pending_emails().await;
     // Mock verifies on Drop that we've sent the newsletter email
 }
 
@@ -98,7 +106,7 @@ async fn requests_missing_authorization_are_rejected() {
     let app = spawn_app().await;
 
     let response = reqwest::Client::new()
-        .post(&format!("{}/newsletters", &app.address))
+        .post(format!("{}/newsletters", &app.address))
         .json(&serde_json::json!({
             "title": "Newsletter title",
             "content": {
@@ -121,42 +129,596 @@ async fn requests_missing_authorization_are_rejected() {
     );
 }
 
-/// Use the public API of the application under test
-/// to create an unconfirmed subscriber.
-async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
-    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+/// Time a `POST /newsletters` request authenticated with the given credentials.
+async fn time_publishing_as(app: &TestApp, username: &str, password: &str) -> Duration {
+    let start = Instant::now();
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters", &app.address))
+        .basic_auth(username, Some(password))
+        .json(&serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter content",
+                "html": "<h1>Newsletter content</h1>"
+            }
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    let elapsed = start.elapsed();
+    assert_eq!(401, response.status().as_u16());
+    elapsed
+}
+
+fn median(mut samples: Vec<Duration>) -> Duration {
+    samples.sort();
+    samples[samples.len() / 2]
+}
+
+#[tokio::test]
+async fn unknown_usernames_take_as_long_to_reject_as_wrong_passwords() {
+    // Arrange
+    // Being locked out would short-circuit the password verification we are timing.
+    let app = spawn_app_with(|c| {
+        c.login_throttling.max_failures_per_username = i64::MAX;
+        c.login_throttling.max_failures_per_ip = i64::MAX;
+    })
+    .await;
+    let mut unknown_username = Vec::new();
+    let mut wrong_password = Vec::new();
+
+    // Act
+    // Interleave the two kinds of requests,
+    // so that noise from the rest of the machine affects both equally.
+    for _ in 0..7 {
+        let password = Uuid::new_v4().to_string();
+        unknown_username
+            .push(time_publishing_as(&app, &Uuid::new_v4().to_string(), &password).await);
+        wrong_password.push(time_publishing_as(&app, &app.test_user.username, &password).await);
+    }
+
+    // Assert
+    // Without a dummy hash to verify against, unknown usernames are rejected
+    // orders of magnitude faster than a full Argon2 verification.
+    let unknown_username = median(unknown_username).as_secs_f64();
+    let wrong_password = median(wrong_password).as_secs_f64();
+    let ratio = unknown_username / wrong_password;
+    assert!(
+        (0.5..2.0).contains(&ratio),
+        "Unknown username: {:.1}ms, wrong password: {:.1}ms",
+        unknown_username * 1000.,
+        wrong_password * 1000.
+    );
+}
+
+#[tokio::test]
+async fn publishing_queues_one_delivery_per_confirmed_subscriber_without_sending() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    create_confirmed_subscriber(&app).await;
+    create_unconfirmed_subscriber(&app).await;
+
+    Mock::given(any())
+        .respond_with(email_sent_response())
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter content",
+                "html": "<h1>Newsletter content</h1>"
+            }
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let issue = sqlx::query!("SELECT title FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch the newsletter issue.");
+    assert_eq!("Newsletter title", issue.title);
+    let n_tasks = sqlx::query_scalar!(r#"SELECT count(*) as "count!" FROM issue_delivery_queue"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to count delivery tasks.");
+    assert_eq!(2, n_tasks);
+    // Mock verifies on Drop that nothing was sent while handling the request
+}
+
+#[tokio::test]
+async fn a_failed_delivery_does_not_stop_the_others() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    create_confirmed_subscriber(&app).await;
+
+    // A client error is not retried, so each subscriber gets exactly one attempt
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(422))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_newsletters(serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter content",
+            "html": "<h1>Newsletter content</h1>"
+        }
+    }))
+    .await
+    .error_for_status()
+    .expect("Failed to publish the newsletter.");
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    // Mock verifies on Drop that both subscribers were attempted
+    let n_tasks = sqlx::query_scalar!(r#"SELECT count(*) as "count!" FROM issue_delivery_queue"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to count delivery tasks.");
+    assert_eq!(0, n_tasks);
+}
+
+#[tokio::test]
+async fn transient_delivery_failures_are_retried_later() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(503))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_newsletters(serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter content",
+            "html": "<h1>Newsletter content</h1>"
+        }
+    }))
+    .await
+    .error_for_status()
+    .expect("Failed to publish the newsletter.");
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let task = sqlx::query!(
+        r#"SELECT n_retries, execute_after > now() as "in_the_future!" FROM issue_delivery_queue"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("The delivery task should still be queued.");
+    assert_eq!(1, task.n_retries);
+    assert!(task.in_the_future);
+}
+
+#[tokio::test]
+async fn deliveries_to_inactive_recipients_are_not_retried() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(422).set_body_json(serde_json::json!({
+            "ErrorCode": 406,
+            "Message": "You tried to send to a recipient that has been marked as inactive."
+        })))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_newsletters(serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter content",
+            "html": "<h1>Newsletter content</h1>"
+        }
+    }))
+    .await
+    .error_for_status()
+    .expect("Failed to publish the newsletter.");
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let n_tasks = sqlx::query_scalar!(r#"SELECT count(*) as "count!" FROM issue_delivery_queue"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to count delivery tasks.");
+    assert_eq!(0, n_tasks);
+}
+
+#[tokio::test]
+async fn subscribers_who_leave_before_delivery_are_skipped() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(any())
+        .respond_with(email_sent_response())
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_newsletters(serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter content",
+            "html": "<h1>Newsletter content</h1>"
+        }
+    }))
+    .await
+    .error_for_status()
+    .expect("Failed to publish the newsletter.");
+    sqlx::query!("UPDATE subscriptions SET status = 'unsubscribed'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    // Mock verifies on Drop that we haven't sent the newsletter email
+}
+
+#[tokio::test]
+async fn newsletter_creation_is_idempotent() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(email_sent_response())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter content",
+            "html": "<h1>Newsletter content</h1>"
+        }
+    });
+    let idempotency_key = uuid::Uuid::new_v4().to_string();
+
+    // Act - Part 1 - Submit newsletter
+    let response = app
+        .post_newsletters_with_idempotency_key(newsletter_request_body.clone(), &idempotency_key)
+        .await;
+    assert_eq!(200, response.status().as_u16());
+
+    // Act - Part 2 - Submit newsletter **again**
+    let response = app
+        .post_newsletters_with_idempotency_key(newsletter_request_body, &idempotency_key)
+        .await;
+    assert_eq!(200, response.status().as_u16());
+
+    // Assert
+    app.dispatch_all_pending_emails().await;
+    // Mock verifies on Drop that we have sent the newsletter email **once**
+}
+
+#[tokio::test]
+async fn concurrent_newsletter_creation_is_handled_gracefully() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
 
-    let _mock_guard = Mock::given(path("/email"))
+    Mock::given(path("/email"))
         .and(method("POST"))
-        .respond_with(ResponseTemplate::new(200))
-        .named("Create unconfirmed subscriber")
+        .respond_with(email_sent_response())
         .expect(1)
-        // We're not using `mount`
-        // This is because to avoid conflict with the logic in the parent responding to `any()`
-        .mount_as_scoped(&app.email_server)
+        .mount(&app.email_server)
         .await;
 
-    app.post_subscriptions(body)
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter content",
+            "html": "<h1>Newsletter content</h1>"
+        }
+    });
+    let idempotency_key = uuid::Uuid::new_v4().to_string();
+
+    // Act - Submit two newsletter requests concurrently
+    let response1 = app
+        .post_newsletters_with_idempotency_key(newsletter_request_body.clone(), &idempotency_key);
+    let response2 =
+        app.post_newsletters_with_idempotency_key(newsletter_request_body, &idempotency_key);
+    let (response1, response2) = tokio::join!(response1, response2);
+
+    // Assert
+    assert_eq!(response1.status(), response2.status());
+    assert_eq!(
+        response1.text().await.unwrap(),
+        response2.text().await.unwrap()
+    );
+    let n_issues = sqlx::query_scalar!(r#"SELECT count(*) as "count!" FROM newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to count newsletter issues.");
+    assert_eq!(1, n_issues);
+    app.dispatch_all_pending_emails().await;
+    // Mock verifies on Drop that we have sent the newsletter email **once**
+}
+
+#[tokio::test]
+async fn an_expired_idempotency_key_is_processed_again() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(email_sent_response())
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter content",
+            "html": "<h1>Newsletter content</h1>"
+        }
+    });
+    let idempotency_key = uuid::Uuid::new_v4().to_string();
+    app.post_newsletters_with_idempotency_key(newsletter_request_body.clone(), &idempotency_key)
         .await
         .error_for_status()
-        .expect("Failed to create subscriber.");
+        .expect("Failed to publish the newsletter.");
+
+    // Push the stored key past its expiration
+    sqlx::query!("UPDATE idempotency SET created_at = created_at - interval '30 days'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let response = app
+        .post_newsletters_with_idempotency_key(newsletter_request_body, &idempotency_key)
+        .await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    app.dispatch_all_pending_emails().await;
+    // Mock verifies on Drop that we have sent the newsletter email **twice**
+}
+
+#[tokio::test]
+async fn invalid_idempotency_keys_are_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter content",
+            "html": "<h1>Newsletter content</h1>"
+        }
+    });
+    let test_cases = [
+        ("".to_string(), "empty key"),
+        ("a".repeat(50), "key too long"),
+    ];
+
+    for (idempotency_key, description) in test_cases {
+        // Act
+        let response = app
+            .post_newsletters_with_idempotency_key(
+                newsletter_request_body.clone(),
+                &idempotency_key,
+            )
+            .await;
+
+        // Assert
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request when the idempotency key was an {}.",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn newsletter_bodies_are_personalised_for_each_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber_with(&app, "Ursula", "ursula@example.com").await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(email_sent_response())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let newsletter_request_body = serde_json::json!({
+        "title": "Tom & Jerry",
+        "content": {
+            "text": "Hi {{ subscriber.name }}, here is {{ issue.title }}.",
+            "html": "<h1>{{ issue.title }}</h1><p>Hi {{ subscriber.name }}</p><a href=\"{{ unsubscribe_url }}\">Leave</a>"
+        }
+    });
+    let response = app.post_newsletters(newsletter_request_body).await;
+    assert_eq!(200, response.status().as_u16());
+    app.dispatch_all_pending_emails().await;
 
-    let email_request = &app
+    // Assert
+    let email_request = app
         .email_server
         .received_requests()
         .await
         .unwrap()
         .pop()
         .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let text_body = body["TextBody"].as_str().unwrap();
+    let html_body = body["HtmlBody"].as_str().unwrap();
+    assert!(text_body.starts_with("Hi Ursula, here is Tom & Jerry."));
+    assert!(html_body.starts_with("<h1>Tom &amp; Jerry</h1><p>Hi Ursula</p>"));
+    // The same link as in the footer, escaped.
+    let unsubscribe_link = text_body
+        .rsplit_once("Unsubscribe from this newsletter: ")
+        .unwrap()
+        .1;
+    assert!(html_body.contains(&format!(
+        "<a href=\"{}\">Leave</a>",
+        htmlescape::encode_minimal(unsubscribe_link)
+    )));
+}
+
+#[tokio::test]
+async fn invalid_templates_are_rejected_with_a_400_before_queueing_anything() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let test_cases = [
+        (
+            serde_json::json!({"text": "Hi {{ subscriber.nickname }}", "html": "<p>Hi</p>"}),
+            "Invalid template in content.text: Unknown variable `subscriber.nickname` at line 1, column 4.",
+        ),
+        (
+            serde_json::json!({"text": "Hi", "html": "<p>\nHi {{ subscriber.name</p>"}),
+            "Invalid template in content.html: The `{{` at line 2, column 4 is never closed.",
+        ),
+    ];
+
+    for (content, error_message) in test_cases {
+        // Act
+        let response = app
+            .post_newsletters(serde_json::json!({
+                "title": "Newsletter title",
+                "content": content,
+            }))
+            .await;
 
-    app.get_confirmation_links(email_request)
+        // Assert
+        assert_eq!(400, response.status().as_u16());
+        assert_eq!(response.text().await.unwrap(), error_message);
+    }
+    let n_tasks = sqlx::query_scalar!(r#"SELECT count(*) as "count!" FROM issue_delivery_queue"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(n_tasks, 0);
 }
 
-async fn create_confirmed_subscriber(app: &TestApp) {
-    let confirmation_link = create_unconfirmed_subscriber(app).await;
-    reqwest::get(confirmation_link.html)
+#[tokio::test]
+async fn newsletters_can_be_written_in_markdown() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber_with(&app, "Ursula", "ursula@example.com").await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(email_sent_response())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "markdown": "Hi **{{ subscriber.name }}**, read [the post](https://example.com/post).\n\n<script>alert('xss')</script>"
+        }
+    });
+    let response = app.post_newsletters(newsletter_request_body).await;
+    assert_eq!(200, response.status().as_u16());
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let email_request = app
+        .email_server
+        .received_requests()
         .await
-        .expect("Failed to confirm subscription.")
-        .error_for_status()
-        .expect("Failed to confirm subscription.");
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let html_body = body["HtmlBody"].as_str().unwrap();
+    let text_body = body["TextBody"].as_str().unwrap();
+    assert!(html_body.starts_with(
+        "<p>Hi <strong>Ursula</strong>, read \
+        <a href=\"https://example.com/post\" rel=\"noopener noreferrer\">the post</a>.</p>"
+    ));
+    assert!(!html_body.contains("<script"));
+    assert!(
+        text_body.starts_with("Hi Ursula, read the post [1].\n\n[1] https://example.com/post\n\n")
+    );
+}
+
+#[tokio::test]
+async fn newsletter_content_must_be_either_markdown_or_html_and_text() {
+    // Arrange
+    let app = spawn_app().await;
+    let test_cases = [
+        (serde_json::json!({}), "no content"),
+        (serde_json::json!({"html": "<p>Hi</p>"}), "no plain text"),
+        (
+            serde_json::json!({"markdown": "Hi", "html": "<p>Hi</p>", "text": "Hi"}),
+            "both forms",
+        ),
+    ];
+
+    for (content, description) in test_cases {
+        // Act
+        let response = app
+            .post_newsletters(serde_json::json!({
+                "title": "Newsletter title",
+                "content": content,
+            }))
+            .await;
+
+        // Assert
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request when there was {}.",
+            description
+        );
+        assert_eq!(
+            response.text().await.unwrap(),
+            "Provide either content.markdown, or both content.html and content.text."
+        );
+    }
+}
+
+#[tokio::test]
+async fn template_errors_in_markdown_point_at_the_markdown_source() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {"markdown": "# Hello\n\nDear {{ reader }}"},
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+    assert_eq!(
+        response.text().await.unwrap(),
+        "Invalid template in content.markdown: Unknown variable `reader` at line 3, column 6."
+    );
 }
@@ -0,0 +1,316 @@
+use chrono::{Duration, Utc};
+use uuid::Uuid;
+use wiremock::matchers::{any, method, path};
+use wiremock::Mock;
+
+use crate::helpers::{create_confirmed_subscriber, email_sent_response, spawn_app, TestApp};
+
+fn draft_request_body(title: &str) -> serde_json::Value {
+    serde_json::json!({
+        "title": title,
+        "content": {
+            "text": "Hi {{ subscriber.name }}, here is {{ issue.title }}.",
+            "html": "<h1>{{ issue.title }}</h1><p>Hi {{ subscriber.name }}</p>",
+        }
+    })
+}
+
+/// Create a draft and return its id.
+async fn create_draft(app: &TestApp, title: &str) -> Uuid {
+    let response = app.post_drafts(draft_request_body(title)).await;
+    assert_eq!(201, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    body["newsletter_issue_id"]
+        .as_str()
+        .unwrap()
+        .parse()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn drafts_are_not_delivered_until_published() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let issue_id = create_draft(&app, "Draft title").await;
+
+    // Act - Part 1 - Saving a draft sends nothing
+    let _mock_guard = Mock::given(any())
+        .respond_with(email_sent_response())
+        .expect(0)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+    drop(_mock_guard);
+
+    // Act - Part 2 - Publish the draft, without any content
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(email_sent_response())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let response = app
+        .post_publish_draft(issue_id, serde_json::json!({}))
+        .await;
+    assert_eq!(200, response.status().as_u16());
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["Subject"], "Draft title");
+    assert!(body["HtmlBody"]
+        .as_str()
+        .unwrap()
+        .starts_with("<h1>Draft title</h1>"));
+}
+
+#[tokio::test]
+async fn publishing_uses_the_latest_version_of_the_draft() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let issue_id = create_draft(&app, "First title").await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(email_sent_response())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .put_draft(issue_id, draft_request_body("Second title"))
+        .await;
+    assert_eq!(200, response.status().as_u16());
+    app.post_publish_draft(issue_id, serde_json::json!({}))
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["Subject"], "Second title");
+}
+
+#[tokio::test]
+async fn the_preview_renders_the_draft_as_html() {
+    // Arrange
+    let app = spawn_app().await;
+    let issue_id = create_draft(&app, "Tom & Jerry").await;
+
+    // Act
+    let response = app.get_draft_preview(issue_id).await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    assert!(response
+        .headers()
+        .get("Content-Type")
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .starts_with("text/html"));
+    assert_eq!(response.headers()["Content-Security-Policy"], "sandbox");
+    let html = response.text().await.unwrap();
+    assert!(html.starts_with("<h1>Tom &amp; Jerry</h1><p>Hi Jane Doe</p>"));
+    assert!(html.contains("Manage your subscription"));
+}
+
+#[tokio::test]
+async fn test_sends_only_go_to_the_given_addresses() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let issue_id = create_draft(&app, "Draft title").await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(email_sent_response())
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_test_send_draft(
+            issue_id,
+            serde_json::json!({ "emails": ["alice@example.com", "bob@example.com"] }),
+        )
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let requests = app.email_server.received_requests().await.unwrap();
+    // The confirmation email of the subscriber comes first.
+    let bodies: Vec<serde_json::Value> = requests[requests.len() - 2..]
+        .iter()
+        .map(|r| serde_json::from_slice(&r.body).unwrap())
+        .collect();
+    assert_eq!(bodies[0]["To"], "alice@example.com");
+    assert_eq!(bodies[1]["To"], "bob@example.com");
+    for body in &bodies {
+        assert_eq!(body["Subject"], "[TEST] Draft title");
+    }
+    // The draft is still a draft.
+    let response = app
+        .put_draft(issue_id, draft_request_body("Draft title"))
+        .await;
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn test_sends_reject_invalid_recipient_lists() {
+    // Arrange
+    let app = spawn_app().await;
+    let issue_id = create_draft(&app, "Draft title").await;
+    Mock::given(any())
+        .respond_with(email_sent_response())
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let too_many: Vec<String> = (0..11).map(|i| format!("user{}@example.com", i)).collect();
+    let test_cases = vec![
+        (serde_json::json!({ "emails": [] }), "no addresses"),
+        (
+            serde_json::json!({ "emails": ["alice@example.com", "not-an-email"] }),
+            "an invalid address",
+        ),
+        (
+            serde_json::json!({ "emails": too_many }),
+            "too many addresses",
+        ),
+    ];
+
+    for (body, description) in test_cases {
+        // Act
+        let response = app.post_test_send_draft(issue_id, body).await;
+
+        // Assert
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request when the payload had {}.",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn a_draft_can_be_scheduled() {
+    // Arrange
+    let app = spawn_app().await;
+    let issue_id = create_draft(&app, "Draft title").await;
+    let send_at = Utc::now() + Duration::hours(1);
+
+    // Act
+    let response = app
+        .post_publish_draft(issue_id, serde_json::json!({ "send_at": send_at }))
+        .await;
+
+    // Assert
+    assert_eq!(202, response.status().as_u16());
+    let scheduled: serde_json::Value = app.get_scheduled_issues().await.json().await.unwrap();
+    assert_eq!(scheduled[0]["newsletter_issue_id"], issue_id.to_string());
+}
+
+#[tokio::test]
+async fn published_drafts_cannot_be_changed_or_published_again() {
+    // Arrange
+    let app = spawn_app().await;
+    let issue_id = create_draft(&app, "Draft title").await;
+    let response = app
+        .post_publish_draft(issue_id, serde_json::json!({}))
+        .await;
+    assert_eq!(200, response.status().as_u16());
+
+    // Act
+    let update = app
+        .put_draft(issue_id, draft_request_body("Another title"))
+        .await;
+    let publish = app
+        .post_publish_draft(issue_id, serde_json::json!({}))
+        .await;
+    let preview = app.get_draft_preview(issue_id).await;
+
+    // Assert
+    assert_eq!(409, update.status().as_u16());
+    assert_eq!(409, publish.status().as_u16());
+    assert_eq!(409, preview.status().as_u16());
+}
+
+#[tokio::test]
+async fn unknown_drafts_are_rejected_with_a_404() {
+    // Arrange
+    let app = spawn_app().await;
+    let issue_id = Uuid::new_v4();
+
+    // Act
+    let update = app
+        .put_draft(issue_id, draft_request_body("Draft title"))
+        .await;
+    let publish = app
+        .post_publish_draft(issue_id, serde_json::json!({}))
+        .await;
+    let test_send = app
+        .post_test_send_draft(
+            issue_id,
+            serde_json::json!({ "emails": ["alice@example.com"] }),
+        )
+        .await;
+
+    // Assert
+    assert_eq!(404, update.status().as_u16());
+    assert_eq!(404, publish.status().as_u16());
+    assert_eq!(404, test_send.status().as_u16());
+}
+
+#[tokio::test]
+async fn drafts_with_invalid_templates_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_drafts(serde_json::json!({
+            "title": "Draft title",
+            "content": { "markdown": "Hi {{ subscriber.age }}" }
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn requests_without_credentials_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    let issue_id = create_draft(&app, "Draft title").await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .get(format!(
+            "{}/newsletters/drafts/{}/preview",
+            &app.address, issue_id
+        ))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(401, response.status().as_u16());
+}
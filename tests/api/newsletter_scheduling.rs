@@ -0,0 +1,250 @@
+use chrono::{DateTime, Duration, Utc};
+use uuid::Uuid;
+use wiremock::matchers::{any, method, path};
+use wiremock::Mock;
+
+use crate::helpers::{create_confirmed_subscriber, email_sent_response, spawn_app, TestApp};
+
+fn newsletter_request_body(send_at: DateTime<Utc>) -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        },
+        "send_at": send_at,
+    })
+}
+
+/// Schedule an issue and return its id.
+async fn schedule_issue(app: &TestApp, send_at: DateTime<Utc>) -> Uuid {
+    let response = app.post_newsletters(newsletter_request_body(send_at)).await;
+    assert_eq!(202, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    body["newsletter_issue_id"]
+        .as_str()
+        .unwrap()
+        .parse()
+        .unwrap()
+}
+
+/// Pretend the issue's time has come.
+async fn make_due(app: &TestApp, issue_id: Uuid) {
+    sqlx::query!(
+        "UPDATE newsletter_issues SET send_at = now() WHERE newsletter_issue_id = $1",
+        issue_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn scheduled_issues_are_only_delivered_once_due() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let issue_id = schedule_issue(&app, Utc::now() + Duration::hours(1)).await;
+
+    // Act - Part 1 - Nothing is due yet
+    let _mock_guard = Mock::given(any())
+        .respond_with(email_sent_response())
+        .expect(0)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.release_due_issues().await;
+    app.dispatch_all_pending_emails().await;
+    drop(_mock_guard);
+
+    // Act - Part 2 - The issue is due
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(email_sent_response())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    make_due(&app, issue_id).await;
+    app.release_due_issues().await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let issue = sqlx::query!(
+        "SELECT status, published_at FROM newsletter_issues WHERE newsletter_issue_id = $1",
+        issue_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(issue.status, "published");
+    assert!(issue.published_at.is_some());
+}
+
+#[tokio::test]
+async fn issues_scheduled_in_the_past_are_sent_right_away() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(email_sent_response())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletters(newsletter_request_body(Utc::now() - Duration::minutes(1)))
+        .await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn a_due_issue_is_released_only_once() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let issue_id = schedule_issue(&app, Utc::now() + Duration::hours(1)).await;
+    make_due(&app, issue_id).await;
+
+    // Act - Several schedulers race to release the issue
+    tokio::join!(
+        app.release_due_issues(),
+        app.release_due_issues(),
+        app.release_due_issues(),
+        app.release_due_issues()
+    );
+
+    // Assert
+    let n_tasks = sqlx::query_scalar!(
+        r#"SELECT count(*) as "count!" FROM issue_delivery_queue WHERE newsletter_issue_id = $1"#,
+        issue_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(n_tasks, 1);
+}
+
+#[tokio::test]
+async fn scheduled_issues_can_be_listed() {
+    // Arrange
+    let app = spawn_app().await;
+    let later = schedule_issue(&app, Utc::now() + Duration::hours(2)).await;
+    let sooner = schedule_issue(&app, Utc::now() + Duration::hours(1)).await;
+
+    // Act
+    let response = app.get_scheduled_issues().await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let issues: Vec<serde_json::Value> = response.json().await.unwrap();
+    let ids: Vec<_> = issues
+        .iter()
+        .map(|issue| issue["newsletter_issue_id"].as_str().unwrap().to_owned())
+        .collect();
+    assert_eq!(ids, vec![sooner.to_string(), later.to_string()]);
+    assert_eq!(issues[0]["title"], "Newsletter title");
+}
+
+#[tokio::test]
+async fn scheduled_issues_can_be_rescheduled() {
+    // Arrange
+    let app = spawn_app().await;
+    let issue_id = schedule_issue(&app, Utc::now() + Duration::hours(1)).await;
+    let send_at = Utc::now() + Duration::days(1);
+
+    // Act
+    let response = app
+        .post_reschedule_issue(issue_id, serde_json::json!({ "send_at": send_at }))
+        .await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let stored_send_at = sqlx::query_scalar!(
+        "SELECT send_at FROM newsletter_issues WHERE newsletter_issue_id = $1",
+        issue_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .unwrap();
+    // Postgres stores microseconds.
+    assert!((stored_send_at - send_at).num_milliseconds().abs() < 1);
+}
+
+#[tokio::test]
+async fn cancelled_issues_are_never_delivered() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let issue_id = schedule_issue(&app, Utc::now() + Duration::hours(1)).await;
+    Mock::given(any())
+        .respond_with(email_sent_response())
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_cancel_issue(issue_id).await;
+    assert_eq!(200, response.status().as_u16());
+    make_due(&app, issue_id).await;
+    app.release_due_issues().await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let issues: Vec<serde_json::Value> = app.get_scheduled_issues().await.json().await.unwrap();
+    assert!(issues.is_empty());
+}
+
+#[tokio::test]
+async fn issues_which_went_out_cannot_be_rescheduled_or_cancelled() {
+    // Arrange
+    let app = spawn_app().await;
+    let issue_id = schedule_issue(&app, Utc::now() + Duration::hours(1)).await;
+    make_due(&app, issue_id).await;
+    app.release_due_issues().await;
+
+    // Act
+    let reschedule_response = app
+        .post_reschedule_issue(
+            issue_id,
+            serde_json::json!({ "send_at": Utc::now() + Duration::hours(1) }),
+        )
+        .await;
+    let cancel_response = app.post_cancel_issue(issue_id).await;
+
+    // Assert
+    assert_eq!(409, reschedule_response.status().as_u16());
+    assert_eq!(409, cancel_response.status().as_u16());
+}
+
+#[tokio::test]
+async fn unknown_issues_cannot_be_cancelled() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.post_cancel_issue(Uuid::new_v4()).await;
+
+    // Assert
+    assert_eq!(404, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn managing_scheduled_issues_requires_authentication() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .get(format!("{}/newsletters/scheduled", &app.address))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(401, response.status().as_u16());
+}
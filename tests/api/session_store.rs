@@ -0,0 +1,70 @@
+use std::collections::HashMap;
+
+use actix_session::storage::SessionStore;
+use actix_web::cookie::time::Duration;
+use claims::{assert_none, assert_ok};
+use secrecy::Secret;
+
+use zero2prod::configuration::get_configuration;
+use zero2prod::session_store::PostgresSessionStore;
+use zero2prod::startup::Application;
+
+use crate::helpers::spawn_app;
+
+#[tokio::test]
+async fn postgres_session_store_round_trips_session_state() {
+    // Arrange
+    let app = spawn_app().await;
+    let store = PostgresSessionStore::new(app.db_pool.clone());
+    let state = HashMap::from([("user_id".to_string(), "\"42\"".to_string())]);
+
+    // Act - Part 1 - Save and load
+    let session_key = assert_ok!(store.save(state.clone(), &Duration::minutes(10)).await);
+    let loaded = assert_ok!(store.load(&session_key).await);
+    assert_eq!(Some(state), loaded);
+
+    // Act - Part 2 - Update
+    let new_state = HashMap::from([("user_id".to_string(), "\"43\"".to_string())]);
+    let session_key = assert_ok!(
+        store
+            .update(session_key, new_state.clone(), &Duration::minutes(10))
+            .await
+    );
+    let loaded = assert_ok!(store.load(&session_key).await);
+    assert_eq!(Some(new_state), loaded);
+
+    // Act - Part 3 - Delete
+    assert_ok!(store.delete(&session_key).await);
+    assert_none!(assert_ok!(store.load(&session_key).await));
+}
+
+#[tokio::test]
+async fn postgres_session_store_ignores_expired_sessions() {
+    // Arrange
+    let app = spawn_app().await;
+    let store = PostgresSessionStore::new(app.db_pool.clone());
+    let state = HashMap::from([("user_id".to_string(), "\"42\"".to_string())]);
+
+    // Act
+    let session_key = assert_ok!(store.save(state, &Duration::seconds(-1)).await);
+
+    // Assert
+    assert_none!(assert_ok!(store.load(&session_key).await));
+}
+
+#[tokio::test]
+async fn the_application_refuses_to_start_with_a_short_session_key() {
+    // Arrange
+    let mut configuration = get_configuration().expect("Failed to read configuration.");
+    configuration.application.port = 0;
+    configuration.session.secret_key = Secret::new("too-short".into());
+
+    // Act
+    let outcome = Application::build(configuration).await;
+
+    // Assert
+    let Err(e) = outcome else {
+        panic!("The application started with a short session key.");
+    };
+    assert!(e.to_string().contains("session secret key"));
+}
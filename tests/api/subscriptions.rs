@@ -11,7 +11,7 @@ async fn subscribe_returns_a_200_for_valid_form_data() {
 
     Mock::given(path("/email"))
         .and(method("POST"))
-        .respond_with(ResponseTemplate::new(200))
+        .respond_with(helpers::email_sent_response())
         .expect(1)
         .mount(&app.email_server)
         .await;
@@ -21,6 +21,7 @@ async fn subscribe_returns_a_200_for_valid_form_data() {
 
     // Assert
     assert_eq!(200, response.status().as_u16());
+    app.wait_for_emails(1).await;
 }
 
 #[tokio::test]
@@ -30,7 +31,7 @@ async fn subscriber_persists_the_new_subscriber() {
     let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
     Mock::given(method("POST"))
         .and(path("/email"))
-        .respond_with(ResponseTemplate::new(200))
+        .respond_with(helpers::email_sent_response())
         .expect(1)
         .mount(&app.email_server)
         .await;
@@ -47,6 +48,7 @@ async fn subscriber_persists_the_new_subscriber() {
     assert_eq!("ursula_le_guin@gmail.com", saved.email);
     assert_eq!("le guin", saved.name);
     assert_eq!("pending_confirmation", saved.status);
+    app.wait_for_emails(1).await;
 }
 
 #[tokio::test]
@@ -107,7 +109,7 @@ async fn subscribe_sends_a_confirmation_email_for_valid_data() {
 
     Mock::given(method("POST"))
         .and(path("/email"))
-        .respond_with(ResponseTemplate::new(200))
+        .respond_with(helpers::email_sent_response())
         .expect(1)
         .mount(&app.email_server)
         .await;
@@ -116,6 +118,7 @@ async fn subscribe_sends_a_confirmation_email_for_valid_data() {
     app.post_subscriptions(body).await;
 
     // Assert
+    app.wait_for_emails(1).await;
     // Mock asserts on drop
 }
 
@@ -127,7 +130,7 @@ async fn subscribe_sends_a_confirmation_email_with_a_link() {
 
     Mock::given(method("POST"))
         .and(path("/email"))
-        .respond_with(ResponseTemplate::new(200))
+        .respond_with(helpers::email_sent_response())
         .expect(1)
         .mount(&app.email_server)
         .await;
@@ -136,7 +139,7 @@ async fn subscribe_sends_a_confirmation_email_with_a_link() {
     app.post_subscriptions(body).await;
 
     // Assert
-    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let email_request = &app.wait_for_emails(1).await[0];
     let confirmation_links = app.get_confirmation_links(email_request);
 
     assert_eq!(confirmation_links.html, confirmation_links.plain_text);
@@ -160,3 +163,200 @@ async fn subscribe_fails_if_there_is_a_fatal_database_error() {
     // Assert
     assert_eq!(500, response.status().as_u16());
 }
+
+#[tokio::test]
+async fn subscribing_twice_while_pending_resends_the_confirmation_email_with_a_new_link() {
+    // Arrange
+    let app = helpers::spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(method("POST"))
+        .and(path("/email"))
+        .respond_with(helpers::email_sent_response())
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let first_response = app.post_subscriptions(body).await;
+    let second_response = app.post_subscriptions(body).await;
+
+    // Assert
+    assert_eq!(200, first_response.status().as_u16());
+    assert_eq!(200, second_response.status().as_u16());
+    let email_requests = app.wait_for_emails(2).await;
+    let first_link = app.get_confirmation_links(&email_requests[0]).html;
+    let second_link = app.get_confirmation_links(&email_requests[1]).html;
+    assert_ne!(first_link, second_link);
+    // Only the latest link is valid
+    assert_eq!(
+        401,
+        reqwest::get(first_link).await.unwrap().status().as_u16()
+    );
+    assert_eq!(
+        200,
+        reqwest::get(second_link).await.unwrap().status().as_u16()
+    );
+}
+
+#[tokio::test]
+async fn subscribing_again_once_confirmed_returns_a_200_without_sending_an_email() {
+    // Arrange
+    let app = helpers::spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(method("POST"))
+        .and(path("/email"))
+        .respond_with(helpers::email_sent_response())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions(body).await;
+    let email_request = &app.wait_for_emails(1).await[0];
+    reqwest::get(app.get_confirmation_links(email_request).html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    let response = app.post_subscriptions(body).await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!("confirmed", saved.status);
+    // Mock asserts on drop that no further email went out
+}
+
+#[tokio::test]
+async fn subscribing_again_after_unsubscribing_starts_a_new_double_opt_in() {
+    // Arrange
+    let app = helpers::spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(method("POST"))
+        .and(path("/email"))
+        .respond_with(helpers::email_sent_response())
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions(body).await;
+    sqlx::query!("UPDATE subscriptions SET status = 'unsubscribed', unsubscribed_at = now()")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act - Part 1 - Subscribe again
+    let response = app.post_subscriptions(body).await;
+    assert_eq!(200, response.status().as_u16());
+    let saved = sqlx::query!("SELECT status, unsubscribed_at FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!("pending_confirmation", saved.status);
+    assert!(saved.unsubscribed_at.is_none());
+
+    // Act - Part 2 - Follow the new confirmation link
+    let email_request = &app.wait_for_emails(2).await[1];
+    let response = reqwest::get(app.get_confirmation_links(email_request).html)
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!("confirmed", saved.status);
+}
+
+#[tokio::test]
+async fn concurrent_first_time_subscriptions_for_the_same_address_both_succeed() {
+    // Arrange
+    let app = helpers::spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(method("POST"))
+        .and(path("/email"))
+        .respond_with(helpers::email_sent_response())
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let (first_response, second_response) =
+        tokio::join!(app.post_subscriptions(body), app.post_subscriptions(body));
+
+    // Assert
+    assert_eq!(200, first_response.status().as_u16());
+    assert_eq!(200, second_response.status().as_u16());
+    let n_subscribers = sqlx::query_scalar!(r#"SELECT count(*) as "count!" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(1, n_subscribers);
+    app.wait_for_emails(2).await;
+}
+
+#[tokio::test]
+async fn subscribing_again_does_not_change_the_name_of_the_subscriber() {
+    // Arrange
+    let app = helpers::spawn_app().await;
+
+    Mock::given(method("POST"))
+        .and(path("/email"))
+        .respond_with(helpers::email_sent_response())
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await;
+
+    // Act
+    let response = app
+        .post_subscriptions("name=somebody%20else&email=ursula_le_guin%40gmail.com")
+        .await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let saved = sqlx::query!("SELECT name FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!("le guin", saved.name);
+    let email_request = &app.wait_for_emails(2).await[1];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert!(body["TextBody"].as_str().unwrap().contains("le guin"));
+    assert!(!body["TextBody"].as_str().unwrap().contains("somebody else"));
+}
+
+#[tokio::test]
+async fn subscribe_returns_a_200_even_if_the_email_cannot_be_sent() {
+    // Arrange
+    let app = helpers::spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(method("POST"))
+        .and(path("/email"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_subscriptions(body).await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!("pending_confirmation", saved.status);
+    app.wait_for_emails(1).await;
+}
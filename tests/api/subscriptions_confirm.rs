@@ -1,7 +1,7 @@
 use wiremock::matchers::{method, path};
-use wiremock::{Mock, ResponseTemplate};
+use wiremock::Mock;
 
-use crate::helpers::spawn_app;
+use crate::helpers::{create_unconfirmed_subscriber, email_sent_response, spawn_app};
 
 #[tokio::test]
 async fn confirmations_without_toke_are_rejected_with_a_400() {
@@ -25,13 +25,13 @@ async fn the_link_returned_by_subscribe_returns_a_200_if_called_with_a_valid_tok
 
     Mock::given(path("/email"))
         .and(method("POST"))
-        .respond_with(ResponseTemplate::new(200))
+        .respond_with(email_sent_response())
         .expect(1)
         .mount(&app.email_server)
         .await;
 
     app.post_subscriptions(body).await;
-    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let email_request = &app.wait_for_emails(1).await[0];
     let confirmation_links = app.get_confirmation_links(email_request);
 
     // Act
@@ -51,13 +51,13 @@ async fn clicking_on_the_confirmation_link_confirms_a_subscriber() {
 
     Mock::given(path("/email"))
         .and(method("POST"))
-        .respond_with(ResponseTemplate::new(200))
+        .respond_with(email_sent_response())
         .expect(1)
         .mount(&app.email_server)
         .await;
 
     app.post_subscriptions(body).await;
-    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let email_request = &app.wait_for_emails(1).await[0];
     let confirmation_links = app.get_confirmation_links(email_request);
 
     // Act
@@ -75,3 +75,73 @@ async fn clicking_on_the_confirmation_link_confirms_a_subscriber() {
     assert_eq!("le guin", saved.name);
     assert_eq!("confirmed", saved.status);
 }
+
+#[tokio::test]
+async fn a_confirmation_link_can_only_be_used_once() {
+    // Arrange
+    let app = spawn_app().await;
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+    let response = reqwest::get(confirmation_links.html.clone())
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(200, response.status().as_u16());
+
+    // Act
+    let response = reqwest::get(confirmation_links.html)
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(410, response.status().as_u16());
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("This confirmation link has already been used."));
+}
+
+#[tokio::test]
+async fn an_expired_confirmation_link_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+    sqlx::query!("UPDATE subscription_tokens SET created_at = now() - interval '3 days'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let response = reqwest::get(confirmation_links.html)
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(410, response.status().as_u16());
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("This confirmation link has expired."));
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscriber.");
+    assert_eq!("pending_confirmation", saved.status);
+}
+
+#[tokio::test]
+async fn an_unknown_confirmation_token_is_rejected_with_a_401() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::get(&format!(
+        "{}/subscriptions/confirm?subscription_token=unknown",
+        &app.address
+    ))
+    .await
+    .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(401, response.status().as_u16());
+}
@@ -0,0 +1,424 @@
+use wiremock::matchers::{any, method, path};
+use wiremock::Mock;
+use zero2prod::routes::preferences_link;
+
+use crate::helpers::{assert_is_redirect_to, create_confirmed_subscriber, email_sent_response};
+use crate::helpers::{spawn_app, TestApp};
+
+/// Create a confirmed subscriber and return the link to their preferences page.
+async fn confirmed_subscriber_preferences_link(app: &TestApp) -> reqwest::Url {
+    create_confirmed_subscriber(app).await;
+    let subscriber_id = sqlx::query_scalar!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    let mut link = reqwest::Url::parse(&preferences_link(
+        &app.base_url,
+        &app.hmac_secret,
+        subscriber_id,
+    ))
+    .unwrap();
+    link.set_port(Some(app.port)).unwrap();
+    link
+}
+
+async fn post_preferences<Body>(
+    app: &TestApp,
+    preferences_link: &reqwest::Url,
+    action: &str,
+    body: &Body,
+) -> reqwest::Response
+where
+    Body: serde::Serialize,
+{
+    let mut url = preferences_link.clone();
+    url.set_path(&format!("/subscriptions/preferences/{}", action));
+    app.api_client
+        .post(url)
+        .form(body)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn get_preferences_html(app: &TestApp, preferences_link: &reqwest::Url) -> String {
+    app.api_client
+        .get(preferences_link.clone())
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .text()
+        .await
+        .unwrap()
+}
+
+/// Where the preferences forms redirect to, relative to the application's base URL.
+fn page_path(preferences_link: &reqwest::Url) -> String {
+    format!(
+        "{}?{}",
+        preferences_link.path(),
+        preferences_link.query().unwrap()
+    )
+}
+
+#[tokio::test]
+async fn newsletters_link_to_the_preferences_page() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(email_sent_response())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_newsletters(serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter content",
+            "html": "<h1>Newsletter content</h1>"
+        }
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+    app.dispatch_all_pending_emails().await;
+    // The last email, the previous ones were about confirming the subscription
+    let email_request = &app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let preferences_links = app.get_preferences_links(email_request);
+    assert_eq!(preferences_links.html, preferences_links.plain_text);
+
+    // Act
+    let response = reqwest::get(preferences_links.html).await.unwrap();
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    assert!(response.text().await.unwrap().contains("Change name"));
+}
+
+#[tokio::test]
+async fn preferences_requests_with_a_tampered_token_are_rejected_with_a_401() {
+    // Arrange
+    let app = spawn_app().await;
+    let mut preferences_link = confirmed_subscriber_preferences_link(&app).await;
+    let subscriber_id = preferences_link
+        .query_pairs()
+        .find(|(key, _)| key == "subscriber_id")
+        .unwrap()
+        .1
+        .into_owned();
+    preferences_link
+        .query_pairs_mut()
+        .clear()
+        .append_pair("subscriber_id", &subscriber_id)
+        .append_pair("token", "00");
+
+    // Act
+    let response = reqwest::get(preferences_link).await.unwrap();
+
+    // Assert
+    assert_eq!(401, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn subscribers_can_change_their_name() {
+    // Arrange
+    let app = spawn_app().await;
+    let preferences_link = confirmed_subscriber_preferences_link(&app).await;
+
+    // Act - Part 1 - Submit the new name
+    let response = post_preferences(
+        &app,
+        &preferences_link,
+        "name",
+        &serde_json::json!({ "name": "Ursula Le Guin" }),
+    )
+    .await;
+    assert_is_redirect_to(&response, &page_path(&preferences_link));
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = get_preferences_html(&app, &preferences_link).await;
+
+    // Assert
+    assert!(html_page.contains("<p><i>Your name has been updated.</i></p>"));
+    let saved = sqlx::query!("SELECT name FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!("Ursula Le Guin", saved.name);
+}
+
+#[tokio::test]
+async fn invalid_names_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    let preferences_link = confirmed_subscriber_preferences_link(&app).await;
+    let name_before = sqlx::query_scalar!("SELECT name FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let response = post_preferences(
+        &app,
+        &preferences_link,
+        "name",
+        &serde_json::json!({ "name": "<script>" }),
+    )
+    .await;
+
+    // Assert
+    assert_is_redirect_to(&response, &page_path(&preferences_link));
+    let html_page = get_preferences_html(&app, &preferences_link).await;
+    assert!(html_page.contains("<p><i>That is not a valid name.</i></p>"));
+    let name_after = sqlx::query_scalar!("SELECT name FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(name_before, name_after);
+}
+
+#[tokio::test]
+async fn a_new_email_only_takes_effect_once_confirmed() {
+    // Arrange
+    let app = spawn_app().await;
+    let preferences_link = confirmed_subscriber_preferences_link(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(email_sent_response())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Part 1 - Ask for the change
+    let response = post_preferences(
+        &app,
+        &preferences_link,
+        "email",
+        &serde_json::json!({ "email": "new_address@gmail.com" }),
+    )
+    .await;
+    assert_is_redirect_to(&response, &page_path(&preferences_link));
+    let saved = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_ne!("new_address@gmail.com", saved.email);
+
+    // Act - Part 2 - Follow the link sent to the new address
+    // The last email, the previous one was about confirming the subscription
+    let email_request = &app.wait_for_emails(2).await.pop().unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!("new_address@gmail.com", body["To"]);
+    let email_change_links = app.get_email_change_links(email_request);
+    let response = reqwest::get(email_change_links.html.clone()).await.unwrap();
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let saved = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!("new_address@gmail.com", saved.email);
+    // The link can't be used twice
+    let response = reqwest::get(email_change_links.html).await.unwrap();
+    assert_eq!(410, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn a_new_email_taken_in_the_meantime_is_rejected_with_a_409() {
+    // Arrange
+    let app = spawn_app().await;
+    let preferences_link = confirmed_subscriber_preferences_link(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(email_sent_response())
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    post_preferences(
+        &app,
+        &preferences_link,
+        "email",
+        &serde_json::json!({ "email": "new_address@gmail.com" }),
+    )
+    .await;
+    let email_request = app.wait_for_emails(2).await.pop().unwrap();
+    let email_change_links = app.get_email_change_links(&email_request);
+    // Somebody else subscribes with the new address before the change is confirmed
+    app.post_subscriptions("name=someone%20else&email=new_address%40gmail.com")
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    let response = reqwest::get(email_change_links.html).await.unwrap();
+
+    // Assert
+    assert_eq!(409, response.status().as_u16());
+    let n_subscribers = sqlx::query_scalar!(
+        r#"SELECT count(*) as "count!" FROM subscriptions WHERE email = 'new_address@gmail.com'"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(1, n_subscribers);
+    app.wait_for_emails(3).await;
+}
+
+#[tokio::test]
+async fn email_changes_count_towards_the_confirmation_email_limit() {
+    // Arrange
+    let app = spawn_app().await;
+    let preferences_link = confirmed_subscriber_preferences_link(&app).await;
+    // The limit is 3 confirmation emails per address, the subscription itself included
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(email_sent_response())
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    for i in 0..3 {
+        let response = post_preferences(
+            &app,
+            &preferences_link,
+            "email",
+            &serde_json::json!({ "email": format!("new_address_{}@gmail.com", i) }),
+        )
+        .await;
+        assert_is_redirect_to(&response, &page_path(&preferences_link));
+    }
+
+    // Assert
+    app.wait_for_emails(3).await;
+    let html = get_preferences_html(&app, &preferences_link).await;
+    assert!(html.contains("too many confirmation links recently"));
+    let n_requests =
+        sqlx::query_scalar!(r#"SELECT count(*) as "count!" FROM email_change_requests"#)
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(2, n_requests);
+}
+
+#[tokio::test]
+async fn paused_subscribers_do_not_receive_newsletters() {
+    // Arrange
+    let app = spawn_app().await;
+    let preferences_link = confirmed_subscriber_preferences_link(&app).await;
+    let response = post_preferences(
+        &app,
+        &preferences_link,
+        "pause",
+        &serde_json::json!({ "weeks": 2 }),
+    )
+    .await;
+    assert_is_redirect_to(&response, &page_path(&preferences_link));
+    let html_page = get_preferences_html(&app, &preferences_link).await;
+    assert!(html_page.contains("<p><i>Delivery is paused for 2 weeks.</i></p>"));
+
+    Mock::given(any())
+        .respond_with(email_sent_response())
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter content",
+                "html": "<h1>Newsletter content</h1>"
+            }
+        }))
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    // Mock verifies on Drop that we haven't sent the newsletter email
+}
+
+#[tokio::test]
+async fn subscribers_who_pause_after_an_issue_is_published_do_not_receive_it() {
+    // Arrange
+    let app = spawn_app().await;
+    let preferences_link = confirmed_subscriber_preferences_link(&app).await;
+    Mock::given(any())
+        .respond_with(email_sent_response())
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    app.post_newsletters(serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter content",
+            "html": "<h1>Newsletter content</h1>"
+        }
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+
+    // Act
+    let response = post_preferences(
+        &app,
+        &preferences_link,
+        "pause",
+        &serde_json::json!({ "weeks": 2 }),
+    )
+    .await;
+    assert_is_redirect_to(&response, &page_path(&preferences_link));
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let status = sqlx::query_scalar!("SELECT status FROM deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!("skipped", status);
+    // Mock verifies on Drop that we haven't sent the newsletter email
+}
+
+#[tokio::test]
+async fn subscribers_can_resume_delivery() {
+    // Arrange
+    let app = spawn_app().await;
+    let preferences_link = confirmed_subscriber_preferences_link(&app).await;
+    post_preferences(
+        &app,
+        &preferences_link,
+        "pause",
+        &serde_json::json!({ "weeks": 2 }),
+    )
+    .await;
+    assert!(get_preferences_html(&app, &preferences_link)
+        .await
+        .contains("Resume delivery"));
+
+    // Act
+    let response = post_preferences(
+        &app,
+        &preferences_link,
+        "pause",
+        &serde_json::json!({ "weeks": 0 }),
+    )
+    .await;
+
+    // Assert
+    assert_is_redirect_to(&response, &page_path(&preferences_link));
+    let saved = sqlx::query!("SELECT paused_until FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(saved.paused_until.is_none());
+}
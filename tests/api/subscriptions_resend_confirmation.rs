@@ -0,0 +1,190 @@
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{email_sent_response, spawn_app, TestApp};
+
+const EMAIL: &str = "ursula_le_guin@gmail.com";
+
+async fn subscribe(app: &TestApp) {
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .await
+        .error_for_status()
+        .unwrap();
+}
+
+#[tokio::test]
+async fn pending_subscribers_get_a_new_link_that_confirms_them() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(email_sent_response())
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    subscribe(&app).await;
+
+    // Act - Part 1 - Ask for a new confirmation email
+    let response = app.post_resend_confirmation(EMAIL).await;
+    assert_eq!(200, response.status().as_u16());
+
+    // Act - Part 2 - Follow the new link
+    let email_requests = app.wait_for_emails(2).await;
+    let response = reqwest::get(app.get_confirmation_links(&email_requests[1]).html)
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    // The link sent before is not valid anymore
+    let response = reqwest::get(app.get_confirmation_links(&email_requests[0]).html)
+        .await
+        .unwrap();
+    assert_eq!(401, response.status().as_u16());
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!("confirmed", saved.status);
+}
+
+#[tokio::test]
+async fn unknown_and_pending_addresses_get_the_same_response() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(email_sent_response())
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    subscribe(&app).await;
+
+    // Act
+    let pending = app.post_resend_confirmation(EMAIL).await;
+    let unknown = app.post_resend_confirmation("someone_else@gmail.com").await;
+
+    // Assert
+    assert_eq!(pending.status(), unknown.status());
+    assert_eq!(pending.text().await.unwrap(), unknown.text().await.unwrap());
+    app.wait_for_emails(2).await;
+    // Mock asserts on drop that only the pending subscriber got an email
+}
+
+#[tokio::test]
+async fn confirmed_subscribers_are_not_sent_anything() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(email_sent_response())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    subscribe(&app).await;
+    let email_request = &app.wait_for_emails(1).await[0];
+    reqwest::get(app.get_confirmation_links(email_request).html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    let response = app.post_resend_confirmation(EMAIL).await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    // Mock asserts on drop
+}
+
+#[tokio::test]
+async fn resends_are_rate_limited_per_address() {
+    // Arrange
+    let app = spawn_app().await;
+    // The subscription itself and two resends
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(email_sent_response())
+        .expect(3)
+        .mount(&app.email_server)
+        .await;
+    subscribe(&app).await;
+
+    // Act
+    for _ in 0..4 {
+        let response = app.post_resend_confirmation(EMAIL).await;
+        assert_eq!(200, response.status().as_u16());
+    }
+
+    // Assert
+    app.wait_for_emails(3).await;
+    // Mock asserts on drop
+}
+
+#[tokio::test]
+async fn resend_returns_a_400_for_an_invalid_email() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_resend_confirmation("definitely-not-an-email")
+        .await;
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn subscribing_again_counts_towards_the_resend_limit() {
+    // Arrange
+    let app = spawn_app().await;
+    // The limit is 3 confirmation emails per address, however they were asked for
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(email_sent_response())
+        .expect(3)
+        .mount(&app.email_server)
+        .await;
+    subscribe(&app).await;
+
+    // Act
+    for _ in 0..3 {
+        subscribe(&app).await;
+        let response = app.post_resend_confirmation(EMAIL).await;
+        assert_eq!(200, response.status().as_u16());
+    }
+
+    // Assert
+    app.wait_for_emails(3).await;
+    // Mock asserts on drop
+}
+
+#[tokio::test]
+async fn resend_returns_a_200_even_if_the_email_cannot_be_sent() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(email_sent_response())
+        .up_to_n_times(1)
+        .mount(&app.email_server)
+        .await;
+    subscribe(&app).await;
+    app.wait_for_emails(1).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let pending = app.post_resend_confirmation(EMAIL).await;
+    let unknown = app.post_resend_confirmation("someone_else@gmail.com").await;
+
+    // Assert
+    assert_eq!(200, pending.status().as_u16());
+    assert_eq!(pending.text().await.unwrap(), unknown.text().await.unwrap());
+    app.wait_for_emails(2).await;
+}
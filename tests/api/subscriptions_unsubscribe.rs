@@ -0,0 +1,237 @@
+use wiremock::matchers::{any, method, path};
+use wiremock::Mock;
+
+use crate::helpers::{
+    create_confirmed_subscriber, email_sent_response, spawn_app, EmailLinks, TestApp,
+};
+
+/// Publish a newsletter to the confirmed subscriber and
+/// return the email request that was sent to them.
+async fn publish_newsletter(app: &TestApp) -> wiremock::Request {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(email_sent_response())
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    app.post_newsletters(serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter content",
+            "html": "<h1>Newsletter content</h1>"
+        }
+    }))
+    .await
+    .error_for_status()
+    .expect("Failed to publish the newsletter.");
+    app.dispatch_all_pending_emails().await;
+
+    app.email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap()
+}
+
+async fn publish_newsletter_and_get_unsubscribe_links(app: &TestApp) -> EmailLinks {
+    let email_request = publish_newsletter(app).await;
+    app.get_unsubscribe_links(&email_request)
+}
+
+/// Extract the value of a custom header from the body of a Postmark request.
+fn email_header(email_request: &wiremock::Request, name: &str) -> String {
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    body["Headers"]
+        .as_array()
+        .expect("The email has no custom headers.")
+        .iter()
+        .find(|header| header["Name"] == name)
+        .unwrap_or_else(|| panic!("The email has no {} header.", name))["Value"]
+        .as_str()
+        .unwrap()
+        .to_owned()
+}
+
+#[tokio::test]
+async fn newsletters_carry_the_same_unsubscribe_link_in_html_and_text() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    // Act
+    let unsubscribe_links = publish_newsletter_and_get_unsubscribe_links(&app).await;
+
+    // Assert
+    assert_eq!(unsubscribe_links.html, unsubscribe_links.plain_text);
+    assert_eq!("/subscriptions/unsubscribe", unsubscribe_links.html.path());
+}
+
+#[tokio::test]
+async fn following_the_unsubscribe_link_does_not_unsubscribe_on_its_own() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let unsubscribe_links = publish_newsletter_and_get_unsubscribe_links(&app).await;
+
+    // Act
+    let response = reqwest::get(unsubscribe_links.html)
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    assert!(response.text().await.unwrap().contains("<form"));
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscriber.");
+    assert_eq!("confirmed", saved.status);
+}
+
+#[tokio::test]
+async fn submitting_the_unsubscribe_form_unsubscribes_the_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let unsubscribe_links = publish_newsletter_and_get_unsubscribe_links(&app).await;
+    let query: Vec<(String, String)> = unsubscribe_links.html.query_pairs().into_owned().collect();
+
+    // Act
+    let response = app.post_unsubscribe(&query).await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let saved = sqlx::query!("SELECT status, unsubscribed_at FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscriber.");
+    assert_eq!("unsubscribed", saved.status);
+    assert!(saved.unsubscribed_at.is_some());
+}
+
+#[tokio::test]
+async fn unsubscribe_requests_with_a_tampered_token_are_rejected_with_a_401() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let unsubscribe_links = publish_newsletter_and_get_unsubscribe_links(&app).await;
+    let query: Vec<(String, String)> = unsubscribe_links
+        .html
+        .query_pairs()
+        .into_owned()
+        .map(|(key, value)| match key.as_str() {
+            "token" => (key, "0".repeat(value.len())),
+            _ => (key, value),
+        })
+        .collect();
+
+    // Act
+    let response = app.post_unsubscribe(&query).await;
+
+    // Assert
+    assert_eq!(401, response.status().as_u16());
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscriber.");
+    assert_eq!("confirmed", saved.status);
+}
+
+#[tokio::test]
+async fn unsubscribed_subscribers_do_not_receive_newsletters() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let unsubscribe_links = publish_newsletter_and_get_unsubscribe_links(&app).await;
+    let query: Vec<(String, String)> = unsubscribe_links.html.query_pairs().into_owned().collect();
+    app.post_unsubscribe(&query)
+        .await
+        .error_for_status()
+        .expect("Failed to unsubscribe.");
+
+    Mock::given(any())
+        .respond_with(email_sent_response())
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter content",
+                "html": "<h1>Newsletter content</h1>"
+            }
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    app.dispatch_all_pending_emails().await;
+    // Mock verifies on Drop that we haven't sent the newsletter email
+}
+
+#[tokio::test]
+async fn newsletters_advertise_one_click_unsubscription() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    // Act
+    let email_request = publish_newsletter(&app).await;
+
+    // Assert
+    let unsubscribe_links = app.get_unsubscribe_links(&email_request);
+    let list_unsubscribe = email_header(&email_request, "List-Unsubscribe");
+    let targets: Vec<_> = list_unsubscribe
+        .split(", ")
+        .map(|target| target.trim_start_matches('<').trim_end_matches('>'))
+        .collect();
+    assert_eq!(2, targets.len());
+    assert!(targets[0].starts_with("mailto:"));
+    let mut one_click_link = reqwest::Url::parse(targets[1]).unwrap();
+    one_click_link.set_port(Some(app.port)).unwrap();
+    assert_eq!(unsubscribe_links.html, one_click_link);
+    assert_eq!(
+        "List-Unsubscribe=One-Click",
+        email_header(&email_request, "List-Unsubscribe-Post")
+    );
+}
+
+#[tokio::test]
+async fn a_one_click_post_to_the_list_unsubscribe_link_unsubscribes_the_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let email_request = publish_newsletter(&app).await;
+    let list_unsubscribe = email_header(&email_request, "List-Unsubscribe");
+    let https_target = list_unsubscribe
+        .split(", ")
+        .find(|target| !target.starts_with("<mailto:"))
+        .unwrap()
+        .trim_start_matches('<')
+        .trim_end_matches('>');
+    let mut one_click_link = reqwest::Url::parse(https_target).unwrap();
+    one_click_link.set_port(Some(app.port)).unwrap();
+
+    // Act
+    // This mirrors what RFC 8058 mandates mail providers send.
+    let response = reqwest::Client::new()
+        .post(one_click_link)
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("List-Unsubscribe=One-Click")
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscriber.");
+    assert_eq!("unsubscribed", saved.status);
+}
@@ -0,0 +1,193 @@
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{
+    assert_is_redirect_to, create_confirmed_subscriber_with, email_sent_response, spawn_app,
+    TestApp,
+};
+
+async fn tag(app: &TestApp, tag: &str, emails: &[&str]) {
+    let response = app
+        .post_tags(
+            "add",
+            &serde_json::json!({ "tag": tag, "emails": emails.join("\n") }),
+        )
+        .await;
+    assert_is_redirect_to(&response, "/admin/tags");
+}
+
+fn newsletter_request_body(segment: &str) -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        },
+        "segment": segment,
+    })
+}
+
+/// Return the recipients of the emails sent after `n_skipped` emails.
+async fn recipients(app: &TestApp, n_skipped: usize) -> Vec<String> {
+    let mut recipients: Vec<String> = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .skip(n_skipped)
+        .map(|request| {
+            let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+            body["To"].as_str().unwrap().to_owned()
+        })
+        .collect();
+    recipients.sort();
+    recipients
+}
+
+#[tokio::test]
+async fn an_admin_can_tag_and_untag_subscribers_in_bulk() {
+    // Arrange
+    let app = spawn_app().await;
+    app.log_in().await;
+    create_confirmed_subscriber_with(&app, "le guin", "a@example.com").await;
+    create_confirmed_subscriber_with(&app, "le guin", "b@example.com").await;
+
+    // Act - Part 1 - Tag both subscribers, and an unknown address
+    let response = app
+        .post_tags(
+            "add",
+            &serde_json::json!({
+                "tag": "beta",
+                "emails": "a@example.com\r\nb@example.com, unknown@example.com"
+            }),
+        )
+        .await;
+    assert_is_redirect_to(&response, "/admin/tags");
+    let html_page = app.get_tags_html().await;
+    assert!(html_page.contains("<p><i>2 subscriber(s) tagged with beta.</i></p>"));
+
+    // Act - Part 2 - Untag one of them
+    let response = app
+        .post_tags(
+            "remove",
+            &serde_json::json!({ "tag": "beta", "emails": "a@example.com" }),
+        )
+        .await;
+    assert_is_redirect_to(&response, "/admin/tags");
+    let html_page = app.get_tags_html().await;
+    assert!(html_page.contains("<p><i>1 subscriber(s) untagged from beta.</i></p>"));
+
+    // Assert
+    let tagged = sqlx::query_scalar!(
+        r#"
+        SELECT s.email
+        FROM subscriber_tags st
+        JOIN subscriptions s ON s.id = st.subscriber_id
+        "#
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(tagged, vec!["b@example.com"]);
+}
+
+#[tokio::test]
+async fn invalid_tag_names_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.log_in().await;
+
+    // Act
+    let response = app
+        .post_tags(
+            "add",
+            &serde_json::json!({ "tag": "Not valid", "emails": "a@example.com" }),
+        )
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/tags");
+    let html_page = app.get_tags_html().await;
+    assert!(html_page.contains("Tags can only contain"));
+    let n_tags = sqlx::query_scalar!(r#"SELECT count(*) as "count!" FROM tags"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(n_tags, 0);
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_tag_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_tags(
+            "add",
+            &serde_json::json!({ "tag": "beta", "emails": "a@example.com" }),
+        )
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn newsletters_are_only_delivered_to_the_subscribers_in_the_segment() {
+    // Arrange
+    let app = spawn_app().await;
+    app.log_in().await;
+    for email in ["a@example.com", "b@example.com", "c@example.com"] {
+        create_confirmed_subscriber_with(&app, "le guin", email).await;
+    }
+    tag(&app, "beta", &["a@example.com", "b@example.com"]).await;
+    tag(&app, "churned", &["b@example.com"]).await;
+    let n_confirmation_emails = app.email_server.received_requests().await.unwrap().len();
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(email_sent_response())
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletters(newsletter_request_body(
+            "(beta AND NOT churned) OR (NOT beta)",
+        ))
+        .await;
+    assert_eq!(200, response.status().as_u16());
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(
+        recipients(&app, n_confirmation_emails).await,
+        vec!["a@example.com", "c@example.com"]
+    );
+}
+
+#[tokio::test]
+async fn malformed_segments_are_rejected_with_a_400_explaining_the_problem() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletters(newsletter_request_body("beta AND"))
+        .await;
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+    assert_eq!(
+        response.text().await.unwrap(),
+        "Expected a tag name, `NOT` or `(` at column 9, found the end of the expression."
+    );
+}